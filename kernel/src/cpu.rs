@@ -0,0 +1,86 @@
+use core::{arch::x86_64::__cpuid, fmt};
+use x86_64::registers::model_specific::{Efer, EferFlags};
+
+/// The CPU features the kernel cares about, read from CPUID at boot.
+#[derive(Clone, Copy, Debug)]
+pub struct CpuFeatures {
+    pub sse2: bool,
+    pub avx: bool,
+    pub rdrand: bool,
+    pub rdtscp: bool,
+    /// No-execute page protection, which also needs EFER.NXE to be set before use.
+    pub nx: bool,
+    pub huge_pages: bool,
+}
+
+static mut FEATURES: CpuFeatures = CpuFeatures {
+    sse2: false,
+    avx: false,
+    rdrand: false,
+    rdtscp: false,
+    nx: false,
+    huge_pages: false,
+};
+
+impl fmt::Display for CpuFeatures {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, supported) in [
+            ("sse2", self.sse2),
+            ("avx", self.avx),
+            ("rdrand", self.rdrand),
+            ("rdtscp", self.rdtscp),
+            ("nx", self.nx),
+            ("1GiB-pages", self.huge_pages),
+        ] {
+            write!(f, " {}{}", if supported { '+' } else { '-' }, name)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the 12-character vendor string, e.g. `GenuineIntel`.
+fn vendor() -> [u8; 12] {
+    let cpuid = unsafe { __cpuid(0) };
+    let mut vendor = [0; 12];
+    for (chunk, register) in vendor.chunks_mut(4).zip([cpuid.ebx, cpuid.edx, cpuid.ecx]) {
+        chunk.copy_from_slice(&register.to_le_bytes());
+    }
+    vendor
+}
+
+/// Reads the CPUID leaves and logs what the CPU supports. Must run before `features` is used.
+/// This runs before the kernel heap exists, so it must not allocate.
+pub fn detect() {
+    let leaf1 = unsafe { __cpuid(1) };
+    let max_extended_leaf = unsafe { __cpuid(0x8000_0000) }.eax;
+    let extended_edx = if max_extended_leaf >= 0x8000_0001 {
+        unsafe { __cpuid(0x8000_0001) }.edx
+    } else {
+        0
+    };
+    let features = CpuFeatures {
+        sse2: leaf1.edx & (1 << 26) != 0,
+        avx: leaf1.ecx & (1 << 28) != 0,
+        rdrand: leaf1.ecx & (1 << 30) != 0,
+        rdtscp: extended_edx & (1 << 27) != 0,
+        nx: extended_edx & (1 << 20) != 0,
+        huge_pages: extended_edx & (1 << 26) != 0,
+    };
+    unsafe {
+        FEATURES = features;
+    }
+
+    let vendor = vendor();
+    let vendor = core::str::from_utf8(&vendor).unwrap_or("unknown");
+    log::info!("CPU {}:{}", vendor, features);
+}
+
+pub fn features() -> CpuFeatures {
+    unsafe { FEATURES }
+}
+
+/// Returns true if pages can be marked `NO_EXECUTE`. Without EFER.NXE that bit is reserved, and
+/// setting it makes every access to the page fault.
+pub fn nx_enabled() -> bool {
+    Efer::read().contains(EferFlags::NO_EXECUTE_ENABLE)
+}
@@ -47,6 +47,12 @@ impl Add<u64> for VirtualAddressOffset {
 /// Used by [`Inner::make_mut`] and [`Inner::clean_copied_flag`].
 const COPIED: Flags = Flags::BIT_9;
 
+/// Where position independent executables are loaded.
+const PIE_LOAD_ADDR: u64 = 0x2000_0000_0000;
+
+/// The size of an `Elf64_Sym` entry in the dynamic symbol table.
+const SYMBOL_SIZE: u64 = 24;
+
 struct Loader<'a> {
     elf_file: ElfFile<'a>,
     inner: Inner,
@@ -72,34 +78,49 @@ impl<'a> Loader<'a> {
         for program_header in elf_file.program_iter() {
             program::sanity_check(program_header, &elf_file)?;
         }
+        if elf_file.header.pt1.class() != header::Class::SixtyFour
+            || elf_file.header.pt2.machine().as_machine() != header::Machine::X86_64
+        {
+            return Err("ELF file is not an x86-64 program");
+        }
+        // Jumping to an entry point outside the loaded segments would fault in userspace.
+        check_is_in_load(&elf_file, elf_file.header.pt2.entry_point())
+            .map_err(|_| "ELF entry point is not in a load segment")?;
+
+        // Find the lowest and highest virtual memory addresses of the loaded segments.
+        let mut min_addr = u64::MAX;
+        let mut max_addr = 0;
+        for header in elf_file
+            .program_iter()
+            .filter(|h| matches!(h.get_type(), Ok(Type::Load)))
+        {
+            min_addr = min_addr.min(header.virtual_addr());
+            max_addr = max_addr.max(header.virtual_addr() + header.mem_size());
+        }
+        if min_addr == u64::MAX {
+            min_addr = 0;
+        }
 
         let virt_offset = match elf_file.header.pt2.type_().as_type() {
-            header::Type::None => unimplemented!(),
-            header::Type::Relocatable => unimplemented!(),
-            header::Type::Executable => unimplemented!(),
-            header::Type::SharedObject => {
-                // Find the highest virtual memory address and the biggest alignment.
-                let mut min_addr = u64::MAX;
-                let mut max_addr = 0;
-                let mut align = 1;
-                for header in elf_file
-                    .program_iter()
-                    .filter(|h| matches!(h.get_type(), Ok(Type::Load)))
-                {
-                    min_addr = min_addr.min(header.virtual_addr());
-                    max_addr = max_addr.max(header.virtual_addr() + header.mem_size());
-                    align = align.max(header.align());
-                }
-                if min_addr == u64::MAX {
-                    min_addr = 0;
+            header::Type::Executable => {
+                // Executables are loaded where they were linked, which has to stay clear of the
+                // null page and the area used for position independent executables.
+                if min_addr < PAGE_SIZE as u64 || max_addr > PIE_LOAD_ADDR {
+                    return Err("ELF executable is linked outside the user program area");
                 }
-
-                //let size = max_addr - min_addr;
-                let offset: i128 = 0x2000_0000_0000; // TODO!!!
-                VirtualAddressOffset::new(i128::from(offset) - i128::from(min_addr))
+                VirtualAddressOffset::new(0)
+            }
+            header::Type::SharedObject => {
+                VirtualAddressOffset::new(i128::from(PIE_LOAD_ADDR) - i128::from(min_addr))
+            }
+            header::Type::None => return Err("ELF file has no type"),
+            header::Type::Relocatable => {
+                return Err("ELF relocatable object files can't be run, link them first")
+            }
+            header::Type::Core => return Err("ELF core dumps can't be run"),
+            header::Type::ProcessorSpecific(_) => {
+                return Err("ELF file has an unsupported processor-specific type")
             }
-            header::Type::Core => unimplemented!(),
-            header::Type::ProcessorSpecific(_) => unimplemented!(),
         };
 
         header::sanity_check(&elf_file)?;
@@ -177,7 +198,7 @@ impl Inner {
         let start_page: Page = Page::containing_address(virt_start_addr);
 
         let mut segment_flags = Flags::PRESENT;
-        if !segment.flags().is_execute() {
+        if !segment.flags().is_execute() && crate::cpu::nx_enabled() {
             segment_flags |= Flags::NO_EXECUTE;
         }
         if segment.flags().is_write() {
@@ -483,12 +504,12 @@ impl Inner {
             core::ptr::copy_nonoverlapping(frame_ptr, new_frame_ptr, Size4KiB::SIZE as usize);
         }
 
-        // Replace the underlying frame and update the flags.
-        self.memory_mapper.unmap_page(page).unwrap();
+        // Replace the underlying frame and update the flags. The old frame still holds the file
+        // contents, which other segments may share.
         let new_flags = flags | COPIED;
         unsafe {
             self.memory_mapper
-                .map_page(page, new_frame, new_flags)
+                .remap_page(page, new_frame, new_flags)
                 .unwrap();
         }
 
@@ -562,6 +583,8 @@ impl Inner {
         let mut rela = None;
         let mut rela_size = None;
         let mut rela_ent = None;
+        let mut sym_tab = None;
+        let mut sym_ent = None;
         for rel in data {
             let tag = rel.get_tag()?;
             match tag {
@@ -586,6 +609,20 @@ impl Inner {
                         return Err("Dynamic section contains more than one RelaEnt entry");
                     }
                 }
+                dynamic::Tag::SymTab => {
+                    let ptr = rel.get_ptr()?;
+                    let prev = sym_tab.replace(ptr);
+                    if prev.is_some() {
+                        return Err("Dynamic section contains more than one SymTab entry");
+                    }
+                }
+                dynamic::Tag::SymEnt => {
+                    let val = rel.get_val()?;
+                    let prev = sym_ent.replace(val);
+                    if prev.is_some() {
+                        return Err("Dynamic section contains more than one SymEnt entry");
+                    }
+                }
                 _ => {}
             }
         }
@@ -610,11 +647,22 @@ impl Inner {
             "unsupported entry size: {entry_size}"
         );
 
+        let symbol_table = match sym_tab {
+            Some(table) => {
+                let entry_size = sym_ent.ok_or("SymEnt entry is missing")?;
+                if entry_size != SYMBOL_SIZE {
+                    return Err("unsupported symbol table entry size");
+                }
+                Some(table)
+            }
+            None => None,
+        };
+
         // Apply the relocations.
         let num_entries = total_size / entry_size;
         for idx in 0..num_entries {
             let rela = self.read_relocation(offset, idx);
-            self.apply_relocation(rela, elf_file)?;
+            self.apply_relocation(rela, elf_file, symbol_table)?;
         }
 
         Ok(())
@@ -639,38 +687,84 @@ impl Inner {
         }
     }
 
+    /// Reads the value of a symbol from the dynamic symbol table, relocated to where the file is
+    /// loaded. Undefined weak symbols resolve to 0.
+    fn read_symbol_value(
+        &self,
+        elf_file: &ElfFile,
+        symbol_table: u64,
+        idx: u32,
+    ) -> Result<u64, &'static str> {
+        let offset = SYMBOL_SIZE
+            .checked_mul(idx as u64)
+            .and_then(|offset| offset.checked_add(symbol_table))
+            .ok_or("symbol table entry is outside the address space")?;
+
+        // Make sure that the whole entry is in memory mapped by a Load segment.
+        check_range_is_in_load(elf_file, offset, SYMBOL_SIZE)?;
+
+        let addr = VirtAddr::try_new(self.virt_offset + offset)
+            .map_err(|_| "symbol table is outside the address space")?;
+
+        // Elf64_Sym is st_name (4 bytes), st_info, st_other, st_shndx (2 bytes), st_value (8
+        // bytes), and st_size (8 bytes).
+        let mut buf = [0; SYMBOL_SIZE as usize];
+        self.copy_from(addr, &mut buf);
+        let info = buf[4];
+        let section_index = u16::from_le_bytes([buf[6], buf[7]]);
+        let value = u64::from_le_bytes(buf[8..16].try_into().unwrap());
+
+        if section_index == 0 {
+            // The symbol is undefined. There are no other objects to look it up in.
+            const STB_WEAK: u8 = 2;
+            if info >> 4 == STB_WEAK {
+                return Ok(0);
+            }
+            return Err("relocation uses an undefined symbol");
+        }
+        Ok(self.virt_offset + value)
+    }
+
     fn apply_relocation(
         &mut self,
         rela: Rela<u64>,
         elf_file: &ElfFile,
+        symbol_table: Option<u64>,
     ) -> Result<(), &'static str> {
         let symbol_idx = rela.get_symbol_table_index();
-        assert_eq!(
-            symbol_idx, 0,
-            "relocations using the symbol table are not supported"
-        );
+        let symbol_value = || -> Result<u64, &'static str> {
+            let table = symbol_table.ok_or("relocation uses a symbol but there is no SymTab")?;
+            self.read_symbol_value(elf_file, table, symbol_idx)
+        };
 
-        match rela.get_type() {
+        // Calculate the relocated value.
+        let value = match rela.get_type() {
+            // R_X86_64_64
+            1 => symbol_value()?.wrapping_add(rela.get_addend()),
+            // R_X86_64_GLOB_DAT | R_X86_64_JUMP_SLOT
+            6 | 7 => symbol_value()?,
             // R_AMD64_RELATIVE
             8 => {
-                // Make sure that the relocation happens in memory mapped
-                // by a Load segment.
-                check_is_in_load(elf_file, rela.get_offset())?;
+                if symbol_idx != 0 {
+                    return Err("relative relocation uses the symbol table");
+                }
+                self.virt_offset + rela.get_addend()
+            }
+            _ => return Err("relocation type not supported"),
+        };
 
-                // Calculate the destination of the relocation.
-                let addr = self.virt_offset + rela.get_offset();
-                let addr = VirtAddr::new(addr);
+        // Make sure that the relocation happens in memory mapped
+        // by a Load segment.
+        check_range_is_in_load(elf_file, rela.get_offset(), size_of::<u64>() as u64)?;
 
-                // Calculate the relocated value.
-                let value = self.virt_offset + rela.get_addend();
+        // Calculate the destination of the relocation.
+        let addr = self.virt_offset + rela.get_offset();
+        let addr = VirtAddr::new(addr);
 
-                // Write the relocated value to memory.
-                unsafe {
-                    // SAFETY: We just verified that the address is in a Load segment.
-                    self.copy_to(addr, &value.to_ne_bytes());
-                }
-            }
-            ty => unimplemented!("relocation type {:x} not supported", ty),
+        // Write the relocated value to memory.
+        unsafe {
+            // SAFETY: We just verified that the address is in a Load segment.
+            self.copy_to(addr, &value.to_ne_bytes());
         }
 
         Ok(())
@@ -721,11 +815,22 @@ impl Inner {
 
 /// Check that the virtual offset belongs to a load segment.
 fn check_is_in_load(elf_file: &ElfFile, virt_offset: u64) -> Result<(), &'static str> {
+    check_range_is_in_load(elf_file, virt_offset, 1)
+}
+
+/// Check that `len` bytes starting at the virtual offset all belong to the same load segment.
+fn check_range_is_in_load(
+    elf_file: &ElfFile,
+    virt_offset: u64,
+    len: u64,
+) -> Result<(), &'static str> {
     for program_header in elf_file.program_iter() {
         if let Type::Load = program_header.get_type()? {
             if program_header.virtual_addr() <= virt_offset {
                 let offset_in_segment = virt_offset - program_header.virtual_addr();
-                if offset_in_segment < program_header.mem_size() {
+                if offset_in_segment < program_header.mem_size()
+                    && len <= program_header.mem_size() - offset_in_segment
+                {
                     return Ok(());
                 }
             }
@@ -737,25 +842,27 @@ fn check_is_in_load(elf_file: &ElfFile, virt_offset: u64) -> Result<(), &'static
 enum File {
     Empty,
     Partial {
-        phys_frame: PhysFrame<Size4KiB>,
         start_addr: PhysAddr,
-        phys_addr: PhysAddr,
+        capacity: usize,
         file_size: usize,
     },
 }
 
 static mut LOAD_FILE: File = File::Empty;
 
-pub fn start_load() -> Result<(), &'static str> {
+/// Starts loading a file of `len` bytes. The file is read back as one physically contiguous
+/// region, so enough contiguous frames for all of it are reserved up front.
+pub fn start_load(len: usize) -> Result<(), &'static str> {
     let mapper = user_memory_mapper();
     match unsafe { &LOAD_FILE } {
         File::Empty => {
-            let phys_frame = mapper.allocate_frame().unwrap();
-            let start_addr = phys_frame.start_address();
+            let frame_count = len.div_ceil(PAGE_SIZE).max(1);
+            let frames = mapper
+                .allocate_contiguous_frames(frame_count)
+                .ok_or("not enough contiguous memory for the file")?;
             let file = File::Partial {
-                phys_frame,
-                start_addr,
-                phys_addr: start_addr,
+                start_addr: frames.start.start_address(),
+                capacity: frame_count * PAGE_SIZE,
                 file_size: 0,
             };
             unsafe { LOAD_FILE = file };
@@ -765,37 +872,39 @@ pub fn start_load() -> Result<(), &'static str> {
     }
 }
 
-fn load_bytes_subpage(bytes: &[u8]) -> Result<(), &'static str> {
+/// Appends bytes to the file being loaded.
+pub fn load_bytes(bytes: &[u8]) -> Result<(), &'static str> {
     let mapper = user_memory_mapper();
     match unsafe { &mut LOAD_FILE } {
         File::Empty => Err("load not started"),
         File::Partial {
-            phys_frame,
-            start_addr: _,
-            phys_addr,
+            start_addr,
+            capacity,
             file_size,
         } => {
-            unsafe {
-                core::ptr::copy(
-                    bytes.as_ptr(),
-                    mapper.phys_offset(*phys_addr).as_mut_ptr(),
-                    bytes.len(),
-                );
-            }
-            *file_size += bytes.len();
-            *phys_addr += bytes.len();
-            if *phys_addr >= phys_frame.start_address() + phys_frame.size() {
-                *phys_frame = mapper.allocate_frame().unwrap();
-                assert_eq!(phys_frame.start_address(), *phys_addr);
-            }
-            Ok(())
+            let buffer = unsafe {
+                core::slice::from_raw_parts_mut(
+                    mapper.phys_offset(*start_addr).as_mut_ptr(),
+                    *capacity,
+                )
+            };
+            append_bytes(buffer, file_size, bytes)
         }
     }
 }
-pub fn load_bytes(bytes: &[u8]) -> Result<(), &'static str> {
-    for chunk in bytes.chunks(PAGE_SIZE) {
-        load_bytes_subpage(chunk)?;
-    }
+
+/// Copies `bytes` into `buffer` after the first `file_size` bytes, and advances `file_size`.
+fn append_bytes(
+    buffer: &mut [u8],
+    file_size: &mut usize,
+    bytes: &[u8],
+) -> Result<(), &'static str> {
+    let end = file_size
+        .checked_add(bytes.len())
+        .filter(|end| *end <= buffer.len())
+        .ok_or("file is larger than the length given to start_load")?;
+    buffer[*file_size..end].copy_from_slice(bytes);
+    *file_size = end;
     Ok(())
 }
 
@@ -804,9 +913,8 @@ pub fn finish_load() -> Result<(VirtAddr, Option<TlsTemplate>), &'static str> {
     match unsafe { core::mem::replace(&mut LOAD_FILE, File::Empty) } {
         File::Empty => Err("nothing to load"),
         File::Partial {
-            phys_frame: _,
             start_addr,
-            phys_addr: _,
+            capacity: _,
             file_size,
         } => {
             let mut loader = Loader::new(start_addr, file_size, mapper)?;
@@ -816,3 +924,34 @@ pub fn finish_load() -> Result<(VirtAddr, Option<TlsTemplate>), &'static str> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn append_bytes_across_frame_boundaries() {
+        // A file that is not a whole number of frames, written in sector-sized chunks that don't
+        // line up with the frames.
+        let file: alloc::vec::Vec<u8> = (0..PAGE_SIZE * 2 + 100).map(|i| i as u8).collect();
+        let mut buffer = alloc::vec![0; file.len().div_ceil(PAGE_SIZE) * PAGE_SIZE];
+        let mut file_size = 0;
+        append_bytes(&mut buffer, &mut file_size, &file[..3]).unwrap();
+        for chunk in file[3..].chunks(512) {
+            append_bytes(&mut buffer, &mut file_size, chunk).unwrap();
+        }
+        assert_eq!(file_size, file.len());
+        assert_eq!(&buffer[..file_size], &file[..]);
+        assert!(buffer[file_size..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn append_bytes_rejects_overflow() {
+        let mut buffer = [0; 8];
+        let mut file_size = 6;
+        assert!(append_bytes(&mut buffer, &mut file_size, &[1, 2, 3]).is_err());
+        assert_eq!(file_size, 6);
+        append_bytes(&mut buffer, &mut file_size, &[1, 2]).unwrap();
+        assert_eq!(file_size, 8);
+    }
+}
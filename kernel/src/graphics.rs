@@ -10,6 +10,15 @@ pub fn init_graphics(framebuffer: &'static mut bootloader_api::info::FrameBuffer
     let fb_memory = VirtMemRange::new(data.as_ptr() as u64, data.len());
     data.fill(0);
     let context = GraphicsContext::from_framebuffer(framebuffer);
+    let info = framebuffer.info();
+    log::info!(
+        "Framebuffer {}x{} stride:{} format:{:?} bytes per pixel:{}",
+        info.width,
+        info.height,
+        info.stride,
+        info.pixel_format,
+        info.bytes_per_pixel
+    );
     let buffer = FrameBuffer::from_framebuffer(framebuffer);
     load_system_font(&context, [255, 64, 64]);
     unsafe {
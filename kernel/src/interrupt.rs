@@ -1,5 +1,7 @@
 use crate::fatal_error;
-use pc_keyboard::{layouts, HandleControl, Keyboard, ScancodeSet1};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use kernel_common::{time, Key};
+use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
 use pic8259::ChainedPics;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
 
@@ -13,6 +15,7 @@ static mut PICS: ChainedPics = unsafe { ChainedPics::new(PIC_OFFSET, PIC_OFFSET
 pub enum InterruptIndex {
     Timer = PIC_OFFSET + 0,
     Keyboard = PIC_OFFSET + 1,
+    Mouse = PIC_OFFSET + 12,
     PrimaryAta = PIC_OFFSET + 14,
     SecondaryAta = PIC_OFFSET + 15,
 }
@@ -32,6 +35,111 @@ static mut KEYBOARD: Keyboard<layouts::Us104Key, ScancodeSet1> = Keyboard::new(
     HandleControl::Ignore,
 );
 
+const KEY_BUFFER_SIZE: usize = 64;
+
+/// Decoded key presses waiting to be read by userspace. Keys are pushed by the keyboard interrupt
+/// handler and popped with interrupts disabled, so no other locking is needed.
+struct KeyBuffer {
+    keys: [Key; KEY_BUFFER_SIZE],
+    start: usize,
+    len: usize,
+}
+
+impl KeyBuffer {
+    const fn new() -> Self {
+        KeyBuffer {
+            keys: [Key::Raw(0); KEY_BUFFER_SIZE],
+            start: 0,
+            len: 0,
+        }
+    }
+    fn push(&mut self, key: Key) {
+        // Drop new keys when the buffer is full, like a real keyboard controller.
+        if self.len < KEY_BUFFER_SIZE {
+            self.keys[(self.start + self.len) % KEY_BUFFER_SIZE] = key;
+            self.len += 1;
+        }
+    }
+    fn pop(&mut self) -> Option<Key> {
+        if self.len == 0 {
+            return None;
+        }
+        let key = self.keys[self.start];
+        self.start = (self.start + 1) % KEY_BUFFER_SIZE;
+        self.len -= 1;
+        Some(key)
+    }
+}
+
+static mut KEY_BUFFER: KeyBuffer = KeyBuffer::new();
+
+/// Set by the keyboard handler when space or enter is pressed.
+static CONFIRM: AtomicBool = AtomicBool::new(false);
+
+/// Set by the ATA handlers when a bus raises its IRQ, and cleared by `wait_for_ata`.
+static ATA_IRQ: [AtomicBool; 2] = [AtomicBool::new(false), AtomicBool::new(false)];
+/// The status register of each ATA bus. Reading it acknowledges the drive's interrupt.
+const ATA_STATUS_PORTS: [u16; 2] = [0x1F7, 0x177];
+
+static TIMER_TICKS: AtomicU64 = AtomicU64::new(0);
+static SLEEP_TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// The number of timer interrupts since interrupts were enabled. See `kernel_common::time` for the
+/// tick rate.
+pub fn uptime_ticks() -> u64 {
+    TIMER_TICKS.load(Ordering::Relaxed)
+}
+
+/// The total number of ticks spent in `sleep_ticks`.
+pub fn slept_ticks() -> u64 {
+    SLEEP_TICKS.load(Ordering::Relaxed)
+}
+
+/// Halts until `ticks` timer interrupts have happened. Sleeping for 1 tick waits for the next
+/// tick boundary.
+pub fn sleep_ticks(ticks: u64) {
+    let start = uptime_ticks();
+    let target = start.saturating_add(ticks);
+    while uptime_ticks() < target {
+        x86_64::instructions::hlt();
+        crate::screen::blink_cursor();
+    }
+    SLEEP_TICKS.fetch_add(uptime_ticks() - start, Ordering::Relaxed);
+}
+
+/// Halts until space or enter is pressed, or until `timeout` timer ticks have passed. Presses from
+/// before this is called are ignored. Returns whether a key was pressed.
+pub fn wait_for_confirm(timeout: Option<u64>) -> bool {
+    let deadline = timeout.map(|ticks| uptime_ticks().saturating_add(ticks));
+    CONFIRM.store(false, Ordering::Relaxed);
+    while !CONFIRM.load(Ordering::Relaxed) {
+        if deadline.is_some_and(|deadline| uptime_ticks() >= deadline) {
+            return false;
+        }
+        x86_64::instructions::hlt();
+        crate::screen::blink_cursor();
+    }
+    true
+}
+
+/// Halts until ATA bus `bus` raises its IRQ, or until `timeout` timer ticks have passed. Returns
+/// whether the IRQ arrived. An IRQ from before this is called is consumed straight away.
+pub fn wait_for_ata(bus: usize, timeout: u64) -> bool {
+    let deadline = uptime_ticks().saturating_add(timeout);
+    while !ATA_IRQ[bus].swap(false, Ordering::Relaxed) {
+        if uptime_ticks() >= deadline {
+            return false;
+        }
+        x86_64::instructions::hlt();
+    }
+    true
+}
+
+/// Returns the oldest key press that has not been read yet.
+pub fn read_key() -> Option<Key> {
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe { KEY_BUFFER.pop() })
+}
+
 pub fn init_idt() {
     unsafe {
         // Exceptions
@@ -85,6 +193,9 @@ pub fn init_idt() {
         IDT[InterruptIndex::Keyboard as usize]
             .set_handler_fn(keyboard_interrupt_handler)
             .set_stack_index(0);
+        IDT[InterruptIndex::Mouse as usize]
+            .set_handler_fn(mouse_interrupt_handler)
+            .set_stack_index(0);
         IDT[InterruptIndex::PrimaryAta as usize]
             .set_handler_fn(primary_ata_interrupt_handler)
             .set_stack_index(0);
@@ -102,7 +213,7 @@ pub fn init_interrupts() {
     }
 
     // Configure timer.
-    let timer_rate = 19853_u16; // 60.1 Hz
+    let timer_rate = time::TIMER_DIVISOR; // 60.1 Hz
     let mut timer_command_port = Port::new(0x43);
     let mut timer_data_port = Port::new(0x40);
     unsafe {
@@ -121,10 +232,7 @@ pub fn init_interrupts() {
 }
 
 extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    // unsafe {
-    //     crate::game::WAIT_FRAME = false;
-    // }
-    // TODO
+    TIMER_TICKS.fetch_add(1, Ordering::Relaxed);
     InterruptIndex::Timer.end_interrupt();
 }
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
@@ -133,85 +241,189 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
     let scancode: u8 = unsafe { port.read() };
     if let Ok(Some(key_event)) = unsafe { KEYBOARD.add_byte(scancode) } {
         if let Some(key) = unsafe { KEYBOARD.process_keyevent(key_event) } {
-            match key {
-                // DecodedKey::Unicode(character) => log::trace!("Keyboard:{}", character),
-                // DecodedKey::RawKey(key) => log::trace!("Keyboard:{:?}", key),
-                _ => (),
+            let key = match key {
+                DecodedKey::Unicode(character) => Key::Char(character),
+                DecodedKey::RawKey(key) => Key::Raw(key as u8),
+            };
+            if matches!(key, Key::Char(' ' | '\n')) {
+                CONFIRM.store(true, Ordering::Relaxed);
             }
+            unsafe { KEY_BUFFER.push(key) };
         }
     }
     InterruptIndex::Keyboard.end_interrupt();
 }
+extern "x86-interrupt" fn mouse_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    use x86_64::instructions::port::Port;
+    let mut port = Port::new(0x60);
+    let byte: u8 = unsafe { port.read() };
+    crate::mouse::receive_byte(byte);
+    InterruptIndex::Mouse.end_interrupt();
+}
+fn ata_interrupt(bus: usize) {
+    use x86_64::instructions::port::Port;
+    let mut port = Port::<u8>::new(ATA_STATUS_PORTS[bus]);
+    unsafe {
+        port.read();
+    }
+    ATA_IRQ[bus].store(true, Ordering::Relaxed);
+}
 extern "x86-interrupt" fn primary_ata_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    ata_interrupt(0);
     InterruptIndex::PrimaryAta.end_interrupt();
 }
 extern "x86-interrupt" fn secondary_ata_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    ata_interrupt(1);
     InterruptIndex::SecondaryAta.end_interrupt();
 }
 
-extern "x86-interrupt" fn divide_error_handler(_stack_frame: InterruptStackFrame) {
-    fatal_error!("EXCEPTION: {}", "DIVIDE BY 0");
+extern "x86-interrupt" fn divide_error_handler(stack_frame: InterruptStackFrame) {
+    fatal_error!(
+        "EXCEPTION: {} at {:#x}",
+        "DIVIDE BY 0",
+        stack_frame.instruction_pointer
+    );
 }
-extern "x86-interrupt" fn breakpoint_handler(_stack_frame: InterruptStackFrame) {
-    fatal_error!("EXCEPTION: {}", "BREAKPOINT");
+extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+    fatal_error!(
+        "EXCEPTION: {} at {:#x}",
+        "BREAKPOINT",
+        stack_frame.instruction_pointer
+    );
 }
-extern "x86-interrupt" fn overflow_handler(_stack_frame: InterruptStackFrame) {
-    fatal_error!("EXCEPTION: {}", "OVERFLOW");
+extern "x86-interrupt" fn overflow_handler(stack_frame: InterruptStackFrame) {
+    fatal_error!(
+        "EXCEPTION: {} at {:#x}",
+        "OVERFLOW",
+        stack_frame.instruction_pointer
+    );
 }
-extern "x86-interrupt" fn bound_range_exceeded_handler(_stack_frame: InterruptStackFrame) {
-    fatal_error!("EXCEPTION: {}", "BOUND RANGE EXCEEDED");
+extern "x86-interrupt" fn bound_range_exceeded_handler(stack_frame: InterruptStackFrame) {
+    fatal_error!(
+        "EXCEPTION: {} at {:#x}",
+        "BOUND RANGE EXCEEDED",
+        stack_frame.instruction_pointer
+    );
 }
-extern "x86-interrupt" fn invalid_opcode_handler(_stack_frame: InterruptStackFrame) {
-    fatal_error!("EXCEPTION: {}", "INVALID OPCODE");
+extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
+    fatal_error!(
+        "EXCEPTION: {} at {:#x}",
+        "INVALID OPCODE",
+        stack_frame.instruction_pointer
+    );
 }
-extern "x86-interrupt" fn device_not_available_handler(_stack_frame: InterruptStackFrame) {
-    fatal_error!("EXCEPTION: {}", "DEVICE NOT AVAILABLE");
+extern "x86-interrupt" fn device_not_available_handler(stack_frame: InterruptStackFrame) {
+    fatal_error!(
+        "EXCEPTION: {} at {:#x}",
+        "DEVICE NOT AVAILABLE",
+        stack_frame.instruction_pointer
+    );
 }
 extern "x86-interrupt" fn double_fault_handler(
-    _stack_frame: InterruptStackFrame,
+    stack_frame: InterruptStackFrame,
     _error_code: u64,
 ) -> ! {
-    fatal_error!("EXCEPTION: {}", "DOUBLE FAULT");
+    // A double fault usually means the first fault's handler couldn't run, most often because
+    // the stack overflowed into a guard page. CR2 still holds the address of the last page fault.
+    let fault_address = x86_64::registers::control::Cr2::read();
+    let stack_pointer = stack_frame.stack_pointer;
+    let instruction_pointer = stack_frame.instruction_pointer;
+    if let Some(stack) = crate::memory::stack_overflowed(fault_address) {
+        fatal_error!(
+            "EXCEPTION: {}\nSTACK OVERFLOW ({}) at {:#x}\nRSP: {:#x} RIP: {:#x}",
+            "DOUBLE FAULT",
+            stack,
+            fault_address,
+            stack_pointer,
+            instruction_pointer
+        );
+    }
+    fatal_error!(
+        "EXCEPTION: {}\nCR2: {:#x}\nRSP: {:#x} RIP: {:#x}",
+        "DOUBLE FAULT",
+        fault_address,
+        stack_pointer,
+        instruction_pointer
+    );
 }
-extern "x86-interrupt" fn invalid_tss_handler(_stack_frame: InterruptStackFrame, error_code: u64) {
-    fatal_error!("EXCEPTION: {}({})", "INVALID TSS", error_code);
+extern "x86-interrupt" fn invalid_tss_handler(stack_frame: InterruptStackFrame, error_code: u64) {
+    fatal_error!(
+        "EXCEPTION: {}({}) at {:#x}",
+        "INVALID TSS",
+        error_code,
+        stack_frame.instruction_pointer
+    );
 }
 extern "x86-interrupt" fn segment_not_present_handler(
-    _stack_frame: InterruptStackFrame,
+    stack_frame: InterruptStackFrame,
     error_code: u64,
 ) {
-    fatal_error!("EXCEPTION: {}({})", "SEGMENT NOT PRESENT", error_code);
+    fatal_error!(
+        "EXCEPTION: {}({}) at {:#x}",
+        "SEGMENT NOT PRESENT",
+        error_code,
+        stack_frame.instruction_pointer
+    );
 }
 extern "x86-interrupt" fn stack_segment_fault_handler(
-    _stack_frame: InterruptStackFrame,
+    stack_frame: InterruptStackFrame,
     error_code: u64,
 ) {
-    fatal_error!("EXCEPTION: {}({})", "STACK SEGMENT FAULT", error_code);
+    fatal_error!(
+        "EXCEPTION: {}({}) at {:#x}",
+        "STACK SEGMENT FAULT",
+        error_code,
+        stack_frame.instruction_pointer
+    );
 }
 extern "x86-interrupt" fn general_protection_fault_handler(
-    _stack_frame: InterruptStackFrame,
+    stack_frame: InterruptStackFrame,
     error_code: u64,
 ) {
-    fatal_error!("EXCEPTION: {}({})", "GENERAL PROTECTION FAULT", error_code);
+    fatal_error!(
+        "EXCEPTION: {}({}) at {:#x}",
+        "GENERAL PROTECTION FAULT",
+        error_code,
+        stack_frame.instruction_pointer
+    );
 }
 extern "x86-interrupt" fn page_fault_handler(
-    _stack_frame: InterruptStackFrame,
+    stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
 ) {
     let fault_address = x86_64::registers::control::Cr2::read();
+    if let Some(stack) = crate::memory::stack_overflowed(fault_address) {
+        fatal_error!(
+            "EXCEPTION: STACK OVERFLOW ({}) at {:#x}\nRSP: {:#x} RIP: {:#x}",
+            stack,
+            fault_address,
+            stack_frame.stack_pointer,
+            stack_frame.instruction_pointer
+        );
+    }
     fatal_error!(
-        "EXCEPTION: {}({:06b}) {:#x}",
+        "EXCEPTION: {}({:06b}) {:#x} at {:#x}",
         "PAGE FAULT",
         error_code,
-        fault_address
+        fault_address,
+        stack_frame.instruction_pointer
     );
 }
 extern "x86-interrupt" fn alignment_check_handler(
-    _stack_frame: InterruptStackFrame,
-    _error_code: u64,
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
 ) {
-    fatal_error!("EXCEPTION: {}", "ALIGNMENT CHECK");
+    fatal_error!(
+        "EXCEPTION: {}({}) at {:#x}",
+        "ALIGNMENT CHECK",
+        error_code,
+        stack_frame.instruction_pointer
+    );
 }
-extern "x86-interrupt" fn simd_floating_point_handler(_stack_frame: InterruptStackFrame) {
-    fatal_error!("EXCEPTION: {}", "SIMD FLOATING POINT");
+extern "x86-interrupt" fn simd_floating_point_handler(stack_frame: InterruptStackFrame) {
+    fatal_error!(
+        "EXCEPTION: {} at {:#x}",
+        "SIMD FLOATING POINT",
+        stack_frame.instruction_pointer
+    );
 }
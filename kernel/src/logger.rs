@@ -0,0 +1,63 @@
+use crate::serial;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+use log::{LevelFilter, Log, Metadata, Record};
+
+/// ANSI foreground colors used to tint module tags. Red and yellow are left out so tags can't be
+/// mistaken for errors and warnings.
+const TAG_COLORS: [u8; 4] = [32, 34, 35, 36];
+
+/// Whether each log line is prefixed with the module that logged it.
+static SHOW_TARGETS: AtomicBool = AtomicBool::new(false);
+
+/// Picks a tag color for a module, so lines from the same module always get the same tint.
+fn tag_color(target: &str) -> u8 {
+    let hash = target.bytes().fold(0usize, |hash, byte| {
+        hash.wrapping_mul(31).wrapping_add(byte as usize)
+    });
+    TAG_COLORS[hash % TAG_COLORS.len()]
+}
+
+/// Writes log messages to COM1. Run QEMU with `-serial stdio` to see them.
+struct SerialLogger;
+
+impl Log for SerialLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            // Log from interrupt handlers too, without interleaving with the interrupted message.
+            x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+                if SHOW_TARGETS.load(Ordering::Relaxed) {
+                    let target = record.target();
+                    write!(
+                        serial::COM1,
+                        "[{}] \x1b[{}m{}\x1b[0m: ",
+                        record.level(),
+                        tag_color(target),
+                        target
+                    )
+                    .ok();
+                } else {
+                    write!(serial::COM1, "[{}] ", record.level()).ok();
+                }
+                writeln!(serial::COM1, "{}", record.args()).ok();
+            });
+        }
+    }
+    fn flush(&self) {}
+}
+
+static LOGGER: SerialLogger = SerialLogger;
+
+/// Sets up logging to COM1. If `show_targets` is set, each line is tagged with the module that
+/// logged it, in a color picked from the module name.
+pub fn init(level: LevelFilter, show_targets: bool) {
+    unsafe {
+        serial::COM1.init();
+    }
+    SHOW_TARGETS.store(show_targets, Ordering::Relaxed);
+    log::set_logger(&LOGGER).expect("logger already set");
+    log::set_max_level(level);
+}
@@ -2,18 +2,28 @@
 #![feature(alloc_error_handler)]
 #![feature(asm_const)]
 #![feature(step_trait)]
-#![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
+// Unit tests run on the host, where only the pure helpers they cover are used.
+#![cfg_attr(test, allow(dead_code, unused_imports))]
 extern crate alloc;
 
+mod cpu;
 mod elf_loader;
 mod graphics;
 mod interrupt;
+mod logger;
 mod memory;
+mod mouse;
+mod power;
+mod random;
+mod screen;
+mod serial;
 mod userspace;
 
 use alloc::{format, string::String};
 use bootloader_api::{config::Mapping, entry_point, BootInfo, BootloaderConfig};
+use kernel_common::graphics::Texture;
 
 static OS_NAME: &str = "Mythos";
 static OS_VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -25,9 +35,14 @@ static BOOTLOADER_CONFIG: BootloaderConfig = {
     config
 };
 
+#[cfg(not(test))]
 entry_point!(kernel_main, config = &BOOTLOADER_CONFIG);
 
 fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
+    // Start logging to the serial port first so that everything after this can be debugged.
+    logger::init(log::LevelFilter::Debug, true);
+    cpu::detect();
+
     // Save the framebuffer info from the bootloader.
     let framebuffer_memory =
         graphics::init_graphics(boot_info.framebuffer.as_mut().expect("no framebuffer"));
@@ -42,6 +57,12 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
             .expect("physical memory not mapped"),
         &boot_info.memory_regions,
     );
+    let (screen_width, screen_height) = unsafe {
+        let framebuffer = graphics::framebuffer().unwrap();
+        (framebuffer.width(), framebuffer.height())
+    };
+    mouse::init(screen_width, screen_height);
+    random::init();
     interrupt::init_interrupts();
 
     // Save bootloader version
@@ -71,23 +92,26 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
             boot_info.ramdisk_len as usize,
         )
     };
-    elf_loader::start_load().unwrap();
+    elf_loader::start_load(ramdisk.len()).unwrap();
     elf_loader::load_bytes(ramdisk).unwrap();
-    let (entry_point, _tls_template) = elf_loader::finish_load().unwrap();
-    userspace::enter_userspace(entry_point);
+    let (entry_point, tls_template) = elf_loader::finish_load().unwrap();
+    if let Some(tls_template) = tls_template {
+        userspace::init_tls(&tls_template);
+    }
+    userspace::enter_userspace(entry_point, &["userspace"]);
 
     // log::info!("Initializing ATA");
     // let drive_info = get_first_ata_drive().unwrap();
     // log::debug!(
     //     "Found drive {} size:{}KiB",
-    //     drive_info.model,
+    //     drive_info.info.model,
     //     drive_info.size_in_kib()
     // );
     // let user_partition = get_user_partition(drive_info.drive).unwrap();
     // log::debug!("  user partition size:{}KiB", user_partition.size_in_kib());
     // filesystem::init_fs(user_partition);
     // let entry_point = program::load_program("raytrace.elf").unwrap();
-    // userspace::enter_userspace(entry_point);
+    // userspace::enter_userspace(entry_point, &["raytrace.elf"]);
 }
 
 // fn get_first_ata_drive() -> ata::DriveInfo {
@@ -99,9 +123,7 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
 // }
 
 // fn get_user_partition(drive: ata::Drive) -> ata::Partition {
-//     let mut mbr_bytes = alloc::vec![0u8; 512];
-//     drive.read(&mut mbr_bytes, 0, 1).unwrap();
-//     let mbr = mbr::MasterBootRecord::from_bytes(&mbr_bytes).unwrap();
+//     let mbr = mbr::MasterBootRecord::read_from(&drive).unwrap();
 //     if mbr.entries[2].partition_type != mbr::PartitionType::Fat32(0x0c) || !mbr.entries[2].bootable {
 //         panic!("invalid filesystem partition");
 //     }
@@ -116,10 +138,19 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
 macro_rules! fatal_error {
     ($($arg:tt)*) => {{
         use core::fmt::Write;
-        if let Some(mut framebuffer) = unsafe { $crate::graphics::framebuffer() } {
-            let context = $crate::graphics::context();
-            let mut error_writer = $crate::graphics::TextWriter::new(&context, &mut framebuffer, 0, 0);
-            error_writer.write_fmt(format_args!($($arg)*)).ok();
+        // `match` keeps the arguments' temporaries alive so the message can be written twice.
+        match format_args!($($arg)*) {
+            args => {
+                // Always write to serial, so the message is captured even without a screen.
+                x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+                    writeln!($crate::serial::COM1, "[FATAL] {}", args).ok();
+                });
+                if let Some(mut framebuffer) = unsafe { $crate::graphics::framebuffer() } {
+                    let context = $crate::graphics::context();
+                    let mut error_writer = $crate::graphics::TextWriter::new(&context, &mut framebuffer, 0, 0);
+                    error_writer.write_fmt(args).ok();
+                }
+            }
         }
         loop {
             x86_64::instructions::hlt();
@@ -127,11 +158,13 @@ macro_rules! fatal_error {
     }}
 }
 
+#[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
     fatal_error!("{}", info);
 }
 
+#[cfg(not(test))]
 #[alloc_error_handler]
 fn alloc_error_handler(layout: core::alloc::Layout) -> ! {
     fatal_error!("alloc failed: {:?}", layout);
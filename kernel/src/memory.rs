@@ -1,7 +1,10 @@
+use alloc::vec::Vec;
 use bootloader_api::info::{MemoryRegionKind, MemoryRegions};
 use linked_list_allocator::LockedHeap;
 use x86_64::{
+    align_up,
     structures::paging::{
+        frame::PhysFrameRange,
         mapper::{FlagUpdateError, MapToError, MappedFrame, TranslateResult, UnmapError},
         *,
     },
@@ -10,7 +13,7 @@ use x86_64::{
 
 pub const PAGE_SIZE: usize = Size4KiB::SIZE as usize;
 
-#[global_allocator]
+#[cfg_attr(not(test), global_allocator)]
 static ALLOCATOR: LockedHeap = LockedHeap::empty();
 
 #[derive(Debug, Copy, Clone)]
@@ -33,21 +36,51 @@ impl VirtMemRange {
     pub const fn size(&self) -> usize {
         self.1 as usize
     }
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        addr.as_u64() >= self.0 && addr.as_u64() - self.0 < self.1
+    }
+    /// The unmapped page below this range. Stacks grow down into it when they overflow.
+    pub const fn guard_page(&self) -> VirtMemRange {
+        VirtMemRange(self.0 - GUARD_SIZE as u64, GUARD_SIZE as u64)
+    }
 }
 
-/// A FrameAllocator that returns usable frames from the bootloader's memory map.
+/// The size of the unmapped gap left below each stack, so that overflowing a stack causes a page
+/// fault instead of silently corrupting the memory below it.
+const GUARD_SIZE: usize = PAGE_SIZE;
+
+/// Marks the end of the free frame list. Frame addresses are page-aligned, so this is never a
+/// valid frame.
+const FREE_LIST_END: u64 = u64::MAX;
+
+/// A FrameAllocator that returns usable frames from the bootloader's memory map. Frames that are
+/// given back are reused before any new frames are taken from the map.
+///
+/// Returned frames form an intrusive linked list: the first 8 bytes of each free frame hold the
+/// address of the next one. This keeps the allocator off the kernel heap, which is tiny and may
+/// itself need frames.
 struct BootInfoFrameAllocator {
     memory_regions: &'static MemoryRegions,
+    phys_offset: VirtAddr,
     next: usize,
+    free_list: u64,
 }
 
 impl BootInfoFrameAllocator {
-    fn new(memory_regions: &'static MemoryRegions) -> BootInfoFrameAllocator {
+    fn new(
+        memory_regions: &'static MemoryRegions,
+        phys_offset: VirtAddr,
+    ) -> BootInfoFrameAllocator {
         BootInfoFrameAllocator {
             memory_regions,
+            phys_offset,
             next: 0,
+            free_list: FREE_LIST_END,
         }
     }
+    fn free_list_link(&self, frame: PhysFrame) -> *mut u64 {
+        (self.phys_offset + frame.start_address().as_u64()).as_mut_ptr()
+    }
     fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
         // get usable regions from memory map
         let regions = self.memory_regions.iter();
@@ -59,16 +92,69 @@ impl BootInfoFrameAllocator {
         // create `PhysFrame` types from the start addresses
         frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
     }
+    /// Allocates `count` physically contiguous frames. Frames that were given back are not
+    /// considered, the run is always taken from the memory map. Frames skipped over to find the
+    /// run are put on the free list.
+    fn allocate_contiguous_frames(&mut self, count: usize) -> Option<PhysFrameRange> {
+        let (index, start) = find_contiguous_run(self.usable_frames().skip(self.next), count)?;
+        for _ in 0..index {
+            let frame = self.usable_frames().nth(self.next).unwrap();
+            self.next += 1;
+            unsafe {
+                self.deallocate_frame(frame);
+            }
+        }
+        self.next += count;
+        Some(PhysFrame::range(start, start + count as u64))
+    }
+}
+
+/// Finds the first run of `count` consecutive frames in `frames`. Returns the position of the run
+/// in the iterator and its first frame.
+fn find_contiguous_run(
+    frames: impl Iterator<Item = PhysFrame>,
+    count: usize,
+) -> Option<(usize, PhysFrame)> {
+    if count == 0 {
+        return None;
+    }
+    let mut run: Option<(usize, PhysFrame)> = None;
+    let mut run_len = 0;
+    for (index, frame) in frames.enumerate() {
+        match run {
+            Some((_, start)) if frame == start + run_len as u64 => run_len += 1,
+            _ => {
+                run = Some((index, frame));
+                run_len = 1;
+            }
+        }
+        if run_len == count {
+            return run;
+        }
+    }
+    None
 }
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        if self.free_list != FREE_LIST_END {
+            let frame = PhysFrame::containing_address(PhysAddr::new(self.free_list));
+            self.free_list = unsafe { self.free_list_link(frame).read() };
+            return Some(frame);
+        }
         let frame = self.usable_frames().nth(self.next);
         self.next += 1;
         frame
     }
 }
 
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.free_list_link(frame).write(self.free_list);
+        self.free_list = frame.start_address().as_u64();
+    }
+}
+
 unsafe fn active_level_4_table(phys_offset: VirtAddr) -> &'static mut PageTable {
     use x86_64::registers::control::Cr3;
     let (level_4_table_frame, _) = Cr3::read();
@@ -78,7 +164,6 @@ unsafe fn active_level_4_table(phys_offset: VirtAddr) -> &'static mut PageTable
     &mut *page_table_ptr // unsafe
 }
 
-// TODO secure against stack overflows
 // TODO allow heaps to map more memory as needed
 pub struct KernelMemory {
     pub privilege_stack: VirtMemRange,
@@ -91,16 +176,18 @@ impl KernelMemory {
     const STACK_SIZE: usize = PAGE_SIZE;
     const HEAP_SIZE: usize = PAGE_SIZE * 8;
     const fn new(base_addr: u64) -> Self {
-        let offset = Self::STACK_SIZE as u64;
+        // Each stack is preceded by an unmapped guard page.
+        let offset = (GUARD_SIZE + Self::STACK_SIZE) as u64;
+        let first_stack = base_addr + GUARD_SIZE as u64;
         KernelMemory {
-            privilege_stack: VirtMemRange::new(base_addr, Self::STACK_SIZE),
-            interrupt_stack: VirtMemRange::new(base_addr + offset, Self::STACK_SIZE),
-            double_fault_stack: VirtMemRange::new(base_addr + (offset * 2), Self::STACK_SIZE),
+            privilege_stack: VirtMemRange::new(first_stack, Self::STACK_SIZE),
+            interrupt_stack: VirtMemRange::new(first_stack + offset, Self::STACK_SIZE),
+            double_fault_stack: VirtMemRange::new(first_stack + (offset * 2), Self::STACK_SIZE),
             heap: VirtMemRange::new(base_addr + (offset * 3), Self::HEAP_SIZE),
         }
     }
     const fn len() -> usize {
-        (Self::STACK_SIZE * 3) + Self::HEAP_SIZE
+        ((GUARD_SIZE + Self::STACK_SIZE) * 3) + Self::HEAP_SIZE
     }
 }
 
@@ -112,14 +199,32 @@ pub struct UserMemory {
 impl UserMemory {
     const STACK_SIZE: usize = PAGE_SIZE * 4;
     const HEAP_SIZE: usize = PAGE_SIZE * 64;
+    /// The user heap grows on demand up to this size. The virtual range after the initial heap
+    /// is left free for it.
+    const HEAP_MAX_SIZE: usize = PAGE_SIZE * 1024;
+    /// The heap grows by at least this much at a time.
+    const HEAP_GROW_SIZE: usize = PAGE_SIZE * 16;
     const fn new(base_addr: u64) -> Self {
+        // The stack is preceded by an unmapped guard page.
+        let stack_addr = base_addr + GUARD_SIZE as u64;
         UserMemory {
-            stack: VirtMemRange::new(base_addr, Self::STACK_SIZE),
-            heap: VirtMemRange::new(base_addr + (Self::STACK_SIZE as u64), Self::HEAP_SIZE),
+            stack: VirtMemRange::new(stack_addr, Self::STACK_SIZE),
+            heap: VirtMemRange::new(stack_addr + (Self::STACK_SIZE as u64), Self::HEAP_SIZE),
         }
     }
 }
 
+/// How much to grow a user heap of `heap_size` bytes by so that an allocation of `min_size` bytes
+/// can succeed. Zero once the heap has reached its maximum size.
+fn heap_growth(heap_size: usize, min_size: usize) -> usize {
+    let remaining = UserMemory::HEAP_MAX_SIZE - heap_size;
+    let size = align_up(
+        min_size.max(UserMemory::HEAP_GROW_SIZE) as u64,
+        PAGE_SIZE as u64,
+    );
+    (size as usize).min(remaining)
+}
+
 const EXECUTION_MEMORY_START: u64 = 0xc000_0000_0000;
 pub const KERNEL_MEMORY: KernelMemory = KernelMemory::new(EXECUTION_MEMORY_START);
 pub const USER_MEMORY: UserMemory =
@@ -141,7 +246,7 @@ impl KernelMemoryMapper {
             let level_4_table = active_level_4_table(phys_offset);
             OffsetPageTable::new(level_4_table, phys_offset)
         };
-        let frame_allocator = BootInfoFrameAllocator::new(memory_regions);
+        let frame_allocator = BootInfoFrameAllocator::new(memory_regions, phys_offset);
 
         let mut kernel_mapper = KernelMemoryMapper {
             frame_allocator,
@@ -172,6 +277,8 @@ impl KernelMemoryMapper {
         Ok(())
     }
 
+    /// Allocates a frame for every page in `range` and maps it. If that fails part way, the pages
+    /// mapped so far are unmapped and their frames freed again.
     fn alloc_and_map_range(
         &mut self,
         range: VirtMemRange,
@@ -180,11 +287,24 @@ impl KernelMemoryMapper {
         let range_start = Page::from_start_address(range.start()).unwrap();
         let range_end = Page::containing_address(range.last_addr());
         for page in Page::range_inclusive(range_start, range_end) {
-            let frame = self
-                .allocate_frame()
-                .ok_or(MapToError::FrameAllocationFailed)?;
-            unsafe {
-                self.map_page(page, frame, flags)?;
+            let result = match self.allocate_frame() {
+                Some(frame) => unsafe {
+                    self.map_page(page, frame, flags).map_err(|err| {
+                        self.frame_allocator.deallocate_frame(frame);
+                        err
+                    })
+                },
+                None => Err(MapToError::FrameAllocationFailed),
+            };
+            if let Err(err) = result {
+                for mapped in Page::range(range_start, page) {
+                    let (frame, flush) = self.mapper.unmap(mapped).unwrap();
+                    flush.ignore();
+                    unsafe {
+                        self.frame_allocator.deallocate_frame(frame);
+                    }
+                }
+                return Err(err);
             }
         }
         Ok(())
@@ -194,6 +314,14 @@ impl KernelMemoryMapper {
 pub struct UserMemoryMapper {
     kernel_mapper: &'static mut KernelMemoryMapper,
     allocator: LockedHeap,
+    /// The mapped part of the user heap.
+    heap: VirtMemRange,
+    /// Frames allocated for the running program, returned when it exits.
+    program_frames: Vec<PhysFrame>,
+    /// Pages mapped for the running program, unmapped when it exits.
+    program_pages: Vec<Page>,
+    /// The most bytes allocated on the user heap at once since the program started.
+    peak_heap_used: usize,
 }
 
 impl UserMemoryMapper {
@@ -215,6 +343,10 @@ impl UserMemoryMapper {
                     memory_layout.heap.size(),
                 )
             },
+            heap: memory_layout.heap,
+            program_frames: Vec::new(),
+            program_pages: Vec::new(),
+            peak_heap_used: 0,
         })
     }
 
@@ -223,7 +355,18 @@ impl UserMemoryMapper {
     }
 
     pub fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
-        self.kernel_mapper.frame_allocator.allocate_frame()
+        let frame = self.kernel_mapper.frame_allocator.allocate_frame()?;
+        self.program_frames.push(frame);
+        Some(frame)
+    }
+    /// Allocates `count` physically contiguous frames for the running program.
+    pub fn allocate_contiguous_frames(&mut self, count: usize) -> Option<PhysFrameRange> {
+        let frames = self
+            .kernel_mapper
+            .frame_allocator
+            .allocate_contiguous_frames(count)?;
+        self.program_frames.extend(frames);
+        Some(frames)
     }
     pub fn finish_load(&mut self) {
         x86_64::instructions::tlb::flush_all();
@@ -237,6 +380,16 @@ impl UserMemoryMapper {
     }
 
     pub unsafe fn map_page(
+        &mut self,
+        page: Page<Size4KiB>,
+        frame: PhysFrame<Size4KiB>,
+        flags: PageTableFlags,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        self.map_page_untracked(page, frame, flags)?;
+        self.program_pages.push(page);
+        Ok(())
+    }
+    unsafe fn map_page_untracked(
         &mut self,
         page: Page<Size4KiB>,
         frame: PhysFrame<Size4KiB>,
@@ -249,10 +402,37 @@ impl UserMemoryMapper {
             .ignore();
         Ok(())
     }
+    /// Unmaps a page. If its frame was allocated for the program, the frame is returned to the
+    /// frame allocator.
     pub fn unmap_page(&mut self, page: Page<Size4KiB>) -> Result<(), UnmapError> {
-        self.kernel_mapper.mapper.unmap(page)?.1.ignore();
+        let frame = self.unmap_page_untracked(page)?;
+        if let Some(index) = self.program_frames.iter().position(|f| *f == frame) {
+            self.program_frames.swap_remove(index);
+            unsafe {
+                self.kernel_mapper.frame_allocator.deallocate_frame(frame);
+            }
+        }
         Ok(())
     }
+    fn unmap_page_untracked(
+        &mut self,
+        page: Page<Size4KiB>,
+    ) -> Result<PhysFrame<Size4KiB>, UnmapError> {
+        let (frame, flush) = self.kernel_mapper.mapper.unmap(page)?;
+        flush.ignore();
+        Ok(frame)
+    }
+    /// Maps a page to a different frame. The old frame is kept, since it may be mapped elsewhere.
+    pub unsafe fn remap_page(
+        &mut self,
+        page: Page<Size4KiB>,
+        frame: PhysFrame<Size4KiB>,
+        flags: PageTableFlags,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        // The page may not be mapped yet, in which case this is an ordinary map.
+        let _ = self.unmap_page_untracked(page);
+        self.map_page_untracked(page, frame, flags)
+    }
 
     pub fn make_range_user_accessible(
         &mut self,
@@ -274,13 +454,98 @@ impl UserMemoryMapper {
                 }
             };
             // Remap the page with USER_ACCESSIBLE enabled. This also enables it for parent pages.
-            self.unmap_page(page).unwrap();
             unsafe {
-                self.map_page(page, frame, flags).unwrap();
+                self.remap_page(page, frame, flags).unwrap();
             }
         }
         Ok(())
     }
+
+    /// Maps more memory onto the end of the user heap so that an allocation of `min_size` bytes
+    /// can succeed. Returns false if the heap has reached its maximum size.
+    pub fn grow_heap(&mut self, min_size: usize) -> bool {
+        let size = heap_growth(self.heap.size(), min_size);
+        if size == 0 {
+            return false;
+        }
+        let extension = VirtMemRange::new(self.heap.last_addr().as_u64() + 1, size);
+        let flags =
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+        if self
+            .kernel_mapper
+            .alloc_and_map_range(extension, flags)
+            .is_err()
+        {
+            return false;
+        }
+        x86_64::instructions::tlb::flush_all();
+        unsafe {
+            self.allocator.lock().extend(size);
+        }
+        self.heap = VirtMemRange::new(self.heap.start().as_u64(), self.heap.size() + size);
+        true
+    }
+
+    /// Returns true if every byte of `start..start + len` is in memory the running program may
+    /// access: its stack, its heap, or pages mapped when it was loaded.
+    pub fn is_user_range(&self, start: VirtAddr, len: usize) -> bool {
+        if len == 0 {
+            return true;
+        }
+        let last = match start
+            .as_u64()
+            .checked_add(len as u64 - 1)
+            .map(VirtAddr::try_new)
+        {
+            Some(Ok(last)) => last,
+            _ => return false,
+        };
+        let pages = Page::<Size4KiB>::range_inclusive(
+            Page::containing_address(start),
+            Page::containing_address(last),
+        );
+        for page in pages {
+            let in_range = USER_MEMORY.stack.contains(page.start_address())
+                || self.heap.contains(page.start_address())
+                || self.program_pages.contains(&page);
+            if !in_range {
+                return false;
+            }
+        }
+        true
+    }
+    /// Returns true if `ptr` points into the user heap.
+    pub fn is_heap_pointer(&self, ptr: *const u8) -> bool {
+        VirtAddr::try_new(ptr as u64).is_ok_and(|addr| self.heap.contains(addr))
+    }
+
+    /// Updates the peak heap usage. Call after every allocation.
+    pub fn record_heap_usage(&mut self) {
+        self.peak_heap_used = self.peak_heap_used.max(self.allocator.lock().used());
+    }
+    /// The bytes currently allocated on the user heap, and the most that were allocated at once.
+    pub fn heap_usage(&self) -> (usize, usize) {
+        (self.allocator.lock().used(), self.peak_heap_used)
+    }
+
+    /// Unmaps every page mapped for the running program, returns its frames to the frame allocator,
+    /// and empties the user heap.
+    pub fn reclaim_program_memory(&mut self) {
+        for page in core::mem::take(&mut self.program_pages) {
+            // Pages may already have been unmapped, or listed twice if their frame was replaced.
+            let _ = self.unmap_page_untracked(page);
+        }
+        for frame in core::mem::take(&mut self.program_frames) {
+            unsafe {
+                self.kernel_mapper.frame_allocator.deallocate_frame(frame);
+            }
+        }
+        x86_64::instructions::tlb::flush_all();
+        // Pages the heap grew into stay mapped, so the next program can use them straight away.
+        self.allocator =
+            unsafe { LockedHeap::new(self.heap.start().as_mut_ptr(), self.heap.size()) };
+        self.peak_heap_used = 0;
+    }
 }
 
 static mut KERNEL_MEMORY_MAPPER: Option<KernelMemoryMapper> = None;
@@ -310,9 +575,82 @@ pub fn init_memory(phys_offset: u64, memory_regions: &'static MemoryRegions) {
     }
 }
 
+/// Returns the name of the stack whose guard page contains `addr`, if any.
+pub fn stack_overflowed(addr: VirtAddr) -> Option<&'static str> {
+    let stacks = [
+        ("kernel", KERNEL_MEMORY.privilege_stack),
+        ("interrupt", KERNEL_MEMORY.interrupt_stack),
+        ("double fault", KERNEL_MEMORY.double_fault_stack),
+        ("user", USER_MEMORY.stack),
+    ];
+    stacks
+        .into_iter()
+        .find(|(_, stack)| stack.guard_page().contains(addr))
+        .map(|(name, _)| name)
+}
+
 pub fn user_memory_mapper() -> &'static mut UserMemoryMapper {
     unsafe { USER_MEMORY_MAPPER.as_mut().expect("no user memory mapper") }
 }
 pub fn user_allocator() -> &'static LockedHeap {
     &user_memory_mapper().allocator
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::alloc::Layout;
+
+    fn frames(addrs: &[u64]) -> impl Iterator<Item = PhysFrame> + '_ {
+        addrs
+            .iter()
+            .map(|addr| PhysFrame::containing_address(PhysAddr::new(*addr)))
+    }
+
+    #[test]
+    fn contiguous_run_skips_gaps() {
+        // Two usable regions with a hole between them.
+        let addrs = [0x1000, 0x2000, 0x5000, 0x6000, 0x7000, 0x8000];
+        let (index, start) = find_contiguous_run(frames(&addrs), 3).unwrap();
+        assert_eq!(index, 2);
+        assert_eq!(start.start_address().as_u64(), 0x5000);
+        let (index, _) = find_contiguous_run(frames(&addrs), 2).unwrap();
+        assert_eq!(index, 0);
+    }
+
+    #[test]
+    fn contiguous_run_too_long() {
+        let addrs = [0x1000, 0x2000, 0x4000];
+        assert!(find_contiguous_run(frames(&addrs), 3).is_none());
+        assert!(find_contiguous_run(frames(&addrs), 0).is_none());
+    }
+
+    #[test]
+    fn user_heap_exhausts_at_max_size() {
+        // Back the heap with host memory and grow it the way `grow_heap` does until allocations
+        // fail, as a program that never frees would.
+        let mut memory = vec![0u64; UserMemory::HEAP_MAX_SIZE / 8];
+        let heap =
+            unsafe { LockedHeap::new(memory.as_mut_ptr() as *mut u8, UserMemory::HEAP_SIZE) };
+        let mut heap_size = UserMemory::HEAP_SIZE;
+        let layout = Layout::from_size_align(PAGE_SIZE * 3, 8).unwrap();
+        let mut allocations = 0;
+        loop {
+            if heap.lock().allocate_first_fit(layout).is_ok() {
+                allocations += 1;
+                continue;
+            }
+            let growth = heap_growth(heap_size, layout.size() + layout.align());
+            if growth == 0 {
+                break;
+            }
+            assert_eq!(growth % PAGE_SIZE, 0);
+            unsafe { heap.lock().extend(growth) };
+            heap_size += growth;
+        }
+        assert_eq!(heap_size, UserMemory::HEAP_MAX_SIZE);
+        assert_eq!(heap.lock().size(), UserMemory::HEAP_MAX_SIZE);
+        assert!(allocations > UserMemory::HEAP_SIZE / layout.size());
+        assert!(heap.lock().free() < layout.size());
+    }
+}
@@ -0,0 +1,127 @@
+use kernel_common::MouseState;
+use x86_64::instructions::port::Port;
+
+const DATA_PORT: u16 = 0x60;
+const COMMAND_PORT: u16 = 0x64;
+/// How many times the controller's status register is polled before giving up. Machines without
+/// an 8042 controller or a mouse never become ready.
+const TIMEOUT_SPINS: usize = 100_000;
+/// The byte the mouse answers a command with once it has accepted it.
+const ACK: u8 = 0xfa;
+
+struct Mouse {
+    /// The bytes of the packet being received.
+    packet: [u8; 3],
+    packet_len: usize,
+    width: i32,
+    height: i32,
+    state: MouseState,
+}
+
+static mut MOUSE: Mouse = Mouse {
+    packet: [0; 3],
+    packet_len: 0,
+    width: 0,
+    height: 0,
+    state: MouseState {
+        x: 0,
+        y: 0,
+        buttons: 0,
+    },
+};
+
+/// Polls the controller status until `bit` equals `set`.
+fn wait_for_status(bit: u8, set: bool) -> Result<(), &'static str> {
+    let mut status = Port::<u8>::new(COMMAND_PORT);
+    for _ in 0..TIMEOUT_SPINS {
+        if (unsafe { status.read() } & bit != 0) == set {
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+    Err("PS/2 controller timed out")
+}
+fn write_command(command: u8) -> Result<(), &'static str> {
+    wait_for_status(0b10, false)?;
+    unsafe { Port::new(COMMAND_PORT).write(command) };
+    Ok(())
+}
+fn write_data(data: u8) -> Result<(), &'static str> {
+    wait_for_status(0b10, false)?;
+    unsafe { Port::new(DATA_PORT).write(data) };
+    Ok(())
+}
+fn read_data() -> Result<u8, &'static str> {
+    wait_for_status(0b1, true)?;
+    Ok(unsafe { Port::new(DATA_PORT).read() })
+}
+fn write_mouse(command: u8) -> Result<(), &'static str> {
+    // Route the next data byte to the auxiliary device, then wait for its acknowledgement.
+    write_command(0xd4)?;
+    write_data(command)?;
+    match read_data()? {
+        ACK => Ok(()),
+        _ => Err("mouse did not acknowledge a command"),
+    }
+}
+
+fn enable_mouse() -> Result<(), &'static str> {
+    // Enable the auxiliary device.
+    write_command(0xa8)?;
+    // Enable IRQ12 and the mouse clock in the controller configuration byte.
+    write_command(0x20)?;
+    let config = (read_data()? | 0b10) & !0b10_0000;
+    write_command(0x60)?;
+    write_data(config)?;
+    // Use default settings, then start sending movement packets.
+    write_mouse(0xf6)?;
+    write_mouse(0xf4)
+}
+
+/// Enables the PS/2 auxiliary device through the 8042 controller. The cursor starts in the
+/// centre of a `width` by `height` screen and is kept inside it. Must be called with interrupts
+/// disabled. If there is no controller or mouse, this logs a warning and the cursor never moves.
+pub fn init(width: u32, height: u32) {
+    unsafe {
+        MOUSE.width = width as i32;
+        MOUSE.height = height as i32;
+        MOUSE.state.x = width as i32 / 2;
+        MOUSE.state.y = height as i32 / 2;
+    }
+    if let Err(err) = enable_mouse() {
+        log::warn!("No PS/2 mouse: {}", err);
+    }
+}
+
+/// Handles one byte from the mouse. Called from the IRQ12 handler.
+pub fn receive_byte(byte: u8) {
+    let mouse = unsafe { &mut MOUSE };
+    // Bit 3 of the first byte is always set. Skip bytes until the packets line up again.
+    if mouse.packet_len == 0 && byte & 0b1000 == 0 {
+        return;
+    }
+    mouse.packet[mouse.packet_len] = byte;
+    mouse.packet_len += 1;
+    if mouse.packet_len < mouse.packet.len() {
+        return;
+    }
+    mouse.packet_len = 0;
+
+    let [flags, dx, dy] = mouse.packet;
+    // Discard packets where the movement overflowed.
+    if flags & 0b1100_0000 != 0 {
+        return;
+    }
+    // The sign bits of the movement are in the flags byte.
+    let dx = dx as i32 - (((flags as i32) << 4) & 0x100);
+    let dy = dy as i32 - (((flags as i32) << 3) & 0x100);
+    let state = &mut mouse.state;
+    state.x = (state.x + dx).clamp(0, (mouse.width - 1).max(0));
+    // Positive dy is up, but screen coordinates increase downward.
+    state.y = (state.y - dy).clamp(0, (mouse.height - 1).max(0));
+    state.buttons = flags & 0b111;
+}
+
+pub fn state() -> MouseState {
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe { MOUSE.state })
+}
@@ -0,0 +1,43 @@
+use x86_64::instructions::port::{PortReadOnly, PortWriteOnly};
+
+/// Powers off the machine. This does not parse the ACPI tables; it tries the fixed ACPI shutdown
+/// ports used by common emulators, and halts if none of them work.
+pub fn shutdown() -> ! {
+    log::info!("Shutting down");
+    // (port, value) pairs that trigger an ACPI S5 sleep.
+    const SHUTDOWN_PORTS: [(u16, u16); 3] = [
+        (0x604, 0x2000),  // QEMU
+        (0xb004, 0x2000), // Bochs and older QEMU
+        (0x4004, 0x3400), // VirtualBox
+    ];
+    for (port, value) in SHUTDOWN_PORTS {
+        unsafe {
+            PortWriteOnly::<u16>::new(port).write(value);
+        }
+    }
+    log::warn!("Shutdown failed, halting");
+    halt()
+}
+
+/// Restarts the machine by pulsing the CPU reset line through the 8042 keyboard controller.
+pub fn reboot() -> ! {
+    log::info!("Rebooting");
+    let mut status = PortReadOnly::<u8>::new(0x64);
+    let mut command = PortWriteOnly::<u8>::new(0x64);
+    unsafe {
+        // Wait for the controller's input buffer to be empty.
+        while status.read() & 0b10 != 0 {
+            core::hint::spin_loop();
+        }
+        command.write(0xfe);
+    }
+    log::warn!("Reboot failed, halting");
+    halt()
+}
+
+fn halt() -> ! {
+    x86_64::instructions::interrupts::disable();
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
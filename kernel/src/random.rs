@@ -0,0 +1,62 @@
+use core::arch::asm;
+
+/// Where random numbers come from: the CPU's `RDRAND` instruction if it has one, otherwise a
+/// xorshift generator seeded from the timestamp counter at boot. The fallback is predictable and
+/// must not be used for anything cryptographic.
+enum RandomSource {
+    /// `RDRAND`, with a xorshift state to fall back on if the hardware generator stays drained.
+    Rdrand(u64),
+    Xorshift(u64),
+}
+
+static mut RANDOM_SOURCE: RandomSource = RandomSource::Xorshift(0);
+
+fn rdrand() -> Option<u64> {
+    // RDRAND can fail transiently when the hardware generator is drained; Intel recommends
+    // retrying up to 10 times.
+    for _ in 0..10 {
+        let value: u64;
+        let ok: u8;
+        unsafe {
+            asm!("rdrand {}", "setc {}", out(reg) value, out(reg_byte) ok, options(nomem, nostack));
+        }
+        if ok != 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn xorshift(state: &mut u64) -> u64 {
+    let mut x = *state;
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    *state = x;
+    x
+}
+
+pub fn init() {
+    // The xorshift state must never be 0.
+    let seed = unsafe { core::arch::x86_64::_rdtsc() } | 1;
+    let source = if crate::cpu::features().rdrand {
+        log::info!("Random numbers from RDRAND");
+        RandomSource::Rdrand(seed)
+    } else {
+        log::warn!("No RDRAND, random numbers are not cryptographically secure");
+        RandomSource::Xorshift(seed)
+    };
+    unsafe {
+        RANDOM_SOURCE = source;
+    }
+}
+
+pub fn random_u64() -> u64 {
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+        match &mut RANDOM_SOURCE {
+            // A program can drain RDRAND by asking for numbers in a loop, so that must not be fatal.
+            RandomSource::Rdrand(fallback) => rdrand().unwrap_or_else(|| xorshift(fallback)),
+            RandomSource::Xorshift(state) => xorshift(state),
+        }
+    })
+}
@@ -0,0 +1,85 @@
+use crate::{graphics, interrupt};
+use kernel_common::{
+    graphics::{FrameBuffer, GraphicsContext, Texture},
+    screen::{make_user_text_palette, update_cursor_phase, ImageScreen, Screen, TextScreen},
+    Color, UserError,
+};
+
+/// The screen a program draws to through the `Screen*` syscalls.
+enum ProgramScreen {
+    Text(TextScreen),
+    Image(ImageScreen),
+}
+
+// There is only one program, so there is at most one screen and it is always active.
+static mut SCREEN: Option<ProgramScreen> = None;
+
+fn with_display<R>(f: impl FnOnce(&GraphicsContext, &mut FrameBuffer) -> R) -> R {
+    let context = graphics::context();
+    let mut framebuffer = unsafe { graphics::framebuffer() }.expect("graphics not initialized");
+    f(&context, &mut framebuffer)
+}
+
+/// Replaces the program's screen with a new text or image screen, and draws it.
+pub fn create(image: bool) {
+    with_display(|context, framebuffer| {
+        let mut screen = if image {
+            let (width, height) = (framebuffer.width(), framebuffer.height());
+            ProgramScreen::Image(ImageScreen::new(
+                width as usize,
+                height as usize,
+                Color::BLACK,
+            ))
+        } else {
+            let mut screen = TextScreen::new(make_user_text_palette(context));
+            screen.show_cursor(context, framebuffer, true);
+            ProgramScreen::Text(screen)
+        };
+        match &mut screen {
+            ProgramScreen::Text(screen) => screen.set_active(context, framebuffer, true),
+            ProgramScreen::Image(screen) => screen.set_active(context, framebuffer, true),
+        }
+        unsafe {
+            SCREEN = Some(screen);
+        }
+    })
+}
+
+/// Runs `f` on the program's text screen. Returns `UserError::MissingScreen` if the program hasn't
+/// created one.
+pub fn with_text_screen(
+    f: impl FnOnce(&mut TextScreen, &GraphicsContext, &mut FrameBuffer),
+) -> Result<(), UserError> {
+    match unsafe { SCREEN.as_mut() } {
+        Some(ProgramScreen::Text(screen)) => {
+            with_display(|context, framebuffer| f(screen, context, framebuffer));
+            Ok(())
+        }
+        _ => Err(UserError::MissingScreen),
+    }
+}
+
+/// Runs `f` on the program's image screen. Returns `UserError::MissingScreen` if the program
+/// hasn't created one.
+pub fn with_image_screen(
+    f: impl FnOnce(&mut ImageScreen, &GraphicsContext, &mut FrameBuffer),
+) -> Result<(), UserError> {
+    match unsafe { SCREEN.as_mut() } {
+        Some(ProgramScreen::Image(screen)) => {
+            with_display(|context, framebuffer| f(screen, context, framebuffer));
+            Ok(())
+        }
+        _ => Err(UserError::MissingScreen),
+    }
+}
+
+/// Redraws the cursor of the program's text screen if it blinked since the last call. Called
+/// while the kernel waits, not from the timer interrupt, so it never draws over a syscall that is
+/// drawing.
+pub fn blink_cursor() {
+    if update_cursor_phase(interrupt::uptime_ticks()) {
+        let _ = with_text_screen(|screen, context, framebuffer| {
+            screen.draw_cursor(context, framebuffer)
+        });
+    }
+}
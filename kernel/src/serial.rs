@@ -0,0 +1,68 @@
+use x86_64::instructions::port::{Port, PortReadOnly, PortWriteOnly};
+
+/// A 16550 UART, driven by polling.
+pub struct SerialPort {
+    data: Port<u8>,
+    interrupt_enable: PortWriteOnly<u8>,
+    fifo_control: PortWriteOnly<u8>,
+    line_control: PortWriteOnly<u8>,
+    modem_control: PortWriteOnly<u8>,
+    line_status: PortReadOnly<u8>,
+}
+
+impl SerialPort {
+    /// The line status bit that is set when the transmit holding register is empty.
+    const TRANSMIT_EMPTY: u8 = 1 << 5;
+
+    pub const fn new(base: u16) -> SerialPort {
+        SerialPort {
+            data: Port::new(base),
+            interrupt_enable: PortWriteOnly::new(base + 1),
+            fifo_control: PortWriteOnly::new(base + 2),
+            line_control: PortWriteOnly::new(base + 3),
+            modem_control: PortWriteOnly::new(base + 4),
+            line_status: PortReadOnly::new(base + 5),
+        }
+    }
+
+    /// Configures the port for 38400 baud, 8 data bits, no parity, one stop bit.
+    pub fn init(&mut self) {
+        unsafe {
+            // Disable interrupts, we only poll.
+            self.interrupt_enable.write(0x00);
+            // Set the baud divisor (115200 / 3 = 38400) with DLAB enabled.
+            self.line_control.write(0x80);
+            self.data.write(0x03);
+            self.interrupt_enable.write(0x00);
+            // 8 bits, no parity, one stop bit, DLAB disabled.
+            self.line_control.write(0x03);
+            // Enable and clear the FIFOs, with a 14-byte threshold.
+            self.fifo_control.write(0xc7);
+            // Data terminal ready, request to send, auxiliary output 2.
+            self.modem_control.write(0x0b);
+        }
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        unsafe {
+            while self.line_status.read() & Self::TRANSMIT_EMPTY == 0 {
+                core::hint::spin_loop();
+            }
+            self.data.write(byte);
+        }
+    }
+}
+
+impl core::fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for byte in s.bytes() {
+            if byte == b'\n' {
+                self.write_byte(b'\r');
+            }
+            self.write_byte(byte);
+        }
+        Ok(())
+    }
+}
+
+pub static mut COM1: SerialPort = SerialPort::new(0x3f8);
@@ -1,5 +1,11 @@
-use crate::memory::{KERNEL_MEMORY, USER_MEMORY};
-use core::arch::{asm, global_asm};
+use crate::memory::{self, KERNEL_MEMORY, USER_MEMORY};
+use alloc::string::String;
+use bootloader_api::info::TlsTemplate;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    arch::{asm, global_asm},
+    fmt::Write,
+};
 use kernel_common::Syscall;
 use x86_64::{
     registers::segmentation::Segment,
@@ -69,6 +75,15 @@ unsafe fn setup_userspace(segments: &Segments) {
     Efer::update(|flags| {
         *flags |= EferFlags::SYSTEM_CALL_EXTENSIONS;
     });
+    // Enable no-execute pages, which the ELF loader uses for segments that aren't code.
+    if crate::cpu::features().nx {
+        Efer::update(|flags| {
+            *flags |= EferFlags::NO_EXECUTE_ENABLE;
+        });
+        log::info!("NX enabled");
+    } else {
+        log::warn!("CPU has no NX support, program data will be executable");
+    }
     // Setup segments
     Star::write(
         segments.user_code,
@@ -84,15 +99,107 @@ unsafe fn setup_userspace(segments: &Segments) {
     syscall_fns::init();
 }
 
-pub fn enter_userspace(entry_point: VirtAddr) -> ! {
-    let user_stack: u64 = USER_MEMORY.stack.stack_start().as_u64();
+/// Alignment used for the TLS block. `TlsTemplate` does not record the segment's alignment, so
+/// this must be at least as large as any `#[thread_local]` variable's alignment.
+const TLS_ALIGN: usize = 16;
+
+/// Allocates the program's thread-local storage on the user heap and points `fs` at it.
+///
+/// This follows the x86-64 TLS ABI (variant II): the TLS block sits directly below the thread
+/// control block, and `fs` points at the TCB, whose first word is a pointer to itself.
+pub fn init_tls(template: &TlsTemplate) {
+    use x86_64::registers::model_specific::FsBase;
+    let tls_size = x86_64::align_up(template.mem_size, TLS_ALIGN as u64) as usize;
+    let tcb_size = core::mem::size_of::<u64>();
+    let layout = Layout::from_size_align(tls_size + tcb_size, TLS_ALIGN).unwrap();
+    unsafe {
+        let block = memory::user_allocator().alloc(layout);
+        if block.is_null() {
+            crate::fatal_error!("failed to allocate TLS block");
+        }
+        // The template starts at the beginning of the block, `tls_size` bytes below the TCB. Copy
+        // the initialized data and zero the rest (.tbss).
+        core::ptr::write_bytes(block, 0, tls_size);
+        core::ptr::copy_nonoverlapping(
+            template.start_addr as *const u8,
+            block,
+            template.file_size as usize,
+        );
+        let tcb = block.add(tls_size) as *mut u64;
+        tcb.write(tcb as u64);
+        FsBase::write(VirtAddr::from_ptr(tcb));
+    }
+}
+
+/// The uptime and total sleep time when the program started, used to work out its CPU time.
+static mut PROGRAM_START_TICKS: (u64, u64) = (0, 0);
+
+/// Copies the program arguments to the top of the user stack, in the layout the System V ABI
+/// gives a process at startup. Returns the new stack pointer and the address of `argv`.
+///
+/// From the returned stack pointer upward the stack holds:
+/// * a null return address, as if the entry point had been called
+/// * `argc`
+/// * `argc` pointers to the arguments, followed by a null pointer (`argv`)
+/// * a null pointer, ending the empty environment (`envp`)
+/// * an `AT_NULL` pair, ending the empty auxiliary vector
+///
+/// The NUL-terminated argument strings are stored above that. `argc` is 16-byte aligned, so the
+/// entry point sees `rsp % 16 == 8` like any other function.
+unsafe fn push_args(stack_top: u64, args: &[&str]) -> (u64, u64) {
+    // Each argument is stored directly below the previous one.
+    let string_addrs = args.iter().scan(stack_top, |addr, arg| {
+        *addr -= arg.len() as u64 + 1;
+        Some(*addr)
+    });
+    let mut addr = stack_top;
+    for (arg, string_addr) in args.iter().zip(string_addrs.clone()) {
+        let dest = string_addr as *mut u8;
+        core::ptr::copy_nonoverlapping(arg.as_ptr(), dest, arg.len());
+        dest.add(arg.len()).write(0);
+        addr = string_addr;
+    }
+    // argc, argv and its terminator, envp's terminator, and the auxiliary vector's AT_NULL pair.
+    let words = 1 + (args.len() + 1) + 1 + 2;
+    let stack_pointer = x86_64::align_down(addr - (words as u64 * 8), 16);
+    let mut block = stack_pointer as *mut u64;
+    let mut push = |value: u64| {
+        block.write(value);
+        block = block.add(1);
+    };
+    push(args.len() as u64);
+    for string_addr in string_addrs {
+        push(string_addr);
+    }
+    for _ in 0..4 {
+        push(0);
+    }
+    let return_addr = stack_pointer - 8;
+    (return_addr as *mut u64).write(0);
+    (return_addr, stack_pointer + 8)
+}
+
+/// Jumps to the program's entry point with `args` set up on a fresh stack, see `push_args`. `argc`
+/// and `argv` are also passed in `rdi` and `rsi`, so the entry point can be an ordinary
+/// `extern "C" fn(argc: isize, argv: *const *const u8)`.
+pub fn enter_userspace(entry_point: VirtAddr, args: &[&str]) -> ! {
+    unsafe {
+        PROGRAM_START_TICKS = (
+            crate::interrupt::uptime_ticks(),
+            crate::interrupt::slept_ticks(),
+        );
+    }
+    let (user_stack, argv) = unsafe { push_args(USER_MEMORY.stack.stack_start().as_u64(), args) };
     unsafe {
         asm!(
             "mov rsp, {stack}",
-            "mov rbp, {stack}",
+            // A null frame pointer marks the outermost frame for `user_backtrace`.
+            "xor ebp, ebp",
             "mov r11, {flags}",
             "sysretq",
             in("rcx") entry_point.as_u64(),
+            in("rdi") args.len(),
+            in("rsi") argv,
             stack = in(reg) user_stack,
             flags = const USER_FLAGS,
             options(noreturn),
@@ -106,6 +213,38 @@ static mut _syscall_funcs: [u64; Syscall::NUM_SYSCALLS] = [0; Syscall::NUM_SYSCA
 #[no_mangle]
 static mut _syscall_user_return: u64 = 0;
 
+#[no_mangle]
+static mut _syscall_user_rbp: u64 = 0;
+
+/// The most frames `user_backtrace` follows.
+const MAX_BACKTRACE_FRAMES: usize = 32;
+
+/// Lists the return addresses on the program's stack, starting from the syscall it is making.
+/// Frames are found by following the saved `rbp` chain, so the program must be built with frame
+/// pointers for this to get past the first address. Only frames inside the user stack are read.
+fn user_backtrace() -> String {
+    let stack = USER_MEMORY.stack;
+    let (stack_start, stack_end) = (stack.start().as_u64(), stack.last_addr().as_u64() + 1);
+    let mut backtrace = String::from("backtrace:");
+    let (mut rbp, return_addr) = unsafe { (_syscall_user_rbp, _syscall_user_return) };
+    write!(backtrace, "\n  {:#x}", return_addr).unwrap();
+    for _ in 0..MAX_BACKTRACE_FRAMES {
+        // Each frame starts with the caller's rbp, followed by the return address.
+        if rbp % 8 != 0 || rbp < stack_start || rbp > stack_end - 16 {
+            break;
+        }
+        let (caller_rbp, return_addr) =
+            unsafe { (*(rbp as *const u64), *((rbp + 8) as *const u64)) };
+        write!(backtrace, "\n  {:#x}", return_addr).unwrap();
+        // Callers' frames are always higher up the stack, so this also stops a looping chain.
+        if caller_rbp <= rbp {
+            break;
+        }
+        rbp = caller_rbp;
+    }
+    backtrace
+}
+
 extern "C" {
     fn syscall() -> !;
 }
@@ -115,6 +254,7 @@ global_asm!(
 .globl syscall
 syscall:
     mov [_syscall_user_return + rip], rcx
+    mov [_syscall_user_rbp + rip], rbp
     lea rcx, [_syscall_funcs + rip]
     add rax, rcx
     pop rcx
@@ -127,71 +267,313 @@ syscall:
 
 #[allow(improper_ctypes_definitions)]
 mod syscall_fns {
-    use crate::{fatal_error, graphics, memory};
+    use crate::{fatal_error, graphics, interrupt, memory, mouse, power, random, screen};
     use alloc::string::String;
-    use core::alloc::{GlobalAlloc, Layout};
+    use core::{
+        alloc::{GlobalAlloc, Layout},
+        mem::{align_of, size_of},
+    };
     use kernel_common::{
         graphics::{FrameBuffer, GraphicsContext},
-        Syscall,
+        screen::{PaletteColor, TextScreen},
+        Color, Key, MouseState, ProgramStats, Syscall, UserError,
     };
+    use x86_64::VirtAddr;
 
     pub unsafe fn init() {
         use super::_syscall_funcs as funcs;
-        funcs[Syscall::INFO_OS_NAME] = info_os_name as u64;
-        funcs[Syscall::INFO_OS_VERSION] = info_os_version as u64;
-        funcs[Syscall::INFO_BOOTLOADER_VERSION] = info_bootloader_version as u64;
-        funcs[Syscall::INFO_FRAMEBUFFER] = info_framebuffer as u64;
-        funcs[Syscall::INFO_GRAPHICS_CTX] = info_graphics_ctx as u64;
-        funcs[Syscall::MEM_ALLOC] = mem_alloc as u64;
-        funcs[Syscall::MEM_DEALLOC] = mem_dealloc as u64;
-        funcs[Syscall::MEM_ALLOC_ZEROED] = mem_alloc_zeroed as u64;
-        funcs[Syscall::MEM_REALLOC] = mem_realloc as u64;
-        funcs[Syscall::PROGRAM_PANIC] = program_panic as u64;
-    }
-
-    fn copy_str_to_user_memory(input: &str) -> String {
+        // IDs without a handler in this kernel stop the program instead of jumping to address 0.
+        funcs.fill(unsupported_syscall as u64);
+        funcs[Syscall::InfoOsName as usize] = info_os_name as u64;
+        funcs[Syscall::InfoOsVersion as usize] = info_os_version as u64;
+        funcs[Syscall::InfoBootloaderVersion as usize] = info_bootloader_version as u64;
+        funcs[Syscall::InfoFramebuffer as usize] = info_framebuffer as u64;
+        funcs[Syscall::InfoGraphicsCtx as usize] = info_graphics_ctx as u64;
+        funcs[Syscall::MemAlloc as usize] = mem_alloc as u64;
+        funcs[Syscall::MemDealloc as usize] = mem_dealloc as u64;
+        funcs[Syscall::MemAllocZeroed as usize] = mem_alloc_zeroed as u64;
+        funcs[Syscall::MemRealloc as usize] = mem_realloc as u64;
+        funcs[Syscall::ProgramPanic as usize] = program_panic as u64;
+        funcs[Syscall::ProgramPrint as usize] = program_print as u64;
+        funcs[Syscall::ProgramReadKey as usize] = program_read_key as u64;
+        funcs[Syscall::ProgramTimeTicks as usize] = program_time_ticks as u64;
+        funcs[Syscall::ProgramSleepTicks as usize] = program_sleep_ticks as u64;
+        funcs[Syscall::ProgramExit as usize] = program_exit as u64;
+        funcs[Syscall::ProgramShutdown as usize] = program_shutdown as u64;
+        funcs[Syscall::ProgramReboot as usize] = program_reboot as u64;
+        funcs[Syscall::ProgramReadMouse as usize] = program_read_mouse as u64;
+        funcs[Syscall::ProgramRandom as usize] = program_random as u64;
+        funcs[Syscall::ProgramStats as usize] = program_stats as u64;
+        funcs[Syscall::ProgramWaitForConfirm as usize] = program_wait_for_confirm as u64;
+        funcs[Syscall::ProgramWaitAta as usize] = program_wait_ata as u64;
+        funcs[Syscall::ScreenCreate as usize] = screen_create as u64;
+        funcs[Syscall::ScreenSetChar as usize] = screen_set_char as u64;
+        funcs[Syscall::ScreenSetPixel as usize] = screen_set_pixel as u64;
+        funcs[Syscall::ScreenBlit as usize] = screen_blit as u64;
+        funcs[Syscall::ScreenPresent as usize] = screen_present as u64;
+        funcs[Syscall::ScreenClear as usize] = screen_clear as u64;
+        funcs[Syscall::ScreenScroll as usize] = screen_scroll as u64;
+    }
+
+    extern "sysv64" fn unsupported_syscall() -> ! {
+        fatal_error!("userspace called an unsupported syscall");
+    }
+
+    /// Copies a string onto the program's heap, so the program can free it like any other string.
+    fn copy_str_to_user_memory(input: &str) -> Result<String, UserError> {
+        let len = input.len();
+        if len == 0 {
+            return Ok(String::new());
+        }
         unsafe {
-            let len = input.len();
             let buf = mem_alloc(Layout::from_size_align_unchecked(len, 1));
+            if buf.is_null() {
+                return Err(UserError::OutOfMemory);
+            }
             core::slice::from_raw_parts_mut(buf, len).copy_from_slice(input.as_bytes());
-            String::from_raw_parts(buf, len, len)
+            Ok(String::from_raw_parts(buf, len, len))
         }
     }
-    extern "sysv64" fn info_os_name() -> String {
-        copy_str_to_user_memory(crate::OS_NAME)
+    /// Writes a syscall's result to `out`, a pointer passed by the program. Nothing is written, and
+    /// `value` isn't called, unless `out` is aligned and lies in the program's memory. Returns 0, or
+    /// the `UserError` code for the program if `out` is invalid or `value` fails.
+    fn write_user<T>(out: *mut T, value: impl FnOnce() -> Result<T, UserError>) -> u64 {
+        let valid = VirtAddr::try_new(out as u64).is_ok_and(|start| {
+            out as usize % align_of::<T>() == 0
+                && memory::user_memory_mapper().is_user_range(start, size_of::<T>())
+        });
+        if !valid {
+            return UserError::InvalidValue as u64;
+        }
+        match value() {
+            Ok(value) => {
+                unsafe {
+                    out.write(value);
+                }
+                0
+            }
+            Err(err) => err as u64,
+        }
+    }
+
+    extern "sysv64" fn info_os_name(out: *mut String) -> u64 {
+        write_user(out, || copy_str_to_user_memory(crate::OS_NAME))
+    }
+    extern "sysv64" fn info_os_version(out: *mut String) -> u64 {
+        write_user(out, || copy_str_to_user_memory(crate::OS_VERSION))
     }
-    extern "sysv64" fn info_os_version() -> String {
-        copy_str_to_user_memory(crate::OS_VERSION)
+    extern "sysv64" fn info_bootloader_version(out: *mut String) -> u64 {
+        write_user(out, || {
+            let bootloader_version = unsafe { crate::BOOTLOADER_VERSION.as_deref().unwrap_or("") };
+            copy_str_to_user_memory(bootloader_version)
+        })
     }
-    extern "sysv64" fn info_bootloader_version() -> String {
-        let bootloader_version = unsafe { crate::BOOTLOADER_VERSION.as_deref().unwrap_or("") };
-        copy_str_to_user_memory(bootloader_version)
+    extern "sysv64" fn info_framebuffer(out: *mut FrameBuffer) -> u64 {
+        write_user(out, || unsafe {
+            Ok(graphics::framebuffer().expect("graphics not initialized"))
+        })
     }
-    unsafe extern "sysv64" fn info_framebuffer() -> FrameBuffer {
-        graphics::framebuffer().expect("graphics not initialized")
+    extern "sysv64" fn info_graphics_ctx(out: *mut GraphicsContext) -> u64 {
+        write_user(out, || Ok(graphics::context()))
     }
-    extern "sysv64" fn info_graphics_ctx() -> GraphicsContext {
-        graphics::context()
+
+    /// Runs an allocation, growing the user heap and retrying once if it fails. Returns null if the
+    /// heap is out of memory, which the program's allocator reports as an allocation error.
+    fn grow_heap_on_failure(min_size: usize, alloc: impl Fn() -> *mut u8) -> *mut u8 {
+        let mapper = memory::user_memory_mapper();
+        let mut ptr = alloc();
+        if ptr.is_null() && mapper.grow_heap(min_size) {
+            ptr = alloc();
+        }
+        if ptr.is_null() {
+            log::warn!("User heap out of memory, {} bytes requested", min_size);
+        }
+        mapper.record_heap_usage();
+        ptr
     }
 
     unsafe extern "sysv64" fn mem_alloc(layout: Layout) -> *mut u8 {
-        memory::user_allocator().alloc(layout)
+        grow_heap_on_failure(layout.size() + layout.align(), || {
+            memory::user_allocator().alloc(layout)
+        })
     }
     unsafe extern "sysv64" fn mem_dealloc(ptr: *mut u8, layout: Layout) {
+        if !memory::user_memory_mapper().is_heap_pointer(ptr) {
+            log::warn!("Program freed a pointer outside its heap: {:p}", ptr);
+            return;
+        }
         memory::user_allocator().dealloc(ptr, layout)
     }
     unsafe extern "sysv64" fn mem_alloc_zeroed(layout: Layout) -> *mut u8 {
-        memory::user_allocator().alloc_zeroed(layout)
+        grow_heap_on_failure(layout.size() + layout.align(), || {
+            memory::user_allocator().alloc_zeroed(layout)
+        })
     }
     unsafe extern "sysv64" fn mem_realloc(
         ptr: *mut u8,
         layout: Layout,
         new_size: usize,
     ) -> *mut u8 {
-        memory::user_allocator().realloc(ptr, layout, new_size)
+        if !memory::user_memory_mapper().is_heap_pointer(ptr) {
+            log::warn!("Program reallocated a pointer outside its heap: {:p}", ptr);
+            return core::ptr::null_mut();
+        }
+        // A failed realloc leaves the old allocation in place, so it is safe to retry.
+        grow_heap_on_failure(new_size + layout.align(), || {
+            memory::user_allocator().realloc(ptr, layout, new_size)
+        })
     }
 
-    extern "sysv64" fn program_panic(message: &str) -> ! {
-        fatal_error!("userspace panic:\n{}", message);
+    /// Reads a slice passed by the program, checking that it is aligned and lies in the program's
+    /// memory. A slice argument arrives as a pointer and a length in two registers.
+    fn user_slice<T>(ptr: *const T, len: usize) -> Result<&'static [T], UserError> {
+        let size = len
+            .checked_mul(size_of::<T>())
+            .ok_or(UserError::InvalidValue)?;
+        let start = VirtAddr::try_new(ptr as u64).map_err(|_| UserError::InvalidValue)?;
+        if ptr.is_null()
+            || ptr as usize % align_of::<T>() != 0
+            || !memory::user_memory_mapper().is_user_range(start, size)
+        {
+            return Err(UserError::InvalidValue);
+        }
+        Ok(unsafe { core::slice::from_raw_parts(ptr, len) })
+    }
+    /// Reads a string passed by the program, checking that it lies in the program's memory and is
+    /// valid UTF-8.
+    fn user_str(ptr: *const u8, len: usize) -> Result<&'static str, UserError> {
+        core::str::from_utf8(user_slice(ptr, len)?).map_err(|_| UserError::InvalidValue)
+    }
+
+    extern "sysv64" fn program_panic(message_ptr: *const u8, message_len: usize) -> ! {
+        match user_str(message_ptr, message_len) {
+            Ok(message) => {
+                fatal_error!("userspace panic:\n{}\n{}", message, super::user_backtrace())
+            }
+            Err(err) => fatal_error!(
+                "userspace panic:\n<message unreadable: {:?}>\n{}",
+                err,
+                super::user_backtrace()
+            ),
+        }
+    }
+    extern "sysv64" fn program_print(message_ptr: *const u8, message_len: usize) {
+        match user_str(message_ptr, message_len) {
+            Ok(message) => log::info!("{}", message.trim_end_matches('\n')),
+            Err(err) => log::warn!("Program printed an unreadable message: {:?}", err),
+        }
+    }
+    extern "sysv64" fn program_read_key() -> Option<Key> {
+        interrupt::read_key()
+    }
+    extern "sysv64" fn program_time_ticks() -> u64 {
+        interrupt::uptime_ticks()
+    }
+    extern "sysv64" fn program_sleep_ticks(ticks: u64) {
+        interrupt::sleep_ticks(ticks)
+    }
+    fn stats() -> ProgramStats {
+        let (start_uptime, start_slept) = unsafe { super::PROGRAM_START_TICKS };
+        let (heap_used, peak_heap_used) = memory::user_memory_mapper().heap_usage();
+        ProgramStats {
+            cpu_ticks: (interrupt::uptime_ticks() - start_uptime)
+                - (interrupt::slept_ticks() - start_slept),
+            heap_used,
+            peak_heap_used,
+        }
+    }
+    extern "sysv64" fn program_stats(out: *mut ProgramStats) -> u64 {
+        write_user(out, || Ok(stats()))
+    }
+    extern "sysv64" fn program_wait_for_confirm(timeout_ticks: u64) -> u64 {
+        let timeout = Some(timeout_ticks).filter(|&ticks| ticks != 0);
+        interrupt::wait_for_confirm(timeout) as u64
+    }
+    extern "sysv64" fn program_wait_ata(bus: u64, timeout_ticks: u64) -> u64 {
+        if bus > 1 {
+            return 0;
+        }
+        interrupt::wait_for_ata(bus as usize, timeout_ticks) as u64
+    }
+    extern "sysv64" fn program_exit() -> ! {
+        let stats = stats();
+        log::info!(
+            "Program exited: {} cpu ticks, peak heap {} bytes",
+            stats.cpu_ticks,
+            stats.peak_heap_used
+        );
+        memory::user_memory_mapper().reclaim_program_memory();
+        // There is only one program, so there is nothing left to run.
+        power::shutdown()
+    }
+    extern "sysv64" fn program_shutdown() -> ! {
+        power::shutdown()
+    }
+    extern "sysv64" fn program_reboot() -> ! {
+        power::reboot()
+    }
+    extern "sysv64" fn program_read_mouse(out: *mut MouseState) -> u64 {
+        write_user(out, || Ok(mouse::state()))
+    }
+    extern "sysv64" fn program_random() -> u64 {
+        random::random_u64()
+    }
+
+    /// Converts the result of a syscall that returns nothing into its return value.
+    fn error_code(result: Result<(), UserError>) -> u64 {
+        result.map_or_else(|err| err as u64, |()| 0)
+    }
+    extern "sysv64" fn screen_create(image: u64) -> u64 {
+        screen::create(image != 0);
+        0
+    }
+    extern "sysv64" fn screen_set_char(x: usize, y: usize, ch: u64, color: u64) -> u64 {
+        if x >= TextScreen::WIDTH || y >= TextScreen::HEIGHT {
+            return UserError::InvalidValue as u64;
+        }
+        error_code(screen::with_text_screen(|screen, context, framebuffer| {
+            let color = PaletteColor::new(color as u8);
+            screen.set_char(context, framebuffer, (x, y), ch as u8, color)
+        }))
+    }
+    extern "sysv64" fn screen_set_pixel(x: usize, y: usize, color: u64) -> u64 {
+        error_code(screen::with_image_screen(|screen, context, framebuffer| {
+            screen.set_pixel(context, framebuffer, x, y, Color::from_u32(color as u32))
+        }))
+    }
+    extern "sysv64" fn screen_blit(
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        pixels_ptr: *const Color,
+        pixels_len: usize,
+    ) -> u64 {
+        if width.checked_mul(height) != Some(pixels_len) {
+            return UserError::InvalidValue as u64;
+        }
+        let pixels = match user_slice(pixels_ptr, pixels_len) {
+            Ok(pixels) => pixels,
+            Err(err) => return err as u64,
+        };
+        error_code(screen::with_image_screen(|screen, context, framebuffer| {
+            screen.blit(context, framebuffer, (x, y), width, height, pixels)
+        }))
+    }
+    extern "sysv64" fn screen_present() -> u64 {
+        error_code(screen::with_image_screen(|screen, context, framebuffer| {
+            screen.present(context, framebuffer)
+        }))
+    }
+    extern "sysv64" fn screen_clear(color: u64) -> u64 {
+        error_code(screen::with_image_screen(|screen, context, framebuffer| {
+            screen.clear(context, framebuffer, Color::from_u32(color as u32))
+        }))
+    }
+    extern "sysv64" fn screen_scroll(dx: isize, dy: isize, fill_color: u64) -> u64 {
+        error_code(screen::with_image_screen(|screen, context, framebuffer| {
+            let fill_color = Color::from_u32(fill_color as u32);
+            screen.scroll(context, framebuffer, (dx, dy), fill_color)
+        }))
     }
 }
@@ -3,12 +3,13 @@ extern crate alloc;
 
 use alloc::{string::String, vec::Vec};
 use bit_field::BitField;
+use core::{fmt, ops::Range};
 use x86_64::instructions::port::{Port, PortReadOnly, PortWriteOnly};
 
 pub use block_device::BlockDevice;
 
 /// Implementation Courtesy of MOROS.
-/// Currently Only Supports ATA-PIO, with 24-bit LBA Addressing.
+/// Currently Only Supports ATA-PIO, with 24-bit LBA Addressing, and reading from ATAPI drives.
 
 fn sleep_ticks(ticks: usize) {
     for _ in 0..=ticks {
@@ -20,7 +21,64 @@ fn sleep_ticks(ticks: usize) {
 enum Command {
     Read = 0x20,
     Write = 0x30,
+    FlushCache = 0xE7,
     Identify = 0xEC,
+    Packet = 0xA0,
+    IdentifyPacket = 0xA1,
+}
+
+/// The SCSI commands sent to ATAPI drives in a PACKET command.
+#[repr(u8)]
+enum PacketCommand {
+    ReadCapacity = 0x25,
+    Read12 = 0xA8,
+}
+
+/// The names of the SCSI sense keys an ATAPI drive reports in the top half of its error register.
+const SENSE_KEYS: [&str; 16] = [
+    "no sense",
+    "recovered error",
+    "not ready",
+    "medium error",
+    "hardware error",
+    "illegal request",
+    "unit attention",
+    "data protect",
+    "blank check",
+    "vendor specific",
+    "copy aborted",
+    "aborted command",
+    "reserved",
+    "volume overflow",
+    "miscompare",
+    "reserved",
+];
+
+#[allow(dead_code)]
+#[allow(clippy::upper_case_acronyms)]
+#[repr(usize)]
+enum ErrorBit {
+    AMNF = 0,
+    TKZNF = 1,
+    ABRT = 2,
+    MCR = 3,
+    IDNF = 4,
+    MC = 5,
+    UNC = 6,
+    BBK = 7,
+}
+
+impl ErrorBit {
+    const ALL: [(ErrorBit, &'static str); 8] = [
+        (ErrorBit::AMNF, "address mark not found"),
+        (ErrorBit::TKZNF, "track zero not found"),
+        (ErrorBit::ABRT, "aborted command"),
+        (ErrorBit::MCR, "media change request"),
+        (ErrorBit::IDNF, "ID not found"),
+        (ErrorBit::MC, "media changed"),
+        (ErrorBit::UNC, "uncorrectable data"),
+        (ErrorBit::BBK, "bad block"),
+    ];
 }
 
 #[allow(dead_code)]
@@ -37,11 +95,21 @@ enum Status {
     BSY = 7,
 }
 
+/// I/O base, control base and IRQ line of the primary and secondary buses.
+const BUS_PORTS: [(u16, u16, u8); 2] = [(0x1F0, 0x3F6, 14), (0x170, 0x376, 15)];
+
+/// Waits up to `timeout_ticks` timer ticks for an IRQ from the given bus, returning whether one
+/// arrived. The IRQ handlers live in the kernel, so this is provided by whoever runs the driver.
+pub type InterruptWait = fn(bus: u8, timeout_ticks: u64) -> bool;
+
+static mut INTERRUPT_WAIT: Option<InterruptWait> = None;
+
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct Bus {
     id: u8,
     irq: u8,
+    interrupt_driven: bool,
 
     data_register: Port<u16>,
     error_register: PortReadOnly<u8>,
@@ -60,11 +128,19 @@ pub struct Bus {
 }
 
 impl Bus {
+    /// The largest number of sectors a single READ/WRITE SECTORS command can transfer.
+    pub const MAX_SECTORS: usize = 256;
+    /// How many times `busy_loop` polls the status register before giving up on the drive.
+    const BUSY_TIMEOUT_SPINS: usize = 100_000;
+    /// How long to wait for an IRQ before falling back to polling.
+    const IRQ_TIMEOUT_TICKS: u64 = 2;
+
     #[allow(clippy::identity_op)]
     pub fn new(id: u8, io_base: u16, ctrl_base: u16, irq: u8) -> Self {
         Self {
             id,
             irq,
+            interrupt_driven: false,
 
             data_register: Port::new(io_base + 0),
             error_register: PortReadOnly::new(io_base + 1),
@@ -87,11 +163,37 @@ impl Bus {
         unsafe {
             self.control_register.write(4); // Set SRST bit
             sleep_ticks(2);
-            self.control_register.write(0); // Then clear it
+            self.control_register.write(self.control_bits()); // Then clear it
             sleep_ticks(2);
         }
     }
 
+    fn control_bits(&self) -> u8 {
+        // Set nIEN to mask the drive's IRQ when polling.
+        if self.interrupt_driven {
+            0
+        } else {
+            2
+        }
+    }
+
+    /// Wait for the drive's IRQ between sectors instead of spinning on the status register. Has
+    /// no effect on waiting until `set_interrupt_wait` has been called.
+    pub fn set_interrupt_driven(&mut self, interrupt_driven: bool) {
+        self.interrupt_driven = interrupt_driven;
+        unsafe {
+            self.control_register.write(self.control_bits());
+        }
+    }
+
+    /// Waits for the drive's IRQ, for at most `timeout_ticks`. The caller's `busy_loop` still
+    /// checks the status afterwards, so a missed or stale IRQ only costs the time spent waiting.
+    fn wait_for_interrupt(&mut self, timeout_ticks: u64) {
+        if let (true, Some(wait)) = (self.interrupt_driven, unsafe { INTERRUPT_WAIT }) {
+            wait(self.id, timeout_ticks);
+        }
+    }
+
     fn wait(&mut self) {
         for _ in 0..4 {
             // Wait about 4 x 100 ns
@@ -127,17 +229,20 @@ impl Bus {
         unsafe { self.data_register.write(data) }
     }
 
-    fn busy_loop(&mut self) {
+    fn busy_loop(&mut self) -> Result<(), AtaError> {
         self.wait();
-        let start = 0;
+        let mut spins = 0;
         while self.is_busy() {
-            if 0 - start > 1 {
+            spins += 1;
+            if spins > Self::BUSY_TIMEOUT_SPINS {
                 // Hanged
-                return self.reset();
+                self.reset();
+                return Err(AtaError::Timeout);
             }
 
             core::hint::spin_loop();
         }
+        Ok(())
     }
 
     fn is_busy(&mut self) -> bool {
@@ -152,6 +257,28 @@ impl Bus {
         self.status().get_bit(Status::RDY as usize)
     }
 
+    /// Returns true if nothing is attached to the bus (or the selected drive). The pull-up
+    /// resistors on a floating bus make the status register read as 0xFF.
+    pub fn is_floating(&mut self) -> bool {
+        self.status() == 0xFF
+    }
+
+    /// Returns true if the given drive on this bus responds to being selected.
+    fn drive_is_present(&mut self, drive: u8) -> bool {
+        self.select_drive(drive);
+        self.wait();
+        !self.is_floating()
+    }
+
+    fn check_error(&mut self) -> Result<(), AtaError> {
+        let status = self.status();
+        if status.get_bit(Status::ERR as usize) || status.get_bit(Status::DF as usize) {
+            let error = unsafe { self.error_register.read() };
+            return Err(AtaError::DeviceError { status, error });
+        }
+        Ok(())
+    }
+
     fn select_drive(&mut self, drive: u8) {
         // Drive #0 (primary) = 0xA0
         // Drive #1 (secondary) = 0xB0
@@ -161,19 +288,23 @@ impl Bus {
         }
     }
 
-    fn setup(&mut self, drive: u8, block: u32) {
+    fn setup(&mut self, drive: u8, block: u32, sector_count: usize) {
+        assert!(sector_count > 0 && sector_count <= Self::MAX_SECTORS);
         let drive_id = 0xE0 | (drive << 4);
+        // Forget any IRQ left over from a previous command.
+        self.wait_for_interrupt(0);
         unsafe {
             self.drive_register
                 .write(drive_id | ((block.get_bits(24..28) as u8) & 0x0F));
-            self.sector_count_register.write(1);
+            // A sector count of 0 means 256 sectors.
+            self.sector_count_register.write(sector_count as u8);
             self.lba0_register.write(block.get_bits(0..8) as u8);
             self.lba1_register.write(block.get_bits(8..16) as u8);
             self.lba2_register.write(block.get_bits(16..24) as u8);
         }
     }
 
-    pub fn identify_drive(&mut self, drive: u8) -> Option<[u16; 256]> {
+    pub fn identify_drive(&mut self, drive: u8) -> Option<IdentifyInfo> {
         self.reset();
         self.wait();
         self.select_drive(drive);
@@ -190,7 +321,9 @@ impl Bus {
             return None;
         }
 
-        self.busy_loop();
+        if self.busy_loop().is_err() {
+            return None;
+        }
 
         if self.lba1() != 0 || self.lba2() != 0 {
             return None;
@@ -213,11 +346,148 @@ impl Bus {
         for it in res.iter_mut() {
             *it = self.read_data();
         }
-        Some(res)
+        Some(IdentifyInfo::from_words(&res))
+    }
+
+    /// Identifies an ATAPI drive with IDENTIFY PACKET DEVICE. Returns `None` if the drive doesn't
+    /// have the packet device signature.
+    pub fn identify_packet_drive(&mut self, drive: u8) -> Option<IdentifyInfo> {
+        // After a reset, packet devices put their signature in the LBA registers.
+        self.reset();
+        self.wait();
+        self.select_drive(drive);
+        self.wait();
+        if self.lba1() != 0x14 || self.lba2() != 0xEB {
+            return None;
+        }
+
+        self.write_command(Command::IdentifyPacket);
+        if self.busy_loop().is_err() || self.is_error() {
+            return None;
+        }
+
+        let mut res = [0; 256];
+        for it in res.iter_mut() {
+            *it = self.read_data();
+        }
+        Some(IdentifyInfo::from_words(&res))
+    }
+
+    /// ATAPI drives report errors as a SCSI sense key in the top half of the error register.
+    fn check_packet_error(&mut self) -> Result<(), AtaError> {
+        let status = self.status();
+        if status.get_bit(Status::ERR as usize) {
+            let error = unsafe { self.error_register.read() };
+            return Err(AtaError::PacketError {
+                status,
+                sense_key: error >> 4,
+            });
+        }
+        Ok(())
     }
 
-    /// Read A single, 512-byte long slice from a given block
-    /// panics if buf isn't EXACTLY 512 Bytes long;
+    /// Sends a 12-byte SCSI command to an ATAPI drive with the PACKET command, and reads exactly
+    /// `buf.len()` bytes of response.
+    fn send_packet(
+        &mut self,
+        drive: u8,
+        packet: &[u8; 12],
+        buf: &mut [u8],
+    ) -> Result<(), AtaError> {
+        // The most bytes the drive sends before waiting for the next DRQ block.
+        const BYTE_COUNT_LIMIT: u16 = 2048;
+        self.select_drive(drive);
+        self.wait();
+        unsafe {
+            // PIO transfer, no DMA or overlap.
+            self.features_register.write(0);
+            self.lba1_register
+                .write(BYTE_COUNT_LIMIT.get_bits(0..8) as u8);
+            self.lba2_register
+                .write(BYTE_COUNT_LIMIT.get_bits(8..16) as u8);
+        }
+        self.write_command(Command::Packet);
+        self.busy_loop()?;
+        self.check_packet_error()?;
+        for word in packet.chunks_exact(2) {
+            self.write_data(u16::from_le_bytes([word[0], word[1]]));
+        }
+
+        let mut offset = 0;
+        while offset < buf.len() {
+            self.busy_loop()?;
+            self.check_packet_error()?;
+            if !self.status().get_bit(Status::DRQ as usize) {
+                return Err(AtaError::ShortTransfer);
+            }
+            // The drive says how many bytes are in this block in the LBA registers.
+            let byte_count = (self.lba2() as usize) << 8 | self.lba1() as usize;
+            if byte_count == 0 {
+                // The drive has nothing more to send, so the transfer would never finish.
+                return Err(AtaError::ShortTransfer);
+            }
+            for i in (0..byte_count).step_by(2) {
+                let data = self.read_data().to_le_bytes();
+                // Anything past the end of the buffer is read and discarded.
+                for (j, byte) in data.iter().enumerate() {
+                    if let Some(dest) = buf.get_mut(offset + i + j) {
+                        *dest = *byte;
+                    }
+                }
+            }
+            offset += byte_count;
+        }
+        self.busy_loop()?;
+        self.check_packet_error()
+    }
+
+    /// Read `sector_count` consecutive 2048-byte blocks from an ATAPI drive, using READ (12).
+    pub fn read_packet(
+        &mut self,
+        drive: u8,
+        block: u32,
+        sector_count: u32,
+        buf: &mut [u8],
+    ) -> Result<(), AtaError> {
+        assert_eq!(
+            buf.len(),
+            sector_count as usize * AtapiDrive::BLOCK_SIZE as usize
+        );
+        let [b0, b1, b2, b3] = block.to_be_bytes();
+        let [c0, c1, c2, c3] = sector_count.to_be_bytes();
+        let packet = [
+            PacketCommand::Read12 as u8,
+            0,
+            b0,
+            b1,
+            b2,
+            b3,
+            c0,
+            c1,
+            c2,
+            c3,
+            0,
+            0,
+        ];
+        self.send_packet(drive, &packet, buf)
+    }
+
+    /// Returns the number of blocks on the disc in an ATAPI drive, using READ CAPACITY.
+    pub fn packet_capacity(&mut self, drive: u8) -> Result<u32, AtaError> {
+        let mut packet = [0; 12];
+        packet[0] = PacketCommand::ReadCapacity as u8;
+        // The response is the address of the last block, then the block size, both big-endian.
+        let mut response = [0; 8];
+        self.send_packet(drive, &packet, &mut response)?;
+        let last_block = u32::from_be_bytes(response[0..4].try_into().unwrap());
+        // A last block of 0xFFFFFFFF would mean more blocks than a u32 can count.
+        last_block.checked_add(1).ok_or(AtaError::OutOfBounds)
+    }
+
+    /// Read `sector_count` consecutive 512-byte blocks starting at a given block, using a
+    /// single READ SECTORS command.
+    /// panics if buf isn't EXACTLY `sector_count * 512` Bytes long, or if `sector_count` is
+    /// not between 1 and `MAX_SECTORS`;
     /// Example:
     /// ```rust
     /// // Read A Single block from a disk
@@ -231,20 +501,35 @@ impl Bus {
     ///     read(0, 0, 0, &mut buffer);
     /// }
 
-    pub fn read(&mut self, drive: u8, block: u32, buf: &mut [u8]) {
-        assert_eq!(buf.len(), 512);
-        self.setup(drive, block);
+    pub fn read(
+        &mut self,
+        drive: u8,
+        block: u32,
+        sector_count: usize,
+        buf: &mut [u8],
+    ) -> Result<(), AtaError> {
+        assert_eq!(buf.len(), sector_count * 512);
+        self.setup(drive, block, sector_count);
         self.write_command(Command::Read);
-        self.busy_loop();
-        for i in 0..256 {
-            let data = self.read_data();
-            buf[i * 2] = data.get_bits(0..8) as u8;
-            buf[i * 2 + 1] = data.get_bits(8..16) as u8;
+        for sector in buf.chunks_exact_mut(512) {
+            // The drive raises BSY again between sectors while it fetches the next one, and
+            // interrupts once the sector is ready.
+            self.wait_for_interrupt(Self::IRQ_TIMEOUT_TICKS);
+            self.busy_loop()?;
+            self.check_error()?;
+            for i in 0..256 {
+                let data = self.read_data();
+                sector[i * 2] = data.get_bits(0..8) as u8;
+                sector[i * 2 + 1] = data.get_bits(8..16) as u8;
+            }
         }
+        Ok(())
     }
 
-    /// Write A single, 512-byte long slice to a given block
-    /// panics if buf isn't EXACTLY 512 Bytes long;
+    /// Write `sector_count` consecutive 512-byte blocks starting at a given block, using a
+    /// single WRITE SECTORS command.
+    /// panics if buf isn't EXACTLY `sector_count * 512` Bytes long, or if `sector_count` is
+    /// not between 1 and `MAX_SECTORS`;
     /// Example:
     /// ```rust
     /// // Read A Single block from a disk
@@ -258,18 +543,41 @@ impl Bus {
     ///     write(0, 0, 0, &buffer);
     /// }
 
-    pub fn write(&mut self, drive: u8, block: u32, buf: &[u8]) {
-        assert_eq!(buf.len(), 512);
-        self.setup(drive, block);
+    pub fn write(
+        &mut self,
+        drive: u8,
+        block: u32,
+        sector_count: usize,
+        buf: &[u8],
+    ) -> Result<(), AtaError> {
+        assert_eq!(buf.len(), sector_count * 512);
+        self.setup(drive, block, sector_count);
         self.write_command(Command::Write);
-        self.busy_loop();
-        for i in 0..256 {
-            let mut data = 0u16;
-            data.set_bits(0..8, buf[i * 2] as u16);
-            data.set_bits(8..16, buf[i * 2 + 1] as u16);
-            self.write_data(data);
+        for sector in buf.chunks_exact(512) {
+            self.busy_loop()?;
+            self.check_error()?;
+            for i in 0..256 {
+                let mut data = 0u16;
+                data.set_bits(0..8, sector[i * 2] as u16);
+                data.set_bits(8..16, sector[i * 2 + 1] as u16);
+                self.write_data(data);
+            }
+            // The drive interrupts after it has written each sector.
+            self.wait_for_interrupt(Self::IRQ_TIMEOUT_TICKS);
         }
-        self.busy_loop();
+        self.busy_loop()?;
+        self.check_error()
+    }
+
+    /// Ask the drive to write its cache to the disk, so that completed writes survive a reset
+    /// or power loss. Safe to call even if nothing has been written.
+    pub fn flush(&mut self, drive: u8) -> Result<(), AtaError> {
+        unsafe {
+            self.drive_register.write(0xE0 | (drive << 4));
+        }
+        self.write_command(Command::FlushCache);
+        self.busy_loop()?;
+        self.check_error()
     }
 }
 
@@ -281,6 +589,78 @@ pub enum AtaError {
     AddressNotAligned,
     OutOfBounds,
     WrongSizeBuffer,
+    Timeout,
+    /// The drive reported an error. Holds the contents of the status and error registers.
+    DeviceError {
+        status: u8,
+        error: u8,
+    },
+    /// An ATAPI drive reported an error. Holds the status register and the SCSI sense key.
+    PacketError {
+        status: u8,
+        sense_key: u8,
+    },
+    /// An ATAPI drive finished a command before sending all the requested data.
+    ShortTransfer,
+    /// The drive can't be written to.
+    ReadOnly,
+}
+
+impl fmt::Display for AtaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            AtaError::NotInitialized => write!(f, "ATA not initialized"),
+            AtaError::AddressNotAligned => write!(f, "address not aligned to block size"),
+            AtaError::OutOfBounds => write!(f, "address out of bounds"),
+            AtaError::WrongSizeBuffer => write!(f, "buffer size does not match block count"),
+            AtaError::Timeout => write!(f, "drive timed out"),
+            AtaError::DeviceError { status, error } => {
+                write!(
+                    f,
+                    "device error (status {:#04x}, error {:#04x})",
+                    status, error
+                )?;
+                let mut separator = ": ";
+                if status.get_bit(Status::DF as usize) {
+                    write!(f, "{}drive fault", separator)?;
+                    separator = ", ";
+                }
+                for (bit, description) in ErrorBit::ALL {
+                    if error.get_bit(bit as usize) {
+                        write!(f, "{}{}", separator, description)?;
+                        separator = ", ";
+                    }
+                }
+                Ok(())
+            }
+            AtaError::PacketError { status, sense_key } => write!(
+                f,
+                "packet device error (status {:#04x}): {}",
+                status,
+                SENSE_KEYS[sense_key as usize & 0xF]
+            ),
+            AtaError::ShortTransfer => write!(f, "drive sent less data than requested"),
+            AtaError::ReadOnly => write!(f, "drive is read-only"),
+        }
+    }
+}
+
+/// Converts a byte address into a block index, checking that it is block-aligned and that
+/// `number_of_blocks` blocks starting there fit within `block_count` blocks.
+fn byte_index_to_block(
+    address: usize,
+    number_of_blocks: usize,
+    block_count: usize,
+) -> Result<usize, AtaError> {
+    const BLOCK_SIZE: usize = Drive::BLOCK_SIZE as usize;
+    if address % BLOCK_SIZE != 0 {
+        return Err(AtaError::AddressNotAligned);
+    }
+    let block = address / BLOCK_SIZE;
+    match block.checked_add(number_of_blocks) {
+        Some(end) if end <= block_count => Ok(block),
+        _ => Err(AtaError::OutOfBounds),
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -300,24 +680,21 @@ impl Drive {
     }
     fn byte_index_to_lba(
         &self,
-        mut address: usize,
+        address: usize,
         number_of_blocks: usize,
     ) -> Result<usize, AtaError> {
-        const BLOCK_SIZE: usize = Drive::BLOCK_SIZE as usize;
-        if address % BLOCK_SIZE != 0 {
-            return Err(AtaError::AddressNotAligned);
-        }
-        address /= BLOCK_SIZE;
-        if address + number_of_blocks > self.block_count {
-            Err(AtaError::OutOfBounds)
-        } else {
-            Ok(address)
-        }
+        byte_index_to_block(address, number_of_blocks, self.block_count)
     }
 
     pub fn size_in_kib(&self) -> usize {
         self.block_count / 2
     }
+
+    /// Flush the drive's write cache. See `Bus::flush`.
+    pub fn flush(&self) -> Result<(), AtaError> {
+        let buses = unsafe { BUSES.as_mut().ok_or(AtaError::NotInitialized)? };
+        buses[self.bus].flush(self.drive)
+    }
 }
 
 impl BlockDevice for Drive {
@@ -335,13 +712,17 @@ impl BlockDevice for Drive {
         }
         let address = self.byte_index_to_lba(address, number_of_blocks)?;
         let buses = unsafe { BUSES.as_mut().ok_or(AtaError::NotInitialized)? };
-        for i in 0..number_of_blocks {
-            let off = i * BLOCK_SIZE;
+        let mut block = 0;
+        while block < number_of_blocks {
+            let count = (number_of_blocks - block).min(Bus::MAX_SECTORS);
+            let off = block * BLOCK_SIZE;
             buses[self.bus].read(
                 self.drive,
-                (address + i) as u32,
-                &mut buf[off..off + BLOCK_SIZE],
-            );
+                (address + block) as u32,
+                count,
+                &mut buf[off..off + (count * BLOCK_SIZE)],
+            )?;
+            block += count;
         }
         Ok(())
     }
@@ -357,13 +738,17 @@ impl BlockDevice for Drive {
         }
         let address = self.byte_index_to_lba(address, number_of_blocks)?;
         let buses = unsafe { BUSES.as_mut().ok_or(AtaError::NotInitialized)? };
-        for i in 0..number_of_blocks {
-            let off = i * BLOCK_SIZE;
+        let mut block = 0;
+        while block < number_of_blocks {
+            let count = (number_of_blocks - block).min(Bus::MAX_SECTORS);
+            let off = block * BLOCK_SIZE;
             buses[self.bus].write(
                 self.drive,
-                (address + i) as u32,
-                &buf[off..off + BLOCK_SIZE],
-            );
+                (address + block) as u32,
+                count,
+                &buf[off..off + (count * BLOCK_SIZE)],
+            )?;
+            block += count;
         }
         Ok(())
     }
@@ -372,33 +757,34 @@ impl BlockDevice for Drive {
 #[derive(Debug, Copy, Clone)]
 pub struct Partition {
     drive: Drive,
-    start_byte: usize,
-    num_bytes: usize,
+    start_lba: usize,
+    block_count: usize,
 }
 
 impl Partition {
     pub fn new(drive: Drive, lba: usize, num_blocks: usize) -> Partition {
         Partition {
             drive,
-            start_byte: lba * Drive::BLOCK_SIZE as usize,
-            num_bytes: num_blocks * Drive::BLOCK_SIZE as usize,
+            start_lba: lba,
+            block_count: num_blocks,
         }
     }
     pub fn size_in_kib(&self) -> usize {
-        self.num_bytes / 1024
+        self.block_count / 2
     }
 
-    fn check_address_in_bounds(
-        &self,
-        address: usize,
-        number_of_blocks: usize,
-    ) -> Result<(), AtaError> {
+    /// Flush the write cache of the drive containing this partition.
+    pub fn flush(&self) -> Result<(), AtaError> {
+        self.drive.flush()
+    }
+
+    /// Translates a byte address within the partition into a byte address on the drive,
+    /// checking that the whole access stays inside the partition. All bounds math is done in
+    /// blocks, the same as `Drive`.
+    fn drive_address(&self, address: usize, number_of_blocks: usize) -> Result<usize, AtaError> {
         const BLOCK_SIZE: usize = Drive::BLOCK_SIZE as usize;
-        if address + (number_of_blocks * BLOCK_SIZE) > self.num_bytes {
-            Err(AtaError::OutOfBounds)
-        } else {
-            Ok(())
-        }
+        let block = byte_index_to_block(address, number_of_blocks, self.block_count)?;
+        Ok((self.start_lba + block) * BLOCK_SIZE)
     }
 }
 
@@ -411,9 +797,8 @@ impl BlockDevice for Partition {
         address: usize,
         number_of_blocks: usize,
     ) -> Result<(), Self::Error> {
-        self.check_address_in_bounds(address, number_of_blocks)?;
-        self.drive
-            .read(buf, address + self.start_byte, number_of_blocks)
+        let address = self.drive_address(address, number_of_blocks)?;
+        self.drive.read(buf, address, number_of_blocks)
     }
     fn write(
         &self,
@@ -421,17 +806,137 @@ impl BlockDevice for Partition {
         address: usize,
         number_of_blocks: usize,
     ) -> Result<(), Self::Error> {
-        self.check_address_in_bounds(address, number_of_blocks)?;
-        self.drive
-            .write(buf, address + self.start_byte, number_of_blocks)
+        let address = self.drive_address(address, number_of_blocks)?;
+        self.drive.write(buf, address, number_of_blocks)
+    }
+}
+
+/// An ATAPI drive, such as a CD-ROM drive. Only reading is supported.
+#[derive(Debug, Copy, Clone)]
+pub struct AtapiDrive {
+    bus: usize,
+    drive: u8,
+}
+
+impl AtapiDrive {
+    fn new(bus: u8, drive: u8) -> AtapiDrive {
+        AtapiDrive {
+            bus: bus as usize,
+            drive,
+        }
+    }
+
+    /// Returns the number of blocks on the disc currently in the drive. Fails with a "not ready"
+    /// sense key if there is no disc.
+    pub fn block_count(&self) -> Result<u32, AtaError> {
+        let buses = unsafe { BUSES.as_mut().ok_or(AtaError::NotInitialized)? };
+        buses[self.bus].packet_capacity(self.drive)
+    }
+}
+
+impl BlockDevice for AtapiDrive {
+    const BLOCK_SIZE: u32 = 2048;
+    type Error = AtaError;
+    fn read(
+        &self,
+        buf: &mut [u8],
+        address: usize,
+        number_of_blocks: usize,
+    ) -> Result<(), Self::Error> {
+        const BLOCK_SIZE: usize = AtapiDrive::BLOCK_SIZE as usize;
+        if buf.len() != number_of_blocks * BLOCK_SIZE {
+            return Err(AtaError::WrongSizeBuffer);
+        }
+        if address % BLOCK_SIZE != 0 {
+            return Err(AtaError::AddressNotAligned);
+        }
+        // The drive checks the range against the disc itself, reporting an illegal request.
+        let block = u32::try_from(address / BLOCK_SIZE).map_err(|_| AtaError::OutOfBounds)?;
+        let count = u32::try_from(number_of_blocks).map_err(|_| AtaError::OutOfBounds)?;
+        let buses = unsafe { BUSES.as_mut().ok_or(AtaError::NotInitialized)? };
+        buses[self.bus].read_packet(self.drive, block, count, buf)
+    }
+    fn write(
+        &self,
+        _buf: &[u8],
+        _address: usize,
+        _number_of_blocks: usize,
+    ) -> Result<(), Self::Error> {
+        Err(AtaError::ReadOnly)
+    }
+}
+
+/// The parts of the 256 words returned by the IDENTIFY command that the driver understands.
+#[derive(Debug, Clone)]
+pub struct IdentifyInfo {
+    pub serial: String,
+    pub firmware_revision: String,
+    pub model: String,
+    /// Number of sectors addressable with 28-bit LBA.
+    pub lba28_sectors: u32,
+    /// Number of sectors addressable with 48-bit LBA, if the drive supports it.
+    pub lba48_sectors: Option<u64>,
+    /// Bitmask of supported UDMA modes, bit n set means mode n is supported.
+    pub udma_supported: u8,
+    /// The UDMA mode currently selected, if any.
+    pub udma_selected: Option<u8>,
+    /// Size of a logical sector in bytes.
+    pub sector_size: u32,
+}
+
+impl IdentifyInfo {
+    /// Strings are stored as ASCII with the two bytes of each word swapped.
+    fn read_string(words: &[u16; 256], range: Range<usize>) -> String {
+        let mut string = String::new();
+        for word in &words[range] {
+            for &b in &word.to_be_bytes() {
+                string.push(b as char);
+            }
+        }
+        string.trim().into()
+    }
+
+    pub fn from_words(words: &[u16; 256]) -> IdentifyInfo {
+        let lba28_sectors = (words[61] as u32) << 16 | (words[60] as u32);
+        let lba48_sectors = if words[83].get_bit(10) {
+            Some(
+                (words[103] as u64) << 48
+                    | (words[102] as u64) << 32
+                    | (words[101] as u64) << 16
+                    | (words[100] as u64),
+            )
+        } else {
+            None
+        };
+        let udma_supported = words[88].get_bits(0..8) as u8;
+        let udma_selected = match words[88].get_bits(8..16) {
+            0 => None,
+            selected => Some(selected.trailing_zeros() as u8),
+        };
+        // Word 106 is only valid if bit 14 is set and bit 15 is clear. Bit 12 then says the
+        // logical sector is longer than 256 words, with the length in words 117-118.
+        let sector_size = if words[106].get_bits(14..16) == 0b01 && words[106].get_bit(12) {
+            ((words[118] as u32) << 16 | (words[117] as u32)) * 2
+        } else {
+            512
+        };
+        IdentifyInfo {
+            serial: Self::read_string(words, 10..20),
+            firmware_revision: Self::read_string(words, 23..27),
+            model: Self::read_string(words, 27..47),
+            lba28_sectors,
+            lba48_sectors,
+            udma_supported,
+            udma_selected,
+            sector_size,
+        }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DriveInfo {
     pub drive: Drive,
-    pub model: String,
-    pub serial: String,
+    pub info: IdentifyInfo,
 }
 
 impl DriveInfo {
@@ -440,42 +945,189 @@ impl DriveInfo {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct AtapiDriveInfo {
+    pub drive: AtapiDrive,
+    pub info: IdentifyInfo,
+}
+
+/// The drives found by the last scan. Identifying a drive resets its bus, so this is only filled
+/// by `init` and `rescan`.
+static mut DRIVES: Option<Vec<DriveInfo>> = None;
+static mut ATAPI_DRIVES: Option<Vec<AtapiDriveInfo>> = None;
+
+/// Returns the drives found when the buses were last scanned.
 pub fn list() -> Result<Vec<DriveInfo>, AtaError> {
+    unsafe { DRIVES.clone().ok_or(AtaError::NotInitialized) }
+}
+
+/// Returns the ATAPI drives found when the buses were last scanned.
+pub fn list_atapi() -> Result<Vec<AtapiDriveInfo>, AtaError> {
+    unsafe { ATAPI_DRIVES.clone().ok_or(AtaError::NotInitialized) }
+}
+
+/// Identifies every drive again, replacing the lists returned by `list` and `list_atapi`. This resets the buses, so
+/// it must not be called while a drive is in use.
+pub fn rescan() -> Result<(), AtaError> {
     let buses = unsafe { BUSES.as_mut().ok_or(AtaError::NotInitialized)? };
     let mut res = Vec::new();
+    let mut atapi = Vec::new();
     for bus in 0..2 {
+        // Identifying a drive on a floating bus can hang, so skip it entirely.
+        if buses[bus as usize].is_floating() {
+            continue;
+        }
         for drive in 0..2 {
-            if let Some(buf) = buses[bus as usize].identify_drive(drive) {
-                let mut serial = String::new();
-                for i in 10..20 {
-                    for &b in &buf[i].to_be_bytes() {
-                        serial.push(b as char);
-                    }
-                }
-                serial = serial.trim().into();
-                let mut model = String::new();
-                for i in 27..47 {
-                    for &b in &buf[i].to_be_bytes() {
-                        model.push(b as char);
-                    }
-                }
-                model = model.trim().into();
-                let block_count = (buf[61] as u32) << 16 | (buf[60] as u32);
+            if !buses[bus as usize].drive_is_present(drive) {
+                continue;
+            }
+            if let Some(info) = buses[bus as usize].identify_drive(drive) {
                 res.push(DriveInfo {
-                    drive: Drive::new(bus, drive, block_count),
-                    model,
-                    serial,
+                    drive: Drive::new(bus, drive, info.lba28_sectors),
+                    info,
+                });
+            } else if let Some(info) = buses[bus as usize].identify_packet_drive(drive) {
+                atapi.push(AtapiDriveInfo {
+                    drive: AtapiDrive::new(bus, drive),
+                    info,
                 });
             }
         }
     }
-    Ok(res)
+    unsafe {
+        DRIVES = Some(res);
+        ATAPI_DRIVES = Some(atapi);
+    }
+    Ok(())
 }
 
-// pub fn drive_is_present(bus: usize) -> bool {
-//     unsafe { BUSES.lock()[bus].status_register.read() != 0xFF }
-// }
+/// Makes every bus wait for its IRQ with `wait` between sectors, or go back to polling with `None`.
+/// Must be called after `init`.
+pub unsafe fn set_interrupt_wait(wait: Option<InterruptWait>) {
+    INTERRUPT_WAIT = wait;
+    if let Some(buses) = BUSES.as_mut() {
+        for bus in buses {
+            bus.set_interrupt_driven(wait.is_some());
+        }
+    }
+}
 
 pub unsafe fn init() {
-    BUSES = Some([Bus::new(0, 0x1F0, 0x3F6, 14), Bus::new(1, 0x170, 0x376, 15)]);
+    let [(io0, ctrl0, irq0), (io1, ctrl1, irq1)] = BUS_PORTS;
+    BUSES = Some([Bus::new(0, io0, ctrl0, irq0), Bus::new(1, io1, ctrl1, irq1)]);
+    rescan().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mbr::{MasterBootRecord, PartitionTableEntry, PartitionType};
+    use ramdisk::RamDisk;
+
+    const BLOCK_SIZE: usize = Drive::BLOCK_SIZE as usize;
+
+    /// A disk with an MBR and three partitions directly after each other, and the partitions read
+    /// back from it.
+    fn three_partition_disk() -> (RamDisk, Vec<Partition>) {
+        let disk = RamDisk::new(64);
+        let mut entries = [PartitionTableEntry::empty(); 4];
+        entries[0] = PartitionTableEntry::new(false, PartitionType::Fat32(0x0c), 1, 20);
+        entries[1] = PartitionTableEntry::new(false, PartitionType::LinuxExt(0x83), 21, 20);
+        entries[2] = PartitionTableEntry::new(false, PartitionType::LinuxExt(0x83), 41, 23);
+        let mut block = [0; BLOCK_SIZE];
+        MasterBootRecord {
+            disk_signature: 0,
+            entries,
+        }
+        .serialize(&mut block)
+        .unwrap();
+        disk.write(&block, 0, 1).unwrap();
+
+        let drive = Drive::new(0, 0, disk.block_count() as u32);
+        let partitions = MasterBootRecord::read_from(&disk)
+            .unwrap()
+            .used_partitions()
+            .map(|(_, entry)| {
+                Partition::new(
+                    drive,
+                    entry.logical_block_address as usize,
+                    entry.sector_count as usize,
+                )
+            })
+            .collect();
+        (disk, partitions)
+    }
+
+    /// Writes through a partition's address translation. `Partition` only talks to ATA drives, so
+    /// the write itself goes to the RAM disk.
+    fn write(
+        disk: &RamDisk,
+        partition: &Partition,
+        buf: &[u8],
+        address: usize,
+        number_of_blocks: usize,
+    ) -> Result<(), AtaError> {
+        let address = partition.drive_address(address, number_of_blocks)?;
+        disk.write(buf, address, number_of_blocks).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn last_block_write_stays_in_partition() {
+        let (disk, partitions) = three_partition_disk();
+        assert_eq!(partitions.len(), 3);
+        for (index, partition) in partitions.iter().enumerate() {
+            let last_block = (partition.block_count - 1) * BLOCK_SIZE;
+            let buf = [index as u8 + 1; BLOCK_SIZE];
+            write(&disk, partition, &buf, last_block, 1).unwrap();
+        }
+
+        let bytes = disk.into_bytes();
+        let block = |lba: usize| &bytes[lba * BLOCK_SIZE..(lba + 1) * BLOCK_SIZE];
+        for lba in 1..64 {
+            let expected = match lba {
+                20 => 1,
+                40 => 2,
+                63 => 3,
+                _ => 0,
+            };
+            assert!(
+                block(lba).iter().all(|b| *b == expected),
+                "block {} should be filled with {}",
+                lba,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn write_past_partition_end_is_rejected() {
+        let (disk, partitions) = three_partition_disk();
+        let partition = &partitions[0];
+        let end = partition.block_count * BLOCK_SIZE;
+        let buf = [0xff; BLOCK_SIZE * 2];
+        assert!(matches!(
+            write(&disk, partition, &buf[..BLOCK_SIZE], end, 1),
+            Err(AtaError::OutOfBounds)
+        ));
+        assert!(matches!(
+            write(&disk, partition, &buf, end - BLOCK_SIZE, 2),
+            Err(AtaError::OutOfBounds)
+        ));
+        assert!(matches!(
+            write(&disk, partition, &buf[..BLOCK_SIZE], 1, 1),
+            Err(AtaError::AddressNotAligned)
+        ));
+        assert!(disk.into_bytes()[BLOCK_SIZE..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn partition_addresses_start_at_its_first_block() {
+        let (_, partitions) = three_partition_disk();
+        assert_eq!(partitions[1].drive_address(0, 1).unwrap(), 21 * BLOCK_SIZE);
+        assert_eq!(
+            partitions[2].drive_address(22 * BLOCK_SIZE, 1).unwrap(),
+            63 * BLOCK_SIZE
+        );
+    }
 }
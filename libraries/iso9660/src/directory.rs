@@ -0,0 +1,76 @@
+use alloc::string::String;
+
+fn read_u32_le(buf: &[u8]) -> u32 {
+    u32::from_le_bytes(buf.try_into().unwrap())
+}
+
+/// One entry of a directory: a file, or a subdirectory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirectoryRecord {
+    /// The first logical block of the entry's data.
+    pub extent: u32,
+    /// The length of the entry's data in bytes.
+    pub data_length: u32,
+    pub flags: u8,
+    /// The file identifier with its `;1` version suffix removed. Empty for the root directory.
+    pub name: String,
+}
+
+impl DirectoryRecord {
+    pub(crate) const FLAG_DIRECTORY: u8 = 1 << 1;
+    /// The size of a record before the file identifier.
+    pub(crate) const HEADER_SIZE: usize = 33;
+
+    pub fn is_directory(&self) -> bool {
+        self.flags & Self::FLAG_DIRECTORY != 0
+    }
+
+    /// Returns true if the record's name matches `name`. ISO9660 names are upper case, so the
+    /// comparison ignores case.
+    pub fn name_matches(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+    }
+
+    /// Parses the record at the start of `bytes`, returning it and its length. Returns `Ok(None)`
+    /// if `bytes` starts with a zero length byte, which pads out the rest of a sector.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Option<(DirectoryRecord, usize)>, ()> {
+        let length = *bytes.first().ok_or(())? as usize;
+        if length == 0 {
+            return Ok(None);
+        }
+        if length < Self::HEADER_SIZE || length > bytes.len() {
+            return Err(());
+        }
+        let name_length = bytes[32] as usize;
+        if Self::HEADER_SIZE + name_length > length {
+            return Err(());
+        }
+        let identifier = &bytes[Self::HEADER_SIZE..Self::HEADER_SIZE + name_length];
+        let record = DirectoryRecord {
+            extent: read_u32_le(&bytes[2..6]),
+            data_length: read_u32_le(&bytes[10..14]),
+            flags: bytes[25],
+            name: Self::parse_name(identifier),
+        };
+        Ok(Some((record, length)))
+    }
+
+    /// Turns a file identifier like `README.TXT;1` into `README.TXT`. The special identifiers for
+    /// the current and parent directory, `\0` and `\1`, become `.` and `..`.
+    fn parse_name(identifier: &[u8]) -> String {
+        match identifier {
+            [0] => return String::from("."),
+            [1] => return String::from(".."),
+            _ => (),
+        }
+        let mut name: String = identifier.iter().map(|b| *b as char).collect();
+        if let Some(version) = name.find(';') {
+            name.truncate(version);
+        }
+        // Files without an extension are written with a trailing dot.
+        if name.ends_with('.') {
+            name.pop();
+        }
+        name
+    }
+}
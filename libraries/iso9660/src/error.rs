@@ -0,0 +1,35 @@
+/// An error thrown while reading an ISO9660 volume.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Iso9660Error<E> {
+    /// The device failed to read a block.
+    Device(E),
+
+    /// The volume descriptors ended without a primary volume descriptor.
+    NoPrimaryVolumeDescriptor,
+
+    /// A volume descriptor did not start with the `CD001` identifier.
+    InvalidDescriptor {
+        /// The sector holding the descriptor
+        sector: u32,
+    },
+
+    /// The volume's logical block size is not 2048, or is not a multiple of the device's block
+    /// size.
+    UnsupportedBlockSize {
+        /// The logical block size read from the primary volume descriptor, or the device's block
+        /// size if that is the one not supported
+        size: u32,
+    },
+
+    /// A directory record was cut off by the end of its sector or directory.
+    InvalidDirectoryRecord,
+
+    /// No file or directory has the requested path.
+    NotFound,
+
+    /// A path component that must be a directory is a file.
+    NotADirectory,
+
+    /// The path names a directory where a file was expected.
+    NotAFile,
+}
@@ -0,0 +1,365 @@
+#![no_std]
+extern crate alloc;
+
+use alloc::{string::String, vec, vec::Vec};
+use block_device::BlockDevice;
+
+mod directory;
+pub use directory::DirectoryRecord;
+
+mod error;
+pub use error::Iso9660Error;
+
+/// The size of a sector, and of every logical block this crate supports.
+const SECTOR_SIZE: usize = 2048;
+/// The first sector of the volume descriptor set. Sectors before it are for system use.
+const DESCRIPTOR_START: u32 = 16;
+const DESCRIPTOR_ID: &[u8; 5] = b"CD001";
+const DESCRIPTOR_PRIMARY: u8 = 1;
+const DESCRIPTOR_TERMINATOR: u8 = 255;
+/// The offset of the root directory record within the primary volume descriptor.
+const ROOT_RECORD_OFFSET: usize = 156;
+
+fn read_u32_le(buf: &[u8]) -> u32 {
+    u32::from_le_bytes(buf.try_into().unwrap())
+}
+fn read_u16_le(buf: &[u8]) -> u16 {
+    u16::from_le_bytes(buf.try_into().unwrap())
+}
+fn read_string(buf: &[u8]) -> String {
+    let string: String = buf.iter().map(|b| *b as char).collect();
+    String::from(string.trim_end())
+}
+
+/// The parts of the primary volume descriptor that describe the volume.
+#[derive(Clone, Debug)]
+pub struct PrimaryVolumeDescriptor {
+    pub system_id: String,
+    pub volume_id: String,
+    /// The size of the volume in logical blocks.
+    pub volume_space_size: u32,
+    pub logical_block_size: u16,
+    pub root: DirectoryRecord,
+}
+
+impl PrimaryVolumeDescriptor {
+    fn from_bytes<E>(bytes: &[u8]) -> Result<PrimaryVolumeDescriptor, Iso9660Error<E>> {
+        let (root, _) = DirectoryRecord::from_bytes(&bytes[ROOT_RECORD_OFFSET..])
+            .ok()
+            .flatten()
+            .ok_or(Iso9660Error::InvalidDirectoryRecord)?;
+        Ok(PrimaryVolumeDescriptor {
+            system_id: read_string(&bytes[8..40]),
+            volume_id: read_string(&bytes[40..72]),
+            volume_space_size: read_u32_le(&bytes[80..84]),
+            logical_block_size: read_u16_le(&bytes[128..130]),
+            root,
+        })
+    }
+}
+
+/// A read-only ISO9660 filesystem on a block device. Only the primary volume descriptor is used,
+/// so names are the plain ISO9660 ones without the Joliet or Rock Ridge extensions.
+pub struct Volume<D: BlockDevice> {
+    device: D,
+    descriptor: PrimaryVolumeDescriptor,
+}
+
+impl<D: BlockDevice> Volume<D> {
+    /// Finds the primary volume descriptor on the device.
+    ///
+    /// The device's block size must divide 2048, so both CD drives and disk images with
+    /// 512-byte blocks can be read.
+    pub fn new(device: D) -> Result<Volume<D>, Iso9660Error<D::Error>> {
+        if D::BLOCK_SIZE == 0 || !SECTOR_SIZE.is_multiple_of(D::BLOCK_SIZE as usize) {
+            return Err(Iso9660Error::UnsupportedBlockSize {
+                size: D::BLOCK_SIZE,
+            });
+        }
+        let mut sector = DESCRIPTOR_START;
+        let descriptor = loop {
+            let bytes = Self::read_sectors(&device, sector, SECTOR_SIZE)?;
+            if &bytes[1..6] != DESCRIPTOR_ID {
+                return Err(Iso9660Error::InvalidDescriptor { sector });
+            }
+            match bytes[0] {
+                DESCRIPTOR_PRIMARY => break PrimaryVolumeDescriptor::from_bytes(&bytes)?,
+                DESCRIPTOR_TERMINATOR => return Err(Iso9660Error::NoPrimaryVolumeDescriptor),
+                _ => sector += 1,
+            }
+        };
+        if descriptor.logical_block_size as usize != SECTOR_SIZE {
+            return Err(Iso9660Error::UnsupportedBlockSize {
+                size: descriptor.logical_block_size as u32,
+            });
+        }
+        Ok(Volume { device, descriptor })
+    }
+
+    pub fn descriptor(&self) -> &PrimaryVolumeDescriptor {
+        &self.descriptor
+    }
+    pub fn root(&self) -> &DirectoryRecord {
+        &self.descriptor.root
+    }
+
+    /// Reads `length` bytes starting at a sector, rounded up to whole sectors.
+    fn read_sectors(
+        device: &D,
+        sector: u32,
+        length: usize,
+    ) -> Result<Vec<u8>, Iso9660Error<D::Error>> {
+        let block_size = D::BLOCK_SIZE as usize;
+        let mut buffer = vec![0u8; length.div_ceil(SECTOR_SIZE) * SECTOR_SIZE];
+        let number_of_blocks = buffer.len() / block_size;
+        if number_of_blocks > 0 {
+            device
+                .read(&mut buffer, sector as usize * SECTOR_SIZE, number_of_blocks)
+                .map_err(Iso9660Error::Device)?;
+        }
+        Ok(buffer)
+    }
+
+    /// Lists the entries of a directory, not including `.` and `..`.
+    pub fn read_dir(
+        &self,
+        dir: &DirectoryRecord,
+    ) -> Result<Vec<DirectoryRecord>, Iso9660Error<D::Error>> {
+        if !dir.is_directory() {
+            return Err(Iso9660Error::NotADirectory);
+        }
+        let data = Self::read_sectors(&self.device, dir.extent, dir.data_length as usize)?;
+        let mut entries = Vec::new();
+        // Records never cross a sector boundary; the rest of a sector after the last one is zero.
+        for sector in data[..dir.data_length as usize].chunks(SECTOR_SIZE) {
+            let mut offset = 0;
+            while offset < sector.len() {
+                match DirectoryRecord::from_bytes(&sector[offset..]) {
+                    Ok(Some((record, length))) => {
+                        if record.name != "." && record.name != ".." {
+                            entries.push(record);
+                        }
+                        offset += length;
+                    }
+                    Ok(None) => break,
+                    Err(()) => return Err(Iso9660Error::InvalidDirectoryRecord),
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Finds the record for a `/`-separated path, starting from the root directory.
+    pub fn find(&self, path: &str) -> Result<DirectoryRecord, Iso9660Error<D::Error>> {
+        let mut record = self.root().clone();
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            record = self
+                .read_dir(&record)?
+                .into_iter()
+                .find(|entry| entry.name_matches(name))
+                .ok_or(Iso9660Error::NotFound)?;
+        }
+        Ok(record)
+    }
+
+    /// Reads the whole contents of the file at `path`.
+    pub fn read_file(&self, path: &str) -> Result<Vec<u8>, Iso9660Error<D::Error>> {
+        let record = self.find(path)?;
+        if record.is_directory() {
+            return Err(Iso9660Error::NotAFile);
+        }
+        let mut data =
+            Self::read_sectors(&self.device, record.extent, record.data_length as usize)?;
+        data.truncate(record.data_length as usize);
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ramdisk::RamDisk;
+
+    const ROOT_SECTOR: u32 = 18;
+    const SUBDIR_SECTOR: u32 = 19;
+    const README_SECTOR: u32 = 20;
+    const NOTES_SECTOR: u32 = 21;
+    const README: &[u8] = b"hello from the root";
+    const NOTES: &[u8] = b"a file with no extension";
+
+    /// A directory record, padded to an even length as on disc.
+    fn record(extent: u32, data_length: u32, directory: bool, identifier: &[u8]) -> Vec<u8> {
+        let length = (DirectoryRecord::HEADER_SIZE + identifier.len()).next_multiple_of(2);
+        let mut record = vec![0u8; length];
+        record[0] = length as u8;
+        record[2..6].copy_from_slice(&extent.to_le_bytes());
+        record[6..10].copy_from_slice(&extent.to_be_bytes());
+        record[10..14].copy_from_slice(&data_length.to_le_bytes());
+        record[14..18].copy_from_slice(&data_length.to_be_bytes());
+        if directory {
+            record[25] = DirectoryRecord::FLAG_DIRECTORY;
+        }
+        record[32] = identifier.len() as u8;
+        record[DirectoryRecord::HEADER_SIZE..DirectoryRecord::HEADER_SIZE + identifier.len()]
+            .copy_from_slice(identifier);
+        record
+    }
+
+    fn directory(sector: u32, parent: u32, entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = record(sector, SECTOR_SIZE as u32, true, &[0]);
+        data.extend(record(parent, SECTOR_SIZE as u32, true, &[1]));
+        for entry in entries {
+            data.extend(entry);
+        }
+        data
+    }
+
+    fn write_sector(image: &mut [u8], sector: u32, data: &[u8]) {
+        let start = sector as usize * SECTOR_SIZE;
+        image[start..start + data.len()].copy_from_slice(data);
+    }
+
+    /// An image with a primary volume descriptor at sector 16, the terminator after it, and a root
+    /// directory holding `README.TXT;1` and a `DOCS` directory that holds `NOTES.;1`.
+    fn image() -> Vec<u8> {
+        let mut image = vec![0u8; (NOTES_SECTOR as usize + 1) * SECTOR_SIZE];
+
+        let mut pvd = vec![0u8; SECTOR_SIZE];
+        pvd[0] = DESCRIPTOR_PRIMARY;
+        pvd[1..6].copy_from_slice(DESCRIPTOR_ID);
+        pvd[8..40].fill(b' ');
+        pvd[8..14].copy_from_slice(b"MYTHOS");
+        pvd[40..72].fill(b' ');
+        pvd[40..44].copy_from_slice(b"TEST");
+        pvd[80..84].copy_from_slice(&(NOTES_SECTOR + 1).to_le_bytes());
+        pvd[128..130].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+        let root = record(ROOT_SECTOR, SECTOR_SIZE as u32, true, &[0]);
+        pvd[ROOT_RECORD_OFFSET..ROOT_RECORD_OFFSET + root.len()].copy_from_slice(&root);
+        write_sector(&mut image, DESCRIPTOR_START, &pvd);
+
+        let mut terminator = vec![0u8; 7];
+        terminator[0] = DESCRIPTOR_TERMINATOR;
+        terminator[1..6].copy_from_slice(DESCRIPTOR_ID);
+        write_sector(&mut image, DESCRIPTOR_START + 1, &terminator);
+
+        let root_dir = directory(
+            ROOT_SECTOR,
+            ROOT_SECTOR,
+            &[
+                record(README_SECTOR, README.len() as u32, false, b"README.TXT;1"),
+                record(SUBDIR_SECTOR, SECTOR_SIZE as u32, true, b"DOCS"),
+            ],
+        );
+        write_sector(&mut image, ROOT_SECTOR, &root_dir);
+        let subdir = directory(
+            SUBDIR_SECTOR,
+            ROOT_SECTOR,
+            &[record(NOTES_SECTOR, NOTES.len() as u32, false, b"NOTES.;1")],
+        );
+        write_sector(&mut image, SUBDIR_SECTOR, &subdir);
+        write_sector(&mut image, README_SECTOR, README);
+        write_sector(&mut image, NOTES_SECTOR, NOTES);
+        image
+    }
+
+    fn volume() -> Volume<RamDisk> {
+        Volume::new(RamDisk::from_bytes(&image())).unwrap()
+    }
+
+    #[test]
+    fn reads_primary_volume_descriptor() {
+        let volume = volume();
+        let descriptor = volume.descriptor();
+        assert_eq!(descriptor.system_id, "MYTHOS");
+        assert_eq!(descriptor.volume_id, "TEST");
+        assert_eq!(descriptor.volume_space_size, NOTES_SECTOR + 1);
+        assert_eq!(volume.root().extent, ROOT_SECTOR);
+        assert!(volume.root().is_directory());
+    }
+
+    #[test]
+    fn read_dir_skips_dot_entries_and_strips_versions() {
+        let volume = volume();
+        let names: Vec<String> = volume
+            .read_dir(volume.root())
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        assert_eq!(names, ["README.TXT", "DOCS"]);
+
+        let docs = volume.find("/DOCS").unwrap();
+        assert!(docs.is_directory());
+        let notes = volume.read_dir(&docs).unwrap();
+        assert_eq!(notes.len(), 1);
+        // The trailing dot of a name without an extension is removed too.
+        assert_eq!(notes[0].name, "NOTES");
+    }
+
+    #[test]
+    fn find_and_read_files() {
+        let volume = volume();
+        let readme = volume.find("readme.txt").unwrap();
+        assert_eq!(readme.extent, README_SECTOR);
+        assert_eq!(volume.read_file("/README.TXT").unwrap(), README);
+        assert_eq!(volume.read_file("docs/notes").unwrap(), NOTES);
+    }
+
+    #[test]
+    fn missing_paths_and_wrong_kinds() {
+        let volume = volume();
+        assert!(matches!(volume.find("/NOPE"), Err(Iso9660Error::NotFound)));
+        assert!(matches!(
+            volume.find("/DOCS/README.TXT"),
+            Err(Iso9660Error::NotFound)
+        ));
+        assert!(matches!(
+            volume.read_file("/DOCS"),
+            Err(Iso9660Error::NotAFile)
+        ));
+        assert!(matches!(
+            volume.find("/README.TXT/NOTES"),
+            Err(Iso9660Error::NotADirectory)
+        ));
+    }
+
+    #[test]
+    fn no_primary_descriptor() {
+        let mut image = image();
+        image[DESCRIPTOR_START as usize * SECTOR_SIZE] = DESCRIPTOR_TERMINATOR;
+        assert!(matches!(
+            Volume::new(RamDisk::from_bytes(&image)),
+            Err(Iso9660Error::NoPrimaryVolumeDescriptor)
+        ));
+    }
+
+    struct OddBlockDevice;
+
+    impl BlockDevice for OddBlockDevice {
+        const BLOCK_SIZE: u32 = 3000;
+        type Error = ();
+        fn read(&self, _: &mut [u8], _: usize, _: usize) -> Result<(), ()> {
+            Err(())
+        }
+        fn write(&self, _: &[u8], _: usize, _: usize) -> Result<(), ()> {
+            Err(())
+        }
+    }
+
+    #[test]
+    fn unsupported_device_block_size() {
+        assert_eq!(
+            Volume::new(OddBlockDevice).err(),
+            Some(Iso9660Error::UnsupportedBlockSize { size: 3000 })
+        );
+    }
+
+    #[test]
+    fn identifier_past_record_end_is_rejected() {
+        let mut bytes = record(ROOT_SECTOR, 0, false, b"README.TXT;1");
+        bytes.resize(64, 0);
+        // The identifier length claims more bytes than the record has.
+        bytes[32] = 20;
+        assert_eq!(DirectoryRecord::from_bytes(&bytes), Err(()));
+    }
+}
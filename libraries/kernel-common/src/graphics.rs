@@ -3,6 +3,70 @@ use core::fmt::Write;
 
 pub use bootloader_api::info::PixelFormat;
 
+/// An 8-bit per channel color with straight (not premultiplied) alpha.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color::new(0x00, 0x00, 0x00);
+    pub const WHITE: Color = Color::new(0xff, 0xff, 0xff);
+    pub const RED: Color = Color::new(0xff, 0x00, 0x00);
+    pub const GREEN: Color = Color::new(0x00, 0xff, 0x00);
+    pub const BLUE: Color = Color::new(0x00, 0x00, 0xff);
+    pub const YELLOW: Color = Color::new(0xff, 0xff, 0x00);
+    pub const CYAN: Color = Color::new(0x00, 0xff, 0xff);
+    pub const MAGENTA: Color = Color::new(0xff, 0x00, 0xff);
+    pub const TRANSPARENT: Color = Color::new(0x00, 0x00, 0x00).with_alpha(0);
+
+    /// Creates an opaque color.
+    pub const fn new(r: u8, g: u8, b: u8) -> Color {
+        Color { r, g, b, a: 0xff }
+    }
+    pub const fn with_alpha(self, a: u8) -> Color {
+        Color { a, ..self }
+    }
+    /// Unpacks a color stored as `0xAARRGGBB`.
+    pub const fn from_u32(value: u32) -> Color {
+        Color {
+            r: (value >> 16) as u8,
+            g: (value >> 8) as u8,
+            b: value as u8,
+            a: (value >> 24) as u8,
+        }
+    }
+    /// Packs the color as `0xAARRGGBB`.
+    pub const fn to_u32(self) -> u32 {
+        ((self.a as u32) << 24) | ((self.r as u32) << 16) | ((self.g as u32) << 8) | self.b as u32
+    }
+    pub const fn to_tuple(self) -> (u8, u8, u8) {
+        (self.r, self.g, self.b)
+    }
+    /// Composites this color over `background`. The result has the background's alpha.
+    pub fn blend_over(self, background: Color) -> Color {
+        let alpha = self.a as u32;
+        let blend =
+            |fg: u8, bg: u8| ((fg as u32 * alpha + bg as u32 * (0xff - alpha) + 0x7f) / 0xff) as u8;
+        match self.a {
+            0xff => Color {
+                a: background.a,
+                ..self
+            },
+            0 => background,
+            _ => Color {
+                r: blend(self.r, background.r),
+                g: blend(self.g, background.g),
+                b: blend(self.b, background.b),
+                a: background.a,
+            },
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, Default)]
 pub struct Point {
     x: i32,
@@ -17,6 +81,41 @@ pub struct Rect {
     height: u32,
 }
 
+impl Point {
+    pub const fn new(x: i32, y: i32) -> Self {
+        Point { x, y }
+    }
+}
+
+impl Rect {
+    pub const fn new(x: i32, y: i32, width: u32, height: u32) -> Self {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Shrinks the rect so it lies within `0..width` and `0..height`. Returns `None` if nothing is
+    /// left.
+    fn clip(self, width: u32, height: u32) -> Option<Rect> {
+        let left = self.x.max(0) as i64;
+        let top = self.y.max(0) as i64;
+        let right = (self.x as i64 + self.width as i64).min(width as i64);
+        let bottom = (self.y as i64 + self.height as i64).min(height as i64);
+        if left >= right || top >= bottom {
+            return None;
+        }
+        Some(Rect {
+            x: left as i32,
+            y: top as i32,
+            width: (right - left) as u32,
+            height: (bottom - top) as u32,
+        })
+    }
+}
+
 pub trait Texture {
     fn width(&self) -> u32;
     fn height(&self) -> u32;
@@ -108,6 +207,19 @@ impl ImageFormat {
     }
 }
 
+const IMAGE_MAGIC: [u8; 4] = *b"MIMG";
+const IMAGE_HEADER_SIZE: usize = 20;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ImageError {
+    /// The data does not start with the `MIMG` magic, or is too short for the header.
+    InvalidMagic,
+    /// The format byte is not one of the known formats.
+    InvalidFormat(u8),
+    /// The data length in the header doesn't match the image size, or is longer than the file.
+    WrongSize { expected: usize, actual: usize },
+}
+
 pub struct Image<'a> {
     pub width: u32,
     pub height: u32,
@@ -116,6 +228,52 @@ pub struct Image<'a> {
 }
 
 impl<'a> Image<'a> {
+    /// Parses an image file. The header is 20 bytes:
+    ///
+    /// - the magic `MIMG`
+    /// - the width and height as little-endian `u16`s
+    /// - the format: 0 for RGBA, 1 for a mask
+    /// - the foreground then background color of a mask as RGB bytes, ignored for RGBA
+    /// - a reserved byte
+    /// - the data length as a little-endian `u32`
+    ///
+    /// The pixel data follows, row by row. Anything after it is ignored.
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Image<'a>, ImageError> {
+        if bytes.len() < IMAGE_HEADER_SIZE || bytes[0..4] != IMAGE_MAGIC {
+            return Err(ImageError::InvalidMagic);
+        }
+        let width = u16::from_le_bytes([bytes[4], bytes[5]]) as u32;
+        let height = u16::from_le_bytes([bytes[6], bytes[7]]) as u32;
+        let format = match bytes[8] {
+            0 => ImageFormat::Rgba,
+            1 => ImageFormat::Mask(
+                [bytes[9], bytes[10], bytes[11]],
+                [bytes[12], bytes[13], bytes[14]],
+            ),
+            format => return Err(ImageError::InvalidFormat(format)),
+        };
+        let data_len = u32::from_le_bytes([bytes[16], bytes[17], bytes[18], bytes[19]]) as usize;
+        let expected = (width * height) as usize * format.bytes_per_pixel();
+        let data = &bytes[IMAGE_HEADER_SIZE..];
+        if data_len != expected {
+            return Err(ImageError::WrongSize {
+                expected,
+                actual: data_len,
+            });
+        }
+        if data.len() < data_len {
+            return Err(ImageError::WrongSize {
+                expected,
+                actual: data.len(),
+            });
+        }
+        Ok(Image {
+            width,
+            height,
+            format,
+            data: &data[..data_len],
+        })
+    }
     pub fn alloc_and_write(&self, context: &GraphicsContext) -> VecBuffer {
         let mut texture = VecBuffer::alloc(
             context,
@@ -145,6 +303,10 @@ impl GraphicsContext {
     pub fn from_framebuffer(framebuffer: &bootloader_api::info::FrameBuffer) -> Self {
         const IMAGE_SCALE: u32 = 2;
         let info = framebuffer.info();
+        assert!(
+            (1..=4).contains(&info.bytes_per_pixel),
+            "unsupported framebuffer bytes per pixel"
+        );
         GraphicsContext {
             pixel_format: info.pixel_format,
             bytes_per_pixel: info.bytes_per_pixel,
@@ -152,6 +314,12 @@ impl GraphicsContext {
         }
     }
 
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.pixel_format
+    }
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.bytes_per_pixel
+    }
     pub fn image_scale(&self) -> u32 {
         self.image_scale
     }
@@ -159,14 +327,106 @@ impl GraphicsContext {
     fn byte_offset(&self, x: usize, y: usize, texture_stride: usize) -> isize {
         (((y * texture_stride) + x) * self.bytes_per_pixel) as isize
     }
+    /// Whether every pixel inside the texture's width and height is backed by its data, given its
+    /// stride.
+    fn texture_fits<T: Texture>(&self, texture: &T) -> bool {
+        texture.height() == 0
+            || texture.stride() >= texture.width() as usize
+                && self.byte_offset(
+                    texture.width() as usize,
+                    texture.height() as usize - 1,
+                    texture.stride(),
+                ) as usize
+                    <= texture.data().len()
+    }
+    /// Packs an opaque color into the pixel format of textures drawn with this context. Only the
+    /// low `bytes_per_pixel` bytes of the result are written; 2-byte pixels are packed as 5-6-5.
+    pub fn pack_color(&self, color: Color) -> u32 {
+        self.encode_color(color.r, color.g, color.b)
+    }
     fn encode_color(&self, r: u8, g: u8, b: u8) -> u32 {
-        match self.pixel_format {
-            PixelFormat::Rgb => (r as u32) | ((g as u32) << 8) | ((b as u32) << 16),
-            PixelFormat::Bgr => (b as u32) | ((g as u32) << 8) | ((r as u32) << 16),
-            PixelFormat::U8 => r as u32,
+        let (r, g, b) = (r as u32, g as u32, b as u32);
+        match (self.pixel_format, self.bytes_per_pixel) {
+            (PixelFormat::U8, _) => r,
+            (PixelFormat::Rgb, 2) => ((r >> 3) << 11) | ((g >> 2) << 5) | (b >> 3),
+            (PixelFormat::Bgr, 2) => ((b >> 3) << 11) | ((g >> 2) << 5) | (r >> 3),
+            (PixelFormat::Rgb, _) => r | (g << 8) | (b << 16),
+            (PixelFormat::Bgr, _) => b | (g << 8) | (r << 16),
+            (
+                PixelFormat::Unknown {
+                    red_position,
+                    green_position,
+                    blue_position,
+                },
+                _,
+            ) => {
+                let channels = self.unknown_channels([red_position, green_position, blue_position]);
+                [r, g, b]
+                    .iter()
+                    .zip(channels)
+                    .map(|(&value, (position, width))| (value >> (8 - width)) << position)
+                    .fold(0, |color, channel| color | channel)
+            }
+            _ => panic!("unknown pixel format"),
+        }
+    }
+    fn decode_color(&self, color: u32) -> [u8; 3] {
+        let expand =
+            |value: u32, bits: u32| ((value << (8 - bits)) | (value >> (2 * bits - 8))) as u8;
+        let (low, mid, high) = (color as u8, (color >> 8) as u8, (color >> 16) as u8);
+        match (self.pixel_format, self.bytes_per_pixel) {
+            (PixelFormat::U8, _) => [low, low, low],
+            (PixelFormat::Rgb, 2) => [
+                expand((color >> 11) & 0x1f, 5),
+                expand((color >> 5) & 0x3f, 6),
+                expand(color & 0x1f, 5),
+            ],
+            (PixelFormat::Bgr, 2) => [
+                expand(color & 0x1f, 5),
+                expand((color >> 5) & 0x3f, 6),
+                expand((color >> 11) & 0x1f, 5),
+            ],
+            (PixelFormat::Rgb, _) => [low, mid, high],
+            (PixelFormat::Bgr, _) => [high, mid, low],
+            (
+                PixelFormat::Unknown {
+                    red_position,
+                    green_position,
+                    blue_position,
+                },
+                _,
+            ) => self
+                .unknown_channels([red_position, green_position, blue_position])
+                .map(|(position, width)| match width {
+                    0 => 0,
+                    width => {
+                        let max = (1 << width) - 1;
+                        (((color >> position) & max) * 0xff / max) as u8
+                    }
+                }),
             _ => panic!("unknown pixel format"),
         }
     }
+    /// The bit position and width of the red, green and blue channels of an `Unknown` pixel format.
+    /// The bootloader only reports where each channel starts, so a channel is assumed to run up to
+    /// the next channel or the end of the pixel, and to be at most 8 bits wide.
+    fn unknown_channels(&self, positions: [u8; 3]) -> [(u32, u32); 3] {
+        let pixel_bits = self.bytes_per_pixel as u32 * 8;
+        positions.map(|position| {
+            let position = position as u32;
+            let end = positions
+                .iter()
+                .map(|&other| other as u32)
+                .filter(|&other| other > position)
+                .min()
+                .unwrap_or(pixel_bits);
+            match end.saturating_sub(position).min(8) {
+                // A channel that starts outside the pixel is never drawn.
+                0 => (0, 0),
+                width => (position, width),
+            }
+        })
+    }
     fn get_image_pixel(&self, image: &Image, x: u32, y: u32) -> u32 {
         let bpp = image.format.bytes_per_pixel();
         let idx = ((y * image.width) + x) as usize * bpp;
@@ -183,28 +443,96 @@ impl GraphicsContext {
         }
     }
 
-    pub fn clear<T: Texture>(&self, texture: &mut T) {
-        let data = texture.data_mut();
-        unsafe {
-            core::ptr::write_bytes(data.as_mut_ptr(), 0, data.len());
+    /// Sets every pixel of the texture to `color`, which must already be packed for this context.
+    pub fn clear<T: Texture>(&self, texture: &mut T, color: u32) {
+        if color == 0 {
+            let data = texture.data_mut();
+            unsafe {
+                core::ptr::write_bytes(data.as_mut_ptr(), 0, data.len());
+            }
+        } else {
+            let rect = Rect::new(0, 0, texture.width(), texture.height());
+            self.fill_rect(texture, rect, color);
         }
     }
+    /// Sets one pixel. Nothing is drawn if the pixel is outside the texture, or the texture's data
+    /// is too short for its size.
     pub fn set_pixel<T: Texture>(&self, texture: &mut T, x: u32, y: u32, color: u32) {
+        if x >= texture.width() || y >= texture.height() || !self.texture_fits(texture) {
+            return;
+        }
+        unsafe { self.set_pixel_unchecked(texture, x, y, color) }
+    }
+    /// ## Safety
+    /// The pixel must be inside the texture and the texture must fit its data.
+    unsafe fn set_pixel_unchecked<T: Texture>(&self, texture: &mut T, x: u32, y: u32, color: u32) {
         let src = &color as *const u32 as *const u8;
-        unsafe {
-            let dst = texture.data_mut().as_mut_ptr().offset(self.byte_offset(
-                x as usize,
-                y as usize,
-                texture.stride(),
-            ));
-            core::ptr::copy_nonoverlapping(src, dst, self.bytes_per_pixel);
+        let dst = texture.data_mut().as_mut_ptr().offset(self.byte_offset(
+            x as usize,
+            y as usize,
+            texture.stride(),
+        ));
+        core::ptr::copy_nonoverlapping(src, dst, self.bytes_per_pixel);
+    }
+    pub fn get_pixel<T: Texture>(&self, texture: &T, x: u32, y: u32) -> u32 {
+        if x >= texture.width() || y >= texture.height() {
+            return 0;
+        }
+        let offset = self.byte_offset(x as usize, y as usize, texture.stride()) as usize;
+        let mut color = [0u8; 4];
+        color[..self.bytes_per_pixel]
+            .copy_from_slice(&texture.data()[offset..offset + self.bytes_per_pixel]);
+        u32::from_le_bytes(color)
+    }
+    pub fn fill_rect<T: Texture>(&self, texture: &mut T, rect: Rect, color: u32) {
+        let rect = match rect.clip(texture.width(), texture.height()) {
+            Some(rect) => rect,
+            None => return,
+        };
+        if !self.texture_fits(texture) {
+            return;
+        }
+        if self.bytes_per_pixel == 4 && self.fill_rect_u32(texture, rect, color) {
+            return;
+        }
+        for y in rect.y..(rect.y + rect.height as i32) {
+            for x in rect.x..(rect.x + rect.width as i32) {
+                unsafe { self.set_pixel_unchecked(texture, x as u32, y as u32, color) };
+            }
         }
     }
+    /// Fills an already clipped rect of a 4-byte-per-pixel texture a whole row at a time. If the
+    /// rows are full-width they are filled as one run. Returns `false` without drawing anything if
+    /// the texture data isn't aligned for `u32` writes.
+    fn fill_rect_u32<T: Texture>(&self, texture: &mut T, rect: Rect, color: u32) -> bool {
+        let stride = texture.stride();
+        let (rows, row_pixels) = if rect.x == 0 && rect.width as usize == stride {
+            (1, stride * rect.height as usize)
+        } else {
+            (rect.height as usize, rect.width as usize)
+        };
+        let start = self.byte_offset(rect.x as usize, rect.y as usize, stride) as usize;
+        let end = start + ((rows - 1) * stride + row_pixels) * 4;
+        let (prefix, pixels, _) = unsafe { texture.data_mut()[start..end].align_to_mut::<u32>() };
+        if !prefix.is_empty() {
+            return false;
+        }
+        for row in pixels.chunks_mut(stride.max(row_pixels)).take(rows) {
+            row[..row_pixels].fill(color);
+        }
+        true
+    }
     pub fn write<S: Texture, D: Texture>(&self, source: &S, dest: &mut D, dest_offset: usize) {
         if dest.width() < source.width() || dest.height() < source.height() {
             return;
         }
         let source = source.data();
+        if dest_offset
+            .checked_add(source.len())
+            .is_none_or(|end| end > dest.data().len())
+        {
+            return;
+        }
         unsafe {
             core::ptr::copy_nonoverlapping(
                 source.as_ptr(),
@@ -213,6 +541,44 @@ impl GraphicsContext {
             );
         }
     }
+    /// Moves the pixels in `source_rect` so their top left corner is at `dest_point` in the same
+    /// texture. The source and destination may overlap. Anything that would be read from or written
+    /// to outside the texture is skipped.
+    pub fn copy_rect<T: Texture>(&self, texture: &mut T, source_rect: Rect, dest_point: Point) {
+        let (width, height) = (texture.width(), texture.height());
+        let (dx, dy) = (dest_point.x - source_rect.x, dest_point.y - source_rect.y);
+        let dest_rect = match source_rect.clip(width, height).and_then(|source| {
+            Rect {
+                x: source.x + dx,
+                y: source.y + dy,
+                ..source
+            }
+            .clip(width, height)
+        }) {
+            Some(rect) => rect,
+            None => return,
+        };
+        if !self.texture_fits(texture) {
+            return;
+        }
+
+        let row_bytes = dest_rect.width as usize * self.bytes_per_pixel;
+        let stride = texture.stride();
+        let data = texture.data_mut();
+        let mut copy_row = |row: i32| {
+            let y = dest_rect.y + row;
+            let source = self.byte_offset((dest_rect.x - dx) as usize, (y - dy) as usize, stride);
+            let dest = self.byte_offset(dest_rect.x as usize, y as usize, stride);
+            let source = source as usize;
+            data.copy_within(source..source + row_bytes, dest as usize);
+        };
+        // Copy rows in the order that never overwrites a row before it has been read.
+        if dy > 0 {
+            (0..dest_rect.height as i32).rev().for_each(&mut copy_row);
+        } else {
+            (0..dest_rect.height as i32).for_each(&mut copy_row);
+        }
+    }
     pub fn blit<S: Texture, D: Texture>(
         &self,
         source: &S,
@@ -236,12 +602,18 @@ impl GraphicsContext {
                 .unwrap_or(0);
             dest_point.y = 0;
         }
-        // TODO also clamp dest_point on the positive side
-        if source_rect.x < 0
-            || source_rect.y < 0
-            || source_rect.width == 0
-            || source_rect.height == 0
-        {
+        if dest_point.x >= dest.width() as i32 || dest_point.y >= dest.height() as i32 {
+            return;
+        }
+        source_rect.width = source_rect.width.min(dest.width() - dest_point.x as u32);
+        source_rect.height = source_rect.height.min(dest.height() - dest_point.y as u32);
+        // The source rect must lie entirely within the source, otherwise source and destination
+        // pixels would no longer line up.
+        let source_rect = match source_rect.clip(source.width(), source.height()) {
+            Some(clipped) if clipped.x == source_rect.x && clipped.y == source_rect.y => clipped,
+            _ => return,
+        };
+        if !self.texture_fits(source) || !self.texture_fits(dest) {
             return;
         }
 
@@ -265,47 +637,226 @@ impl GraphicsContext {
         }
     }
 
+    /// Draws an RGBA image at `dest_point`, scaled by the image scale. Pixels with an alpha of 0 are
+    /// skipped and partially transparent pixels are blended over the existing contents of `dest`.
+    /// Mask images are drawn opaque.
+    pub fn blit_rgba<T: Texture>(&self, image: &Image, dest: &mut T, dest_point: Point) {
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let color = match image.format {
+                    ImageFormat::Rgba => {
+                        let idx = ((y * image.width) + x) as usize * 4;
+                        let pixel = &image.data[idx..idx + 4];
+                        Color::new(pixel[0], pixel[1], pixel[2]).with_alpha(pixel[3])
+                    }
+                    ImageFormat::Mask(_, _) => {
+                        let [r, g, b] = self.decode_color(self.get_image_pixel(image, x, y));
+                        Color::new(r, g, b)
+                    }
+                };
+                if color.a == 0 {
+                    continue;
+                }
+                for by in 0..self.image_scale {
+                    for bx in 0..self.image_scale {
+                        let dest_x = dest_point.x + (x * self.image_scale + bx) as i32;
+                        let dest_y = dest_point.y + (y * self.image_scale + by) as i32;
+                        if dest_x < 0 || dest_y < 0 {
+                            continue;
+                        }
+                        let (dest_x, dest_y) = (dest_x as u32, dest_y as u32);
+                        let [r, g, b] = self.decode_color(self.get_pixel(dest, dest_x, dest_y));
+                        let blended = color.blend_over(Color::new(r, g, b));
+                        let packed = self.encode_color(blended.r, blended.g, blended.b);
+                        self.set_pixel(dest, dest_x, dest_y, packed);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draws an image opaque at `dest_point`, scaled by the image scale.
+    pub fn draw_image<T: Texture>(&self, image: &Image, dest: &mut T, dest_point: Point) {
+        self.draw_image_scaled(image, dest, dest_point, self.image_scale);
+    }
+    /// Draws an image opaque at `dest_point`, with every image pixel drawn as a `scale` by `scale`
+    /// block. Alpha is ignored. Anything outside `dest` is clipped.
+    pub fn draw_image_scaled<T: Texture>(
+        &self,
+        image: &Image,
+        dest: &mut T,
+        dest_point: Point,
+        scale: u32,
+    ) {
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let rect = Rect::new(
+                    dest_point.x + (x * scale) as i32,
+                    dest_point.y + (y * scale) as i32,
+                    scale,
+                    scale,
+                );
+                self.fill_rect(dest, rect, self.get_image_pixel(image, x, y));
+            }
+        }
+    }
+    /// Draws one character of `font` at `dest_point`, scaled by the image scale. Set font pixels are
+    /// drawn in `color` and the rest in `background`; both must already be packed. Bytes outside
+    /// printable ASCII are drawn as `?`.
+    pub fn draw_font_char<T: Texture>(
+        &self,
+        font: &FontData,
+        ch: u8,
+        dest: &mut T,
+        dest_point: Point,
+        color: u32,
+        background: u32,
+    ) {
+        let index = match ch {
+            0x20..=0x7e => ch - 0x20,
+            _ => b'?' - 0x20,
+        } as u32;
+        let cols = font.width / font.char_width;
+        let source_x = (index % cols) * font.char_width;
+        let source_y = (index / cols) * font.char_height;
+        for y in 0..font.char_height {
+            for x in 0..font.char_width {
+                let offset = ((source_y + y) * font.width + source_x + x) as usize;
+                let set = font.data.get(offset).is_some_and(|&pixel| pixel != 0);
+                let rect = Rect::new(
+                    dest_point.x + (x * self.image_scale) as i32,
+                    dest_point.y + (y * self.image_scale) as i32,
+                    self.image_scale,
+                    self.image_scale,
+                );
+                self.fill_rect(dest, rect, if set { color } else { background });
+            }
+        }
+    }
     pub fn write_image_to_texture<T: Texture>(&self, source: &Image, dest: &mut T) {
         if dest.width() < source.width * self.image_scale
             || dest.height() < source.height * self.image_scale
         {
             panic!("texture too small");
         }
-        for y in 0..source.height {
-            for x in 0..source.width {
-                let color = self.get_image_pixel(source, x, y);
-                for bx in 0..self.image_scale {
-                    for by in 0..self.image_scale {
-                        self.set_pixel(
-                            dest,
-                            (x * self.image_scale) + bx,
-                            (y * self.image_scale) + by,
-                            color,
-                        );
-                    }
-                }
-            }
-        }
+        self.draw_image(source, dest, Point::new(0, 0));
     }
 }
 
 const FONT_TEXTURE_SIZE: usize = 128 * 2 * 64 * 2 * 4;
 
-struct Font {
-    texture: Buffer<[u8; FONT_TEXTURE_SIZE]>,
+const FONT_MAGIC: [u8; 4] = *b"MFNT";
+const FONT_HEADER_SIZE: usize = 8;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FontError {
+    /// The data does not start with the `MFNT` magic.
+    InvalidMagic,
+    /// The character size is zero or larger than the image.
+    InvalidCharSize,
+    /// The data is not a whole number of `width` pixel rows.
+    WrongSize { expected: usize, actual: usize },
+}
+
+/// A monospace bitmap font: an image with one byte per pixel, where any nonzero byte is set,
+/// holding the printable ASCII characters left to right and top to bottom.
+#[derive(Copy, Clone, Debug)]
+pub struct FontData<'a> {
+    width: u32,
+    height: u32,
+    char_width: u32,
+    char_height: u32,
+    data: &'a [u8],
+}
+
+impl<'a> FontData<'a> {
+    /// Creates a font from raw bitmap data that is `width` pixels wide with characters of
+    /// `char_size` (width, height).
+    pub fn from_bytes(
+        data: &'a [u8],
+        width: u32,
+        char_size: (u32, u32),
+    ) -> Result<FontData<'a>, FontError> {
+        let (char_width, char_height) = char_size;
+        if char_width == 0 || char_height == 0 || char_width > width {
+            return Err(FontError::InvalidCharSize);
+        }
+        let rows = data.len() / width as usize;
+        if rows * width as usize != data.len() {
+            return Err(FontError::WrongSize {
+                expected: rows * width as usize,
+                actual: data.len(),
+            });
+        }
+        if rows < char_height as usize {
+            return Err(FontError::InvalidCharSize);
+        }
+        Ok(FontData {
+            width,
+            height: rows as u32,
+            char_width,
+            char_height,
+            data,
+        })
+    }
+    /// Parses a font file: the magic `MFNT`, the image width as a little-endian `u16`, the
+    /// character width and height as one byte each, then the bitmap data.
+    pub fn parse(bytes: &'a [u8]) -> Result<FontData<'a>, FontError> {
+        if bytes.len() < FONT_HEADER_SIZE || bytes[0..4] != FONT_MAGIC {
+            return Err(FontError::InvalidMagic);
+        }
+        let width = u16::from_le_bytes([bytes[4], bytes[5]]) as u32;
+        let char_size = (bytes[6] as u32, bytes[7] as u32);
+        FontData::from_bytes(&bytes[FONT_HEADER_SIZE..], width, char_size)
+    }
+    /// The font that is built into the kernel.
+    pub fn system() -> FontData<'static> {
+        FontData::from_bytes(include_bytes!("font.data"), 128, (7, 9)).unwrap()
+    }
+
+    pub fn char_size(&self) -> (u32, u32) {
+        (self.char_width, self.char_height)
+    }
+
+    fn image(&self, color: [u8; 3]) -> Image<'a> {
+        Image {
+            width: self.width,
+            height: self.height,
+            format: ImageFormat::Mask(color, [0, 0, 0]),
+            data: self.data,
+        }
+    }
+}
+
+/// A font rendered in one color, ready to be drawn with a `TextWriter`.
+pub struct Font<T: Texture = VecBuffer> {
+    texture: T,
     char_width: u32,
     char_height: u32,
 }
 
-impl Font {
-    fn load(&mut self, context: &GraphicsContext, image: &Image) {
-        context.write_image_to_texture(image, &mut self.texture);
+impl Font<VecBuffer> {
+    pub fn new(context: &GraphicsContext, font: &FontData, color: [u8; 3]) -> Self {
+        Font {
+            texture: font.image(color).alloc_and_write(context),
+            char_width: font.char_width * context.image_scale,
+            char_height: font.char_height * context.image_scale,
+        }
     }
-    fn draw_char<T: Texture>(
+}
+
+impl<T: Texture> Font<T> {
+    pub fn char_width(&self) -> u32 {
+        self.char_width
+    }
+    pub fn char_height(&self) -> u32 {
+        self.char_height
+    }
+    fn draw_char<D: Texture>(
         &self,
         context: &GraphicsContext,
         char_index: u32,
-        dest: &mut T,
+        dest: &mut D,
         dest_point: Point,
     ) {
         let cols = self.texture.width() / self.char_width;
@@ -321,7 +872,10 @@ impl Font {
     }
 }
 
-static mut SYSTEM_FONT: Font = Font {
+type SystemFontTexture = Buffer<[u8; FONT_TEXTURE_SIZE]>;
+
+// The system font has a fixed-size texture so it can be used before there is a heap.
+static mut SYSTEM_FONT: Font<SystemFontTexture> = Font {
     texture: Buffer {
         width: 128 * 2,
         height: 64 * 2,
@@ -333,20 +887,16 @@ static mut SYSTEM_FONT: Font = Font {
 };
 
 pub fn load_system_font(context: &GraphicsContext, color: [u8; 3]) {
-    let image = Image {
-        width: 128,
-        height: 64,
-        format: ImageFormat::Mask(color, [0, 0, 0]),
-        data: include_bytes!("font.data"),
-    };
+    let image = FontData::system().image(color);
     unsafe {
-        SYSTEM_FONT.load(context, &image);
+        context.write_image_to_texture(&image, &mut SYSTEM_FONT.texture);
     }
 }
 
-pub struct TextWriter<'a, T: Texture> {
+pub struct TextWriter<'a, T: Texture, F: Texture = SystemFontTexture> {
     context: &'a GraphicsContext,
     texture: &'a mut T,
+    font: &'a Font<F>,
     start_x: i32,
     wrap_x: i32,
     x: i32,
@@ -354,11 +904,25 @@ pub struct TextWriter<'a, T: Texture> {
 }
 
 impl<'a, T: Texture> TextWriter<'a, T> {
+    /// Creates a writer that draws with the system font.
     pub fn new(context: &'a GraphicsContext, texture: &'a mut T, x: i32, y: i32) -> Self {
+        TextWriter::with_font(context, texture, unsafe { &SYSTEM_FONT }, x, y)
+    }
+}
+
+impl<'a, T: Texture, F: Texture> TextWriter<'a, T, F> {
+    pub fn with_font(
+        context: &'a GraphicsContext,
+        texture: &'a mut T,
+        font: &'a Font<F>,
+        x: i32,
+        y: i32,
+    ) -> Self {
         let wrap_x = texture.width() as i32;
         TextWriter {
             context,
             texture,
+            font,
             start_x: x,
             wrap_x,
             x,
@@ -366,14 +930,14 @@ impl<'a, T: Texture> TextWriter<'a, T> {
         }
     }
     pub fn center_x(&mut self, width: u32, chars: usize) {
-        let string_width = chars as u32 * unsafe { SYSTEM_FONT.char_width };
+        let string_width = chars as u32 * self.font.char_width;
         self.start_x = (width as i32 / 2) - (string_width as i32 / 2);
         self.x = self.start_x;
     }
 
     fn write_byte(&mut self, byte: u8) {
-        let char_width = unsafe { SYSTEM_FONT.char_width as i32 };
-        let char_height = unsafe { SYSTEM_FONT.char_height as i32 };
+        let char_width = self.font.char_width as i32;
+        let char_height = self.font.char_height as i32;
         match byte {
             b'\n' => {
                 self.x = self.start_x;
@@ -384,24 +948,22 @@ impl<'a, T: Texture> TextWriter<'a, T> {
                     self.x = self.start_x;
                     self.y += char_height;
                 }
-                unsafe {
-                    SYSTEM_FONT.draw_char(
-                        self.context,
-                        (byte - 0x20) as u32,
-                        self.texture,
-                        Point {
-                            x: self.x,
-                            y: self.y,
-                        },
-                    );
-                }
+                self.font.draw_char(
+                    self.context,
+                    (byte - 0x20) as u32,
+                    self.texture,
+                    Point {
+                        x: self.x,
+                        y: self.y,
+                    },
+                );
                 self.x += char_width;
             }
         }
     }
 }
 
-impl<'a, T: Texture> Write for TextWriter<'a, T> {
+impl<'a, T: Texture, F: Texture> Write for TextWriter<'a, T, F> {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
         for byte in s.bytes() {
             match byte {
@@ -458,15 +1020,8 @@ impl<'a, T: Texture> Write for TextWriter<'a, T> {
 //     }
 
 //     fn draw_tile(&mut self, context: &GraphicsContext, level: &Level, x: u32, y: u32) {
-//         let dest_x = (x * self.tile_size) as i32 + level.scroll_x();
-//         let dest_y = (y * self.tile_size) as i32 + level.scroll_y();
-//         if dest_x < 0
-//             || dest_x >= self.texture.width() as i32
-//             || dest_y < 0
-//             || dest_y >= self.texture.height() as i32
-//         {
-//             return;
-//         }
+//         let dest_x = (x * self.tile_size) as i32 - level.scroll_x();
+//         let dest_y = (y * self.tile_size) as i32 - level.scroll_y();
 //         let tile = level.get_foreground_tile(x, y) as u32;
 //         if tile > 0 {
 //             let source_rect = Rect {
@@ -505,7 +1060,8 @@ impl<'a, T: Texture> Write for TextWriter<'a, T> {
 //             );
 //         }
 //     }
-//     fn draw_object(&mut self, context: &GraphicsContext, object: &Object) {
+//     fn draw_object(&mut self, context: &GraphicsContext, level: &Level, object: &Object) {
+//         let (scroll_x, scroll_y) = (level.scroll_x(), level.scroll_y());
 //         match object.draw {
 //             ObjectDraw::Hidden => (),
 //             ObjectDraw::Text(_) => todo!(),
@@ -518,8 +1074,8 @@ impl<'a, T: Texture> Write for TextWriter<'a, T> {
 //                     height: object.height,
 //                 };
 //                 let dest_point = Point {
-//                     x: object.pixel_x(),
-//                     y: object.pixel_y(),
+//                     x: object.pixel_x() - scroll_x,
+//                     y: object.pixel_y() - scroll_y,
 //                 };
 //                 context.blit(image, source_rect, &mut self.texture, dest_point);
 //             }
@@ -534,13 +1090,360 @@ impl<'a, T: Texture> Write for TextWriter<'a, T> {
 //                 (y as usize) * stride,
 //             );
 //         }
-//         for y in 0..level.height() {
-//             for x in 0..level.width() {
-//                 self.draw_tile(context, level, x as u32, y as u32);
+//         for object in level.objects().filter(|object| object.layer < 0) {
+//             self.draw_object(context, level, object);
+//         }
+//         let (columns, rows) = level.visible_tiles(self.texture.width(), self.texture.height());
+//         for y in rows {
+//             for x in columns.clone() {
+//                 self.draw_tile(context, level, x, y);
 //             }
 //         }
-//         for object in level.objects() {
-//             self.draw_object(context, object);
+//         for object in level.objects().filter(|object| object.layer >= 0) {
+//             self.draw_object(context, level, object);
 //         }
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{vec, vec::Vec};
+
+    fn context(pixel_format: PixelFormat, bytes_per_pixel: usize) -> GraphicsContext {
+        GraphicsContext {
+            pixel_format,
+            bytes_per_pixel,
+            image_scale: 1,
+        }
+    }
+
+    /// A 4-byte-per-pixel texture whose row stride is wider than its width, with every pixel set
+    /// to a different value.
+    fn numbered_texture(width: u32, height: u32, stride: usize) -> VecBuffer {
+        let data = (0..(stride * height as usize) as u32)
+            .flat_map(|pixel| pixel.to_le_bytes())
+            .collect();
+        Buffer {
+            width,
+            height,
+            stride,
+            data,
+        }
+    }
+
+    fn pixel(context: &GraphicsContext, texture: &VecBuffer, x: u32, y: u32) -> u32 {
+        context.get_pixel(texture, x, y)
+    }
+
+    fn pixel_at_stride(texture: &VecBuffer, x: u32, y: u32, stride: usize) -> u32 {
+        let offset = (y as usize * stride + x as usize) * 4;
+        u32::from_le_bytes(texture.data()[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn copy_rect_scrolls_up() {
+        let context = context(PixelFormat::Rgb, 4);
+        let original = numbered_texture(4, 6, 5);
+        let mut texture = numbered_texture(4, 6, 5);
+        context.copy_rect(&mut texture, Rect::new(0, 1, 4, 5), Point::new(0, 0));
+        for y in 0..6 {
+            for x in 0..4 {
+                let source_y = (y + 1).min(5);
+                assert_eq!(
+                    pixel(&context, &texture, x, y),
+                    pixel(&context, &original, x, source_y)
+                );
+            }
+            // The padding at the end of each row is left alone.
+            assert_eq!(pixel_at_stride(&texture, 4, y, 5), y * 5 + 4);
+        }
+    }
+
+    #[test]
+    fn copy_rect_scrolls_down_and_right() {
+        let context = context(PixelFormat::Rgb, 4);
+        let original = numbered_texture(4, 6, 5);
+        let mut texture = numbered_texture(4, 6, 5);
+        context.copy_rect(&mut texture, Rect::new(0, 0, 4, 6), Point::new(1, 2));
+        for y in 0..6 {
+            for x in 0..4 {
+                let expected = if x >= 1 && y >= 2 {
+                    pixel(&context, &original, x - 1, y - 2)
+                } else {
+                    pixel(&context, &original, x, y)
+                };
+                assert_eq!(pixel(&context, &texture, x, y), expected);
+            }
+        }
+    }
+
+    /// Checks that exactly the pixels inside `inside` were set to `color`, and that every other
+    /// pixel and the padding after each row are unchanged.
+    fn assert_filled(texture: &VecBuffer, original: &VecBuffer, inside: Rect, color: u32) {
+        let stride = texture.stride();
+        for y in 0..texture.height() as i32 {
+            for x in 0..stride as i32 {
+                let filled = x >= inside.x
+                    && x < inside.x + inside.width as i32
+                    && y >= inside.y
+                    && y < inside.y + inside.height as i32;
+                let expected = if filled {
+                    color
+                } else {
+                    pixel_at_stride(original, x as u32, y as u32, stride)
+                };
+                assert_eq!(
+                    pixel_at_stride(texture, x as u32, y as u32, stride),
+                    expected,
+                    "pixel ({}, {})",
+                    x,
+                    y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn fill_rect_clips_to_right_edge() {
+        let context = context(PixelFormat::Rgb, 4);
+        let original = numbered_texture(6, 4, 8);
+        let mut texture = numbered_texture(6, 4, 8);
+        context.fill_rect(&mut texture, Rect::new(6 - 2, 1, 10, 2), 0xdead_beef);
+        assert_filled(&texture, &original, Rect::new(4, 1, 2, 2), 0xdead_beef);
+    }
+
+    #[test]
+    fn fill_rect_clips_negative_origin() {
+        let context = context(PixelFormat::Rgb, 4);
+        let original = numbered_texture(6, 4, 8);
+        let mut texture = numbered_texture(6, 4, 8);
+        context.fill_rect(&mut texture, Rect::new(-3, -1, 5, 20), 0xdead_beef);
+        assert_filled(&texture, &original, Rect::new(0, 0, 2, 4), 0xdead_beef);
+    }
+
+    #[test]
+    fn fill_rect_clips_3_byte_pixels() {
+        let context = context(PixelFormat::Rgb, 3);
+        let (width, height, stride) = (6, 3, 8);
+        let mut texture = Buffer {
+            width,
+            height,
+            stride,
+            data: vec![0xaa; stride * height as usize * 3],
+        };
+        context.fill_rect(
+            &mut texture,
+            Rect::new(width as i32 - 2, 0, 10, 2),
+            0x11_2233,
+        );
+        for y in 0..height as usize {
+            for x in 0..stride {
+                let offset = (y * stride + x) * 3;
+                let expected: &[u8] = if (4..6).contains(&x) && y < 2 {
+                    &[0x33, 0x22, 0x11]
+                } else {
+                    &[0xaa; 3]
+                };
+                assert_eq!(
+                    &texture.data()[offset..offset + 3],
+                    expected,
+                    "pixel ({}, {})",
+                    x,
+                    y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn too_short_texture_is_not_drawn() {
+        let context = context(PixelFormat::Rgb, 4);
+        // One byte short of the last pixel.
+        let mut texture = Buffer {
+            width: 4,
+            height: 4,
+            stride: 4,
+            data: vec![0u8; 4 * 4 * 4 - 1],
+        };
+        assert!(!context.texture_fits(&texture));
+        context.fill_rect(&mut texture, Rect::new(0, 0, 4, 4), 0xffff_ffff);
+        context.set_pixel(&mut texture, 3, 3, 0xffff_ffff);
+        assert!(texture.data().iter().all(|byte| *byte == 0));
+
+        // A stride narrower than the width doesn't fit either.
+        let texture = Buffer {
+            width: 4,
+            height: 2,
+            stride: 3,
+            data: vec![0u8; 4 * 2 * 4],
+        };
+        assert!(!context.texture_fits(&texture));
+        assert!(context.texture_fits(&numbered_texture(4, 2, 5)));
+    }
+
+    #[test]
+    fn pack_color_masked_565() {
+        let context = context(
+            PixelFormat::Unknown {
+                red_position: 11,
+                green_position: 5,
+                blue_position: 0,
+            },
+            2,
+        );
+        assert_eq!(context.pack_color(Color::RED), 0xf800);
+        assert_eq!(context.pack_color(Color::GREEN), 0x07e0);
+        assert_eq!(context.pack_color(Color::BLUE), 0x001f);
+        assert_eq!(context.pack_color(Color::WHITE), 0xffff);
+        assert_eq!(context.decode_color(0xf800), [0xff, 0, 0]);
+    }
+
+    #[test]
+    fn pack_color_masked_32bit() {
+        // Channels in the high bytes, as some firmware reports for xRGB-like layouts.
+        let context = context(
+            PixelFormat::Unknown {
+                red_position: 8,
+                green_position: 16,
+                blue_position: 24,
+            },
+            4,
+        );
+        let color = Color::new(0x12, 0x34, 0x56);
+        assert_eq!(context.pack_color(color), 0x5634_1200);
+        assert_eq!(context.decode_color(0x5634_1200), [0x12, 0x34, 0x56]);
+    }
+
+    #[test]
+    fn pack_color_masked_channel_outside_pixel() {
+        let context = context(
+            PixelFormat::Unknown {
+                red_position: 0,
+                green_position: 8,
+                blue_position: 24,
+            },
+            2,
+        );
+        assert_eq!(context.pack_color(Color::WHITE), 0xffff);
+        assert_eq!(context.pack_color(Color::BLUE), 0);
+    }
+
+    #[test]
+    fn draw_image_scaled_doubles_pixels() {
+        let context = context(PixelFormat::Rgb, 4);
+        #[rustfmt::skip]
+        let data = [
+            0x10, 0, 0, 0xff,  0x20, 0, 0, 0xff,
+            0x30, 0, 0, 0xff,  0x40, 0, 0, 0xff,
+        ];
+        let image = Image {
+            width: 2,
+            height: 2,
+            format: ImageFormat::Rgba,
+            data: &data,
+        };
+        let mut texture = Buffer {
+            width: 5,
+            height: 5,
+            stride: 5,
+            data: alloc::vec![0; 5 * 5 * 4],
+        };
+        context.draw_image_scaled(&image, &mut texture, Point::new(0, 0), 2);
+        for y in 0..5 {
+            for x in 0..5 {
+                let expected = if x < 4 && y < 4 {
+                    0x10 * (1 + x / 2 + (y / 2) * 2)
+                } else {
+                    0
+                };
+                assert_eq!(pixel(&context, &texture, x, y), expected, "({}, {})", x, y);
+            }
+        }
+    }
+
+    /// An image file with the given header fields, followed by `payload`.
+    fn image_file(width: u16, height: u16, format: u8, data_len: u32, payload: &[u8]) -> Vec<u8> {
+        let mut file = Vec::from(IMAGE_MAGIC);
+        file.extend(width.to_le_bytes());
+        file.extend(height.to_le_bytes());
+        file.push(format);
+        file.extend([1, 2, 3, 4, 5, 6, 0]);
+        file.extend(data_len.to_le_bytes());
+        file.extend(payload);
+        file
+    }
+
+    #[test]
+    fn image_rgba() {
+        let pixels: Vec<u8> = (0..2 * 3 * 4).collect();
+        // Bytes after the pixel data are ignored.
+        let mut file = image_file(2, 3, 0, pixels.len() as u32, &pixels);
+        file.extend([0xff; 5]);
+        let image = Image::from_bytes(&file).unwrap();
+        assert_eq!((image.width, image.height), (2, 3));
+        assert!(matches!(image.format, ImageFormat::Rgba));
+        assert_eq!(image.data, &pixels[..]);
+    }
+
+    #[test]
+    fn image_mask() {
+        let pixels = [0, 1, 1, 0];
+        let file = image_file(2, 2, 1, 4, &pixels);
+        let image = Image::from_bytes(&file).unwrap();
+        assert!(matches!(
+            image.format,
+            ImageFormat::Mask([1, 2, 3], [4, 5, 6])
+        ));
+        assert_eq!(image.data, &pixels[..]);
+    }
+
+    #[test]
+    fn image_bad_magic() {
+        let mut file = image_file(1, 1, 1, 1, &[0]);
+        file[0] = b'X';
+        assert_eq!(
+            Image::from_bytes(&file).err(),
+            Some(ImageError::InvalidMagic)
+        );
+        // Too short to hold the header.
+        assert_eq!(
+            Image::from_bytes(&IMAGE_MAGIC).err(),
+            Some(ImageError::InvalidMagic)
+        );
+    }
+
+    #[test]
+    fn image_unknown_format() {
+        let file = image_file(1, 1, 7, 1, &[0]);
+        assert_eq!(
+            Image::from_bytes(&file).err(),
+            Some(ImageError::InvalidFormat(7))
+        );
+    }
+
+    #[test]
+    fn image_data_len_mismatch() {
+        // A 2x2 RGBA image needs 16 bytes, not 4.
+        let file = image_file(2, 2, 0, 4, &[0; 16]);
+        assert_eq!(
+            Image::from_bytes(&file).err(),
+            Some(ImageError::WrongSize {
+                expected: 16,
+                actual: 4
+            })
+        );
+    }
+
+    #[test]
+    fn image_truncated_payload() {
+        let file = image_file(2, 2, 0, 16, &[0; 10]);
+        assert_eq!(
+            Image::from_bytes(&file).err(),
+            Some(ImageError::WrongSize {
+                expected: 16,
+                actual: 10
+            })
+        );
+    }
+}
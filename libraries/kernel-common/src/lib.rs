@@ -2,20 +2,122 @@
 extern crate alloc;
 
 pub mod graphics;
+pub mod screen;
+pub mod time;
 
-pub struct Syscall;
+pub use graphics::Color;
+
+/// A key press decoded by the kernel's keyboard driver.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Key {
+    /// A key that produces a character, after applying modifiers.
+    Char(char),
+    /// Any other key, identified by its `pc_keyboard::KeyCode` discriminant.
+    Raw(u8),
+}
+
+/// The mouse cursor position in screen pixels, and which buttons are held.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MouseState {
+    pub x: i32,
+    pub y: i32,
+    /// Bit 0 is the left button, bit 1 the right button and bit 2 the middle button.
+    pub buttons: u8,
+}
+
+/// Resource usage of the running program.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProgramStats {
+    /// Timer ticks since the program started, not counting ticks spent sleeping.
+    pub cpu_ticks: u64,
+    /// Bytes currently allocated on the program's heap.
+    pub heap_used: usize,
+    /// The most bytes that were allocated on the program's heap at once.
+    pub peak_heap_used: usize,
+}
+
+/// Why a syscall failed. Syscalls that return a pointer report `OutOfMemory` as a null pointer
+/// instead.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u64)]
+pub enum UserError {
+    /// An argument was out of range, or pointed outside the program's memory.
+    InvalidValue = 1,
+    /// The program's heap is full and can't grow any further.
+    OutOfMemory = 2,
+    /// The syscall needs a screen but the program hasn't created one.
+    MissingScreen = 3,
+}
+
+impl UserError {
+    /// Converts the value returned by a syscall that returns 0 on success or a `UserError` code.
+    /// Unknown codes are reported as `InvalidValue`.
+    pub fn check(code: u64) -> Result<(), UserError> {
+        match code {
+            0 => Ok(()),
+            2 => Err(UserError::OutOfMemory),
+            3 => Err(UserError::MissingScreen),
+            _ => Err(UserError::InvalidValue),
+        }
+    }
+}
+
+/// The ID of a syscall. Userspace passes `id * 8` in `rax`, which the kernel uses as a byte offset
+/// into its table of syscall functions.
+///
+/// Syscalls that return a string or a struct (the `Info*` syscalls, `ProgramReadMouse` and
+/// `ProgramStats`) take a pointer to write it to as their first argument. They return 0, or a
+/// `UserError` code if the pointer is not aligned or not in the program's memory. The string
+/// syscalls copy the string onto the program's heap, and return `OutOfMemory` if it is full.
+///
+/// The `Screen*` syscalls also return 0 or a `UserError` code. Drawing on a screen the program
+/// hasn't created, or one of the other kind, gives `MissingScreen`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u64)]
+pub enum Syscall {
+    InfoOsName = 1,
+    InfoOsVersion,
+    InfoBootloaderVersion,
+    InfoFramebuffer,
+    InfoGraphicsCtx,
+    MemAlloc,
+    MemDealloc,
+    MemAllocZeroed,
+    /// Takes `(ptr, layout, new_size)` like `GlobalAlloc::realloc`. The layout uses two registers,
+    /// so `new_size` is the fourth argument, in `rcx`. `syscall` overwrites `rcx`, so the stub
+    /// pushes it and the kernel pops it back before calling the handler.
+    MemRealloc,
+    ProgramPanic,
+    ProgramPrint,
+    ProgramReadKey,
+    ProgramTimeTicks,
+    ProgramSleepTicks,
+    ProgramExit,
+    ProgramShutdown,
+    ProgramReboot,
+    ProgramReadMouse,
+    ProgramRandom,
+    ProgramStats,
+    /// Waits for space or enter. The argument is a timeout in timer ticks, or 0 to wait forever.
+    /// Returns 1 if a key was pressed and 0 if the timeout ran out first.
+    ProgramWaitForConfirm,
+    /// Waits for an IRQ from an ATA bus. Takes `(bus, timeout_ticks)` and returns 1 if the bus
+    /// raised its IRQ, or 0 if the timeout ran out first or `bus` isn't 0 or 1. An IRQ that arrived
+    /// before the call counts, so a timeout of 0 just clears a pending one.
+    ProgramWaitAta,
+    /// Replaces the program's screen. The argument is 1 for an image screen or 0 for a text screen.
+    ScreenCreate,
+    ScreenSetChar,
+    ScreenSetPixel,
+    /// Takes `(x, y, width, height, pixels)`, where `pixels` is a `&[Color]` of `width * height`
+    /// pixels. The slice uses the fifth and sixth registers.
+    ScreenBlit,
+    ScreenPresent,
+    ScreenClear,
+    ScreenScroll,
+}
 
 impl Syscall {
-    pub const INFO_OS_NAME: usize = 1;
-    pub const INFO_OS_VERSION: usize = 2;
-    pub const INFO_BOOTLOADER_VERSION: usize = 3;
-    pub const INFO_FRAMEBUFFER: usize = 4;
-    pub const INFO_GRAPHICS_CTX: usize = 5;
-    pub const MEM_ALLOC: usize = 6;
-    pub const MEM_DEALLOC: usize = 7;
-    pub const MEM_ALLOC_ZEROED: usize = 8;
-    pub const MEM_REALLOC: usize = 9;
-    pub const PROGRAM_PANIC: usize = 10;
-
-    pub const NUM_SYSCALLS: usize = 11;
+    /// The size of the syscall table. ID 0 is never used.
+    pub const NUM_SYSCALLS: usize = Syscall::ScreenScroll as usize + 1;
 }
@@ -0,0 +1,582 @@
+use crate::graphics::{Color, FontData, GraphicsContext, Point, Rect, Texture};
+use alloc::{vec, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// A screen a program draws to through the `Screen*` syscalls. A screen keeps its own copy of what
+/// it shows, so it can be redrawn when it becomes active again. Only the active screen draws to
+/// the display, which is passed to every call that might draw.
+pub trait Screen {
+    fn set_active<T: Texture>(&mut self, context: &GraphicsContext, display: &mut T, active: bool);
+    fn draw_full<T: Texture>(&self, context: &GraphicsContext, display: &mut T);
+}
+
+/// An index into a `Palette`. Always less than `Palette::SIZE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PaletteColor(u8);
+
+impl PaletteColor {
+    /// Only the low 4 bits of `idx` are used, so every value is a valid palette index.
+    pub fn new(idx: u8) -> PaletteColor {
+        PaletteColor(idx & (Palette::SIZE as u8 - 1))
+    }
+}
+
+pub struct Palette {
+    colors: [u32; Palette::SIZE],
+}
+
+impl Palette {
+    pub const SIZE: usize = 16;
+
+    pub const fn new() -> Palette {
+        Palette {
+            colors: [0; Palette::SIZE],
+        }
+    }
+    pub fn set_color(&mut self, color: PaletteColor, value: u32) {
+        self.colors[color.0 as usize] = value;
+    }
+    /// Looks up a raw palette index, as stored in a text screen cell. Only the low 4 bits are
+    /// used.
+    fn get(&self, index: u8) -> u32 {
+        self.colors[index as usize % Palette::SIZE]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::new()
+    }
+}
+
+const COLOR_BLACK: u32 = 0;
+
+/// The standard 16-color console palette, in palette index order.
+const CONSOLE_COLORS: [(u8, u8, u8); Palette::SIZE] = [
+    (0x00, 0x00, 0x00), // black
+    (0xaa, 0x00, 0x00), // red
+    (0x00, 0xaa, 0x00), // green
+    (0xaa, 0x55, 0x00), // yellow
+    (0x00, 0x00, 0xaa), // blue
+    (0xaa, 0x00, 0xaa), // magenta
+    (0x00, 0xaa, 0xaa), // cyan
+    (0xaa, 0xaa, 0xaa), // white
+    (0x55, 0x55, 0x55), // bright black
+    (0xff, 0x55, 0x55), // bright red
+    (0x55, 0xff, 0x55), // bright green
+    (0xff, 0xff, 0x55), // bright yellow
+    (0x55, 0x55, 0xff), // bright blue
+    (0xff, 0x55, 0xff), // bright magenta
+    (0x55, 0xff, 0xff), // bright cyan
+    (0xff, 0xff, 0xff), // bright white
+];
+
+/// Builds the palette used for program text screens. Colors are packed by the graphics context so
+/// they are correct for both RGB and BGR formats.
+pub fn make_user_text_palette(context: &GraphicsContext) -> Palette {
+    let mut palette = Palette::new();
+    for (index, (r, g, b)) in CONSOLE_COLORS.iter().enumerate() {
+        palette.set_color(
+            PaletteColor::new(index as u8),
+            context.pack_color(Color::new(*r, *g, *b)),
+        );
+    }
+    palette
+}
+
+/// The number of timer ticks the text cursor stays on or off while blinking.
+const CURSOR_BLINK_TICKS: u64 = 30;
+
+/// Whether blinking cursors are currently in their visible phase. Shared by every text screen so
+/// they all blink together.
+static CURSOR_PHASE: AtomicBool = AtomicBool::new(true);
+
+/// Updates the cursor blink phase from the timer tick counter. Returns `true` if the phase changed,
+/// in which case the active text screen should call `TextScreen::draw_cursor`.
+pub fn update_cursor_phase(ticks: u64) -> bool {
+    let phase = (ticks / CURSOR_BLINK_TICKS).is_multiple_of(2);
+    CURSOR_PHASE.swap(phase, Ordering::Relaxed) != phase
+}
+
+/// What `TextScreen::write_at` does with text that reaches the end of a line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineMode {
+    /// Continue at the start of the next line.
+    Wrap,
+    /// Drop the rest of the line, so text never leaves the line it started on.
+    Clamp,
+}
+
+/// A grid of characters drawn with the system font, each in one of the colors of a palette.
+pub struct TextScreen {
+    active: bool,
+    palette: Palette,
+    data: [(u8, u8); Self::WIDTH * Self::HEIGHT],
+    cursor: (usize, usize),
+    cursor_visible: bool,
+    /// The color of the last character written, used to draw the cursor.
+    cursor_color: PaletteColor,
+}
+
+impl TextScreen {
+    pub const WIDTH: usize = 45;
+    pub const HEIGHT: usize = 26;
+    /// The y coordinate of the first line of text.
+    const TOP: u32 = 12;
+
+    pub fn new(palette: Palette) -> TextScreen {
+        TextScreen {
+            active: false,
+            palette,
+            data: [(0, 0); Self::WIDTH * Self::HEIGHT],
+            cursor: (0, 0),
+            cursor_visible: false,
+            cursor_color: PaletteColor(7),
+        }
+    }
+
+    fn index(x: usize, y: usize) -> usize {
+        x + (y * Self::WIDTH)
+    }
+    /// The size of one character cell on the display, in pixels.
+    fn cell_size(context: &GraphicsContext) -> (u32, u32) {
+        let (width, height) = FontData::system().char_size();
+        (
+            width * context.image_scale(),
+            height * context.image_scale(),
+        )
+    }
+    pub fn cursor(&self) -> (usize, usize) {
+        self.cursor
+    }
+    /// Moves the cursor to `(x, y)`, clamped to the screen.
+    pub fn set_cursor<T: Texture>(
+        &mut self,
+        context: &GraphicsContext,
+        display: &mut T,
+        x: usize,
+        y: usize,
+    ) {
+        let cursor = (x.min(Self::WIDTH - 1), y.min(Self::HEIGHT - 1));
+        if self.cursor != cursor {
+            let old = self.cursor;
+            self.cursor = cursor;
+            self.redraw_cell(context, display, old.0, old.1);
+            self.redraw_cell(context, display, cursor.0, cursor.1);
+        }
+    }
+    /// Shows or hides the blinking cursor.
+    pub fn show_cursor<T: Texture>(
+        &mut self,
+        context: &GraphicsContext,
+        display: &mut T,
+        visible: bool,
+    ) {
+        if self.cursor_visible != visible {
+            self.cursor_visible = visible;
+            self.redraw_cell(context, display, self.cursor.0, self.cursor.1);
+        }
+    }
+    /// Redraws the cell under the cursor, so it reflects the current blink phase.
+    pub fn draw_cursor<T: Texture>(&self, context: &GraphicsContext, display: &mut T) {
+        if self.cursor_visible {
+            self.redraw_cell(context, display, self.cursor.0, self.cursor.1);
+        }
+    }
+    /// Sets the character at `(x, y)` and moves the cursor to the cell after it, wrapping to the
+    /// start of the next line. Cells outside the screen are ignored.
+    pub fn set_char<T: Texture>(
+        &mut self,
+        context: &GraphicsContext,
+        display: &mut T,
+        (x, y): (usize, usize),
+        ch: u8,
+        color: PaletteColor,
+    ) {
+        if x >= Self::WIDTH || y >= Self::HEIGHT {
+            return;
+        }
+        let idx = Self::index(x, y);
+        let value = (ch, color.0);
+        self.cursor_color = color;
+        if self.data[idx] != value {
+            self.data[idx] = value;
+            self.redraw_cell(context, display, x, y);
+        }
+        if x + 1 < Self::WIDTH {
+            self.set_cursor(context, display, x + 1, y);
+        } else {
+            self.set_cursor(context, display, 0, y + 1);
+        }
+    }
+    /// Writes `text` starting at `(x, y)` and returns the cell after the last character written.
+    /// A `\n` moves to the start of the next line in `Wrap` mode and ends the text in `Clamp`
+    /// mode. Unlike writing through the cursor, this never scrolls: text that would go below the
+    /// bottom line is dropped.
+    pub fn write_at<T: Texture>(
+        &mut self,
+        context: &GraphicsContext,
+        display: &mut T,
+        (mut x, mut y): (usize, usize),
+        text: &[u8],
+        color: PaletteColor,
+        mode: LineMode,
+    ) -> (usize, usize) {
+        for &byte in text {
+            if byte == b'\n' || x >= Self::WIDTH {
+                if mode == LineMode::Clamp {
+                    if byte == b'\n' {
+                        break;
+                    }
+                    continue;
+                }
+                x = 0;
+                y += 1;
+                if byte == b'\n' {
+                    continue;
+                }
+            }
+            if y >= Self::HEIGHT {
+                break;
+            }
+            self.set_char(context, display, (x, y), byte, color);
+            x += 1;
+        }
+        (x, y)
+    }
+    /// Blanks every cell on line `y`.
+    pub fn clear_line<T: Texture>(&mut self, context: &GraphicsContext, display: &mut T, y: usize) {
+        if y >= Self::HEIGHT {
+            return;
+        }
+        for x in 0..Self::WIDTH {
+            let idx = Self::index(x, y);
+            if self.data[idx] != (0, 0) {
+                self.data[idx] = (0, 0);
+                self.redraw_cell(context, display, x, y);
+            }
+        }
+    }
+    fn redraw_cell<T: Texture>(
+        &self,
+        context: &GraphicsContext,
+        display: &mut T,
+        x: usize,
+        y: usize,
+    ) {
+        if self.active {
+            self.draw_char(context, display, x, y);
+        }
+    }
+    /// Moves every line up by `lines`, leaving blank lines at the bottom. The pixels of the lines
+    /// that stay on screen are moved directly, so only the new bottom lines are redrawn.
+    pub fn scroll_up<T: Texture>(
+        &mut self,
+        context: &GraphicsContext,
+        display: &mut T,
+        lines: usize,
+    ) {
+        let lines = lines.min(Self::HEIGHT);
+        let shift = lines * Self::WIDTH;
+        self.data.copy_within(shift.., 0);
+        let len = self.data.len();
+        self.data[len - shift..].fill((0, 0));
+        // The cursor moves up with the text it was on, stopping at the top line.
+        self.cursor.1 = self.cursor.1.saturating_sub(lines);
+        if self.active {
+            let (w, h) = Self::cell_size(context);
+            let kept_lines = Self::HEIGHT - lines;
+            context.copy_rect(
+                display,
+                Rect::new(
+                    0,
+                    (Self::TOP + lines as u32 * h) as i32,
+                    Self::WIDTH as u32 * w,
+                    kept_lines as u32 * h,
+                ),
+                Point::new(0, Self::TOP as i32),
+            );
+            for idx in kept_lines * Self::WIDTH..len {
+                self.draw_char(context, display, idx % Self::WIDTH, idx / Self::WIDTH);
+            }
+        }
+        self.redraw_cell(context, display, self.cursor.0, self.cursor.1);
+    }
+    fn draw_char<T: Texture>(
+        &self,
+        context: &GraphicsContext,
+        display: &mut T,
+        col: usize,
+        row: usize,
+    ) {
+        let (w, h) = Self::cell_size(context);
+        let x = (col as u32 * w) as i32;
+        let y = (row as u32 * h + Self::TOP) as i32;
+        let (ch, color) = self.data[Self::index(col, row)];
+        let fg_color = self.palette.get(color);
+        if ch == 0 {
+            context.fill_rect(display, Rect::new(x, y, w, h), COLOR_BLACK);
+        } else {
+            context.draw_font_char(
+                &FontData::system(),
+                ch,
+                display,
+                Point::new(x, y),
+                fg_color,
+                COLOR_BLACK,
+            );
+        }
+        if self.cursor_visible && (col, row) == self.cursor && CURSOR_PHASE.load(Ordering::Relaxed)
+        {
+            // An underline one font pixel tall along the bottom of the cell.
+            let scale = context.image_scale();
+            let cursor_color = self.palette.get(self.cursor_color.0);
+            context.fill_rect(
+                display,
+                Rect::new(x, y + (h - scale) as i32, w, scale),
+                cursor_color,
+            );
+        }
+    }
+}
+
+impl Screen for TextScreen {
+    fn set_active<T: Texture>(&mut self, context: &GraphicsContext, display: &mut T, active: bool) {
+        if self.active != active {
+            self.active = active;
+            if active {
+                self.draw_full(context, display);
+            }
+        }
+    }
+    fn draw_full<T: Texture>(&self, context: &GraphicsContext, display: &mut T) {
+        // Another screen may have drawn over every cell while this one was inactive, so nothing
+        // can be skipped here.
+        for y in 0..Self::HEIGHT {
+            for x in 0..Self::WIDTH {
+                self.draw_char(context, display, x, y);
+            }
+        }
+        // The text rectangle doesn't quite fill the display, so clear the rest.
+        let (w, h) = Self::cell_size(context);
+        let (right, bottom) = (Self::WIDTH as u32 * w, Self::TOP + Self::HEIGHT as u32 * h);
+        let (width, height) = (display.width(), display.height());
+        context.fill_rect(display, Rect::new(0, 0, width, Self::TOP), COLOR_BLACK);
+        context.fill_rect(
+            display,
+            Rect::new(right as i32, 0, width.saturating_sub(right), height),
+            COLOR_BLACK,
+        );
+        context.fill_rect(
+            display,
+            Rect::new(0, bottom as i32, width, height.saturating_sub(bottom)),
+            COLOR_BLACK,
+        );
+    }
+}
+
+/// A screen backed by a buffer of pixels. A buffered screen only draws to the display when
+/// `present` is called; an unbuffered one draws each change as it is made.
+pub struct ImageScreen {
+    active: bool,
+    buffered: bool,
+    width: usize,
+    height: usize,
+    data: Vec<Color>,
+}
+
+impl ImageScreen {
+    pub fn new(width: usize, height: usize, fill_color: Color) -> ImageScreen {
+        ImageScreen {
+            active: false,
+            buffered: true,
+            width,
+            height,
+            data: vec![fill_color; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn set_buffered(&mut self, buffered: bool) {
+        self.buffered = buffered;
+    }
+    /// Draws the whole buffer to the display, if this screen is active.
+    pub fn present<T: Texture>(&self, context: &GraphicsContext, display: &mut T) {
+        if self.active {
+            self.draw_full(context, display);
+        }
+    }
+
+    fn index(&self, x: usize, y: usize) -> usize {
+        x + (y * self.width)
+    }
+    /// Whether changes should be drawn to the display immediately.
+    fn is_live(&self) -> bool {
+        self.active && !self.buffered
+    }
+    pub fn set_pixel<T: Texture>(
+        &mut self,
+        context: &GraphicsContext,
+        display: &mut T,
+        x: usize,
+        y: usize,
+        color: Color,
+    ) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let idx = self.index(x, y);
+        // Translucent colors are composited over what is already on the screen.
+        let color = color.blend_over(self.data[idx]);
+        if self.data[idx] != color {
+            self.data[idx] = color;
+            if self.is_live() {
+                self.draw_pixel(context, display, x, y);
+            }
+        }
+    }
+    /// Copies a `width` by `height` block of pixels, stored row by row, to the screen at `(x, y)`.
+    /// Pixels outside the screen are skipped.
+    pub fn blit<T: Texture>(
+        &mut self,
+        context: &GraphicsContext,
+        display: &mut T,
+        (x, y): (usize, usize),
+        width: usize,
+        height: usize,
+        pixels: &[Color],
+    ) {
+        if width == 0 || x >= self.width || y >= self.height {
+            return;
+        }
+        let live = self.is_live();
+        for (row, line) in pixels.chunks(width).take(height).enumerate() {
+            let dest_y = y + row;
+            if dest_y >= self.height {
+                break;
+            }
+            for (col, color) in line.iter().enumerate() {
+                let dest_x = x + col;
+                if dest_x >= self.width {
+                    break;
+                }
+                let idx = self.index(dest_x, dest_y);
+                if self.data[idx] != *color {
+                    self.data[idx] = *color;
+                    if live {
+                        self.draw_pixel(context, display, dest_x, dest_y);
+                    }
+                }
+            }
+        }
+    }
+    /// Sets every pixel to `color`.
+    pub fn clear<T: Texture>(&mut self, context: &GraphicsContext, display: &mut T, color: Color) {
+        self.data.fill(color);
+        if self.is_live() {
+            let rect = Rect::new(0, 0, self.width as u32, self.height as u32);
+            context.fill_rect(display, rect, context.pack_color(color));
+        }
+    }
+    /// Moves the contents of the screen by `(dx, dy)` pixels, filling the uncovered area with
+    /// `fill_color`. The display's pixels are moved directly instead of being redrawn.
+    pub fn scroll<T: Texture>(
+        &mut self,
+        context: &GraphicsContext,
+        display: &mut T,
+        (dx, dy): (isize, isize),
+        fill_color: Color,
+    ) {
+        if dx.unsigned_abs() >= self.width || dy.unsigned_abs() >= self.height {
+            self.clear(context, display, fill_color);
+            return;
+        }
+        let (width, height) = (self.width as isize, self.height as isize);
+        let old_data =
+            core::mem::replace(&mut self.data, vec![fill_color; self.width * self.height]);
+        let (copy_width, copy_height) = ((width - dx.abs()) as usize, (height - dy.abs()) as usize);
+        let (src_x, dst_x) = if dx < 0 { (-dx, 0) } else { (0, dx) };
+        let (src_y, dst_y) = if dy < 0 { (-dy, 0) } else { (0, dy) };
+        for row in 0..copy_height {
+            let src = self.index(src_x as usize, src_y as usize + row);
+            let dst = self.index(dst_x as usize, dst_y as usize + row);
+            self.data[dst..dst + copy_width].copy_from_slice(&old_data[src..src + copy_width]);
+        }
+        if self.is_live() {
+            context.copy_rect(
+                display,
+                Rect::new(
+                    src_x as i32,
+                    src_y as i32,
+                    copy_width as u32,
+                    copy_height as u32,
+                ),
+                Point::new(dst_x as i32, dst_y as i32),
+            );
+            // Redraw the strips that were uncovered.
+            for y in 0..self.height {
+                let in_copy = (y as isize) >= dst_y && (y as isize) < dst_y + copy_height as isize;
+                for x in 0..self.width {
+                    let in_copy = in_copy
+                        && (x as isize) >= dst_x
+                        && (x as isize) < dst_x + copy_width as isize;
+                    if !in_copy {
+                        self.draw_pixel(context, display, x, y);
+                    }
+                }
+            }
+        }
+    }
+    fn draw_pixel<T: Texture>(
+        &self,
+        context: &GraphicsContext,
+        display: &mut T,
+        x: usize,
+        y: usize,
+    ) {
+        let color = context.pack_color(self.data[self.index(x, y)]);
+        context.set_pixel(display, x as u32, y as u32, color);
+    }
+}
+
+impl Screen for ImageScreen {
+    fn set_active<T: Texture>(&mut self, context: &GraphicsContext, display: &mut T, active: bool) {
+        if self.active != active {
+            self.active = active;
+            if active {
+                self.draw_full(context, display);
+            }
+        }
+    }
+    fn draw_full<T: Texture>(&self, context: &GraphicsContext, display: &mut T) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                self.draw_pixel(context, display, x, y);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::VecBuffer;
+
+    #[test]
+    fn out_of_range_palette_color_wraps() {
+        let context = GraphicsContext::const_default();
+        let mut display = VecBuffer::alloc(&context, 640, 480);
+        let mut screen = TextScreen::new(make_user_text_palette(&context));
+        screen.set_active(&context, &mut display, true);
+        let color = PaletteColor::new(0xFF);
+        assert_eq!(color, PaletteColor::new(0x0F));
+        screen.set_char(&context, &mut display, (0, 0), b'#', color);
+        assert_eq!(screen.data[0], (b'#', 0x0F));
+    }
+}
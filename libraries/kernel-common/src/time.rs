@@ -0,0 +1,14 @@
+/// The input frequency of the programmable interval timer, in Hz.
+pub const PIT_FREQUENCY: u64 = 1_193_182;
+
+/// The PIT divisor used for the system timer. One tick is `TIMER_DIVISOR / PIT_FREQUENCY`
+/// seconds, about 60.1 ticks per second.
+pub const TIMER_DIVISOR: u16 = 19853;
+
+pub const fn ticks_to_millis(ticks: u64) -> u64 {
+    ticks * TIMER_DIVISOR as u64 * 1000 / PIT_FREQUENCY
+}
+
+pub const fn millis_to_ticks(millis: u64) -> u64 {
+    millis * PIT_FREQUENCY / (TIMER_DIVISOR as u64 * 1000)
+}
@@ -1,11 +1,18 @@
-use alloc::vec::Vec;
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::Write;
 use core::{
     num::{ParseIntError, TryFromIntError},
     str::Utf8Error,
 };
 use tar_no_std::TarArchiveRef;
 
-use crate::Level;
+use crate::{
+    tileset::AnimationState, Level, Object, ObjectDraw, ObjectKind, TileAnimation, TileFlags,
+    Tileset,
+};
 
 #[derive(Debug)]
 pub enum LevelLoadError {
@@ -13,6 +20,14 @@ pub enum LevelLoadError {
     CsvWrongSize,
     CsvInvalidValue(ParseIntError),
     CsvValueOutOfRange,
+    MetaNotUtf8,
+    MetaInvalidLine,
+    MetaUnknownKey,
+    MetaInvalidValue(ParseIntError),
+    /// A line of `objects.csv` could not be parsed. Holds the line index.
+    ObjectParse(usize),
+    /// `foreground_tiles.flags` has more entries than there are tile ids.
+    TileFlagsTooLong,
 }
 
 impl From<Utf8Error> for LevelLoadError {
@@ -31,6 +46,74 @@ impl From<TryFromIntError> for LevelLoadError {
     }
 }
 
+#[derive(Debug, PartialEq, Eq)]
+pub enum LevelSaveError {
+    /// An object's custom kind would not be read back as the same kind. Holds the kind's name.
+    InvalidObjectKind(String),
+}
+
+const DEFAULT_BACKGROUND_COLOR: u32 = 0xffff9494;
+
+const TAR_BLOCK_SIZE: usize = 512;
+
+/// Appends a ustar header and the padded contents of a regular file to `out`.
+fn write_tar_entry(out: &mut Vec<u8>, name: &str, data: &[u8]) {
+    let mut header = [0u8; TAR_BLOCK_SIZE];
+    let mut put =
+        |offset: usize, value: &[u8]| header[offset..offset + value.len()].copy_from_slice(value);
+    put(0, name.as_bytes());
+    put(100, b"0000644\0");
+    put(108, b"0000000\0");
+    put(116, b"0000000\0");
+    put(124, alloc::format!("{:011o}\0", data.len()).as_bytes());
+    put(136, b"00000000000\0");
+    put(156, b"0");
+    put(257, b"ustar\0");
+    put(263, b"00");
+    // The checksum is calculated with the checksum field filled with spaces.
+    header[148..156].fill(b' ');
+    let checksum: u32 = header.iter().map(|b| *b as u32).sum();
+    header[148..156].copy_from_slice(alloc::format!("{:06o}\0 ", checksum).as_bytes());
+
+    out.extend_from_slice(&header);
+    out.extend_from_slice(data);
+    let padding = (TAR_BLOCK_SIZE - data.len() % TAR_BLOCK_SIZE) % TAR_BLOCK_SIZE;
+    out.resize(out.len() + padding, 0);
+}
+
+/// Escapes the text of a `text` object so it fits on one line of `objects.csv`. Backslashes and
+/// line breaks are written as `\\`, `\n` and `\r`.
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+/// Reverses `escape_text`. Returns `None` for an unknown escape or a trailing backslash.
+fn unescape_text(text: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        unescaped.push(match chars.next()? {
+            '\\' => '\\',
+            'n' => '\n',
+            'r' => '\r',
+            _ => return None,
+        });
+    }
+    Some(unescaped)
+}
+
 pub struct LevelArchive;
 
 impl LevelArchive {
@@ -66,12 +149,212 @@ impl LevelArchive {
         *height = data_height;
         Ok(tiles)
     }
+    /// Parses `meta.csv`, which holds one `key,value` pair per line. Colors may be written in
+    /// hex with a `0x` prefix.
+    pub fn load_meta(data: &str, level: &mut Level) -> Result<(), LevelLoadError> {
+        for line in data.split('\n') {
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once(',')
+                .ok_or(LevelLoadError::MetaInvalidLine)?;
+            let value = value.trim();
+            match key.trim() {
+                "scroll_x" => {
+                    level.scroll.0 = value.parse().map_err(LevelLoadError::MetaInvalidValue)?
+                }
+                "scroll_y" => {
+                    level.scroll.1 = value.parse().map_err(LevelLoadError::MetaInvalidValue)?
+                }
+                "background_color" => {
+                    level.background_color = match value.strip_prefix("0x") {
+                        Some(hex) => u32::from_str_radix(hex, 16),
+                        None => value.parse(),
+                    }
+                    .map_err(LevelLoadError::MetaInvalidValue)?
+                }
+                _ => return Err(LevelLoadError::MetaUnknownKey),
+            }
+        }
+        Ok(())
+    }
+    pub fn save_meta(level: &Level) -> String {
+        let mut data = String::new();
+        writeln!(data, "scroll_x,{}", level.scroll.0).unwrap();
+        writeln!(data, "scroll_y,{}", level.scroll.1).unwrap();
+        writeln!(data, "background_color,0x{:08x}", level.background_color).unwrap();
+        data
+    }
+    /// Parses `objects.csv`, which holds one object per line with the columns
+    /// `kind,x,y,width,height,layer,draw,arg`:
+    /// * `kind` is parsed by `ObjectKind::parse`
+    /// * `x` and `y` are pixel positions, `width` and `height` are pixel sizes
+    /// * `layer` is the drawing layer; it may be left out, in which case it is 0
+    /// * `draw` is `hidden` (with an empty `arg`), `text` (`arg` is the rest of the line, with
+    ///   `\\`, `\n` and `\r` escapes), or `image` (`arg` is `<image index>:<frame>`)
+    pub fn load_objects(data: &str, level: &mut Level) -> Result<(), LevelLoadError> {
+        for (index, line) in data.split('\n').enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let object = Self::parse_object(line).ok_or(LevelLoadError::ObjectParse(index))?;
+            level.add_object(object);
+        }
+        Ok(())
+    }
+    /// Splits off the first column of `rest`, returning it and the remaining columns.
+    fn split_column(rest: &str) -> (&str, &str) {
+        rest.split_once(',').unwrap_or((rest, ""))
+    }
+    fn parse_object(line: &str) -> Option<Object> {
+        let mut columns = line.splitn(6, ',');
+        let kind = ObjectKind::parse(columns.next()?);
+        let x = columns.next()?.trim().parse().ok()?;
+        let y = columns.next()?.trim().parse().ok()?;
+        let width = columns.next()?.trim().parse().ok()?;
+        let height = columns.next()?.trim().parse().ok()?;
+        // Older archives have no layer column, so the draw type follows the height directly.
+        let rest = columns.next()?;
+        let (column, after) = Self::split_column(rest);
+        let (layer, (draw_type, arg)) = match column.trim().parse() {
+            Ok(layer) => (layer, Self::split_column(after)),
+            Err(_) => (0, (column, after)),
+        };
+        let draw_type = draw_type.trim();
+        let draw = match draw_type {
+            "hidden" => ObjectDraw::Hidden,
+            "text" => ObjectDraw::Text(unescape_text(arg)?),
+            "image" => {
+                let (image, frame) = arg.split_once(':')?;
+                ObjectDraw::Image(image.trim().parse().ok()?, frame.trim().parse().ok()?)
+            }
+            _ => return None,
+        };
+        Some(Object {
+            kind,
+            x,
+            y,
+            width,
+            height,
+            layer,
+            draw,
+        })
+    }
+    /// Writes `objects.csv`. Fails if an object's kind can't be read back, see
+    /// `ObjectKind::can_save`.
+    pub fn save_objects(level: &Level) -> Result<String, LevelSaveError> {
+        let mut data = String::new();
+        for object in level.objects() {
+            if !object.kind.can_save() {
+                return Err(LevelSaveError::InvalidObjectKind(object.kind.to_string()));
+            }
+            write!(
+                data,
+                "{},{},{},{},{},{},",
+                object.kind, object.x, object.y, object.width, object.height, object.layer
+            )
+            .unwrap();
+            match &object.draw {
+                ObjectDraw::Hidden => writeln!(data, "hidden,"),
+                ObjectDraw::Text(text) => writeln!(data, "text,{}", escape_text(text)),
+                ObjectDraw::Image(image, frame) => writeln!(data, "image,{}:{}", image, frame),
+            }
+            .unwrap();
+        }
+        Ok(data)
+    }
+    /// Parses `animations.csv`, which holds one animation per line as
+    /// `tile,ticks_per_frame,frame,frame,...`. Tile ids use the same offset as the tile CSVs.
+    pub fn load_animations(data: &str) -> Result<Tileset, LevelLoadError> {
+        let mut tileset = Tileset::new();
+        let parse_tile = |value: &str| -> Result<u8, LevelLoadError> {
+            Ok(u8::try_from(value.trim().parse::<i32>()? + 1)?)
+        };
+        for line in data.split('\n') {
+            if line.is_empty() {
+                continue;
+            }
+            let mut values = line.split(',');
+            let tile = parse_tile(values.next().unwrap())?;
+            let ticks_per_frame = values
+                .next()
+                .ok_or(LevelLoadError::CsvWrongSize)?
+                .trim()
+                .parse::<u32>()?;
+            if ticks_per_frame == 0 {
+                return Err(LevelLoadError::CsvValueOutOfRange);
+            }
+            let frames = values.map(parse_tile).collect::<Result<Vec<u8>, _>>()?;
+            if frames.is_empty() {
+                return Err(LevelLoadError::CsvWrongSize);
+            }
+            tileset.add_animation(TileAnimation {
+                tile,
+                frames,
+                ticks_per_frame,
+            });
+        }
+        Ok(tileset)
+    }
+    pub fn save_animations(tileset: &Tileset) -> String {
+        let mut data = String::new();
+        for animation in tileset.animations() {
+            write!(
+                data,
+                "{},{}",
+                animation.tile as i32 - 1,
+                animation.ticks_per_frame
+            )
+            .unwrap();
+            for frame in &animation.frames {
+                write!(data, ",{}", *frame as i32 - 1).unwrap();
+            }
+            data.push('\n');
+        }
+        data
+    }
+    /// Parses `foreground_tiles.flags`, which holds one byte of `TileFlags` for each tile in the
+    /// tileset image. The first byte is for tile id 1, the same offset as the tile CSVs.
+    pub fn load_tile_flags(data: &[u8], tileset: &mut Tileset) -> Result<(), LevelLoadError> {
+        if data.len() > u8::MAX as usize {
+            return Err(LevelLoadError::TileFlagsTooLong);
+        }
+        for (index, flags) in data.iter().enumerate() {
+            tileset.set_flags(index as u8 + 1, TileFlags(*flags));
+        }
+        Ok(())
+    }
+    pub fn save_tile_flags(tileset: &Tileset) -> Vec<u8> {
+        let mut data: Vec<u8> = (1..=u8::MAX).map(|tile| tileset.flags(tile).0).collect();
+        while data.last() == Some(&0) {
+            data.pop();
+        }
+        data
+    }
+    pub fn save_csv(tiles: &[u8], width: usize) -> String {
+        let mut data = String::new();
+        for row in tiles.chunks(width.max(1)) {
+            for (index, tile) in row.iter().enumerate() {
+                if index > 0 {
+                    data.push(',');
+                }
+                write!(data, "{}", *tile as i32 - 1).unwrap();
+            }
+            data.push('\n');
+        }
+        data
+    }
     pub fn load(data: &[u8]) -> Result<Level, LevelLoadError> {
         let archive = TarArchiveRef::new(data);
         let mut width = 0;
         let mut height = 0;
         let mut background_tiles = Vec::new();
         let mut foreground_tiles = Vec::new();
+        let mut meta = None;
+        let mut objects = None;
+        let mut tileset = Tileset::new();
+        let mut tile_flags = None;
         for entry in archive.entries() {
             match entry.filename().as_str() {
                 "background.csv" => {
@@ -82,17 +365,186 @@ impl LevelArchive {
                     foreground_tiles =
                         Self::load_csv(entry.data_as_str()?, &mut width, &mut height)?
                 }
+                "meta.csv" => {
+                    meta = Some(
+                        entry
+                            .data_as_str()
+                            .map_err(|_| LevelLoadError::MetaNotUtf8)?,
+                    )
+                }
+                "objects.csv" => objects = Some(entry.data_as_str()?),
+                "animations.csv" => tileset = Self::load_animations(entry.data_as_str()?)?,
+                "foreground_tiles.flags" => tile_flags = Some(entry.data()),
                 _ => (),
             }
         }
-        Ok(Level {
+        // The animations create the tileset, so the flags are added once every entry is read.
+        if let Some(tile_flags) = tile_flags {
+            Self::load_tile_flags(tile_flags, &mut tileset)?;
+        }
+        let mut level = Level {
             width,
             height,
+            tile_size: crate::DEFAULT_TILE_SIZE,
+            tileset,
+            animation_state: AnimationState::new(),
             scroll: (0, 0),
-            background_color: 0xffff9494, // TODO
+            background_color: DEFAULT_BACKGROUND_COLOR,
             background_tiles,
             foreground_tiles,
             objects: Vec::new(),
-        })
+        };
+        if let Some(meta) = meta {
+            Self::load_meta(meta, &mut level)?;
+        }
+        if let Some(objects) = objects {
+            Self::load_objects(objects, &mut level)?;
+        }
+        Ok(level)
+    }
+    pub fn save(level: &Level) -> Result<Vec<u8>, LevelSaveError> {
+        let mut data = Vec::new();
+        let meta = Self::save_meta(level);
+        write_tar_entry(&mut data, "meta.csv", meta.as_bytes());
+        let background = Self::save_csv(&level.background_tiles, level.width);
+        write_tar_entry(&mut data, "background.csv", background.as_bytes());
+        let foreground = Self::save_csv(&level.foreground_tiles, level.width);
+        write_tar_entry(&mut data, "foreground.csv", foreground.as_bytes());
+        let animations = Self::save_animations(&level.tileset);
+        write_tar_entry(&mut data, "animations.csv", animations.as_bytes());
+        let tile_flags = Self::save_tile_flags(&level.tileset);
+        if !tile_flags.is_empty() {
+            write_tar_entry(&mut data, "foreground_tiles.flags", &tile_flags);
+        }
+        let objects = Self::save_objects(level)?;
+        write_tar_entry(&mut data, "objects.csv", objects.as_bytes());
+        // The archive ends with two zeroed blocks.
+        data.resize(data.len() + TAR_BLOCK_SIZE * 2, 0);
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn archive(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (name, contents) in entries {
+            write_tar_entry(&mut data, name, contents.as_bytes());
+        }
+        data.resize(data.len() + TAR_BLOCK_SIZE * 2, 0);
+        data
+    }
+
+    fn test_level() -> Level {
+        let data = archive(&[
+            ("background.csv", "-1,0,1\n2,3,-1\n"),
+            ("foreground.csv", "-1,-1,5\n-1,7,-1\n"),
+            ("meta.csv", "scroll_x,4\nscroll_y,8\nbackground_color,0xff102030\n"),
+            ("animations.csv", "5,10,5,6,7\n"),
+            (
+                "objects.csv",
+                "player,16,32,8,8,1,hidden,\nitem:3,0,0,16,16,0,image,2:1\nsign,4,4,8,8,0,text,a,b\n",
+            ),
+        ]);
+        Level::load(&data).unwrap()
+    }
+
+    fn assert_same(a: &Level, b: &Level) {
+        assert_eq!((a.width(), a.height()), (b.width(), b.height()));
+        assert_eq!(a.background_tiles(), b.background_tiles());
+        assert_eq!(a.foreground_tiles(), b.foreground_tiles());
+        assert_eq!((a.scroll_x(), a.scroll_y()), (b.scroll_x(), b.scroll_y()));
+        assert_eq!(a.background_color(), b.background_color());
+        assert_eq!(a.tileset().animations(), b.tileset().animations());
+        for tile in 0..=u8::MAX {
+            assert_eq!(a.tileset().flags(tile), b.tileset().flags(tile));
+        }
+        let a_objects: Vec<&Object> = a.objects().collect();
+        let b_objects: Vec<&Object> = b.objects().collect();
+        assert_eq!(a_objects.len(), b_objects.len());
+        for (a, b) in a_objects.iter().zip(b_objects) {
+            assert_eq!(a.kind, b.kind);
+            assert_eq!((a.x, a.y, a.width, a.height), (b.x, b.y, b.width, b.height));
+            assert_eq!(a.layer, b.layer);
+            match (&a.draw, &b.draw) {
+                (ObjectDraw::Hidden, ObjectDraw::Hidden) => {}
+                (ObjectDraw::Text(a), ObjectDraw::Text(b)) => assert_eq!(a, b),
+                (ObjectDraw::Image(a, a_frame), ObjectDraw::Image(b, b_frame)) => {
+                    assert_eq!((a, a_frame), (b, b_frame))
+                }
+                _ => panic!("object draw types differ"),
+            }
+        }
+    }
+
+    #[test]
+    fn load_save_load() {
+        let mut level = test_level();
+        level.tileset_mut().set_flags(5, TileFlags::SOLID);
+        let saved = Level::load(&level.save().unwrap()).unwrap();
+        assert_same(&level, &saved);
+        assert_eq!((saved.width(), saved.height()), (3, 2));
+        assert_eq!(saved.background_tiles(), &[0, 1, 2, 3, 4, 0]);
+    }
+
+    #[test]
+    fn text_with_line_breaks_round_trips() {
+        let mut level = test_level();
+        level.add_object(Object {
+            kind: ObjectKind::Custom(String::from("sign")),
+            x: 0.0,
+            y: 0.0,
+            width: 8,
+            height: 8,
+            layer: 0,
+            draw: ObjectDraw::Text(String::from("two\nlines \\n\r")),
+        });
+        let saved = Level::load(&level.save().unwrap()).unwrap();
+        assert_same(&level, &saved);
+    }
+
+    #[test]
+    fn invalid_text_escape() {
+        assert_eq!(unescape_text("a\\nb\\\\").as_deref(), Some("a\nb\\"));
+        assert_eq!(unescape_text("a\\x"), None);
+        assert_eq!(unescape_text("a\\"), None);
+    }
+
+    fn custom_object(name: &str) -> Object {
+        Object {
+            kind: ObjectKind::Custom(String::from(name)),
+            x: 2.0,
+            y: 4.0,
+            width: 8,
+            height: 8,
+            layer: 0,
+            draw: ObjectDraw::Hidden,
+        }
+    }
+
+    #[test]
+    fn custom_kinds_round_trip() {
+        let mut level = test_level();
+        level.add_object(custom_object("door"));
+        level.add_object(custom_object("enemy:boss"));
+        level.add_object(custom_object("item:"));
+        let saved = Level::load(&level.save().unwrap()).unwrap();
+        assert_same(&level, &saved);
+        let kinds: Vec<&ObjectKind> = saved.objects().map(|object| &object.kind).collect();
+        assert!(kinds.contains(&&ObjectKind::Custom(String::from("enemy:boss"))));
+    }
+
+    #[test]
+    fn unsaveable_custom_kinds_are_rejected() {
+        for name in ["", "a,b", "two\nlines", "player", "enemy:3", "item:65535"] {
+            let mut level = test_level();
+            level.add_object(custom_object(name));
+            assert_eq!(
+                level.save().err(),
+                Some(LevelSaveError::InvalidObjectKind(String::from(name)))
+            );
+        }
     }
 }
@@ -2,10 +2,13 @@
 extern crate alloc;
 
 mod archive;
+mod tileset;
 
 use alloc::{string::String, vec::Vec};
+use core::{fmt, ops::Range};
 
-pub use archive::LevelLoadError;
+pub use archive::{LevelLoadError, LevelSaveError};
+pub use tileset::{TileAnimation, TileFlags, Tileset};
 
 pub enum ObjectDraw {
     Hidden,
@@ -13,12 +16,63 @@ pub enum ObjectDraw {
     Image(usize, u32),
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ObjectKind {
+    Player,
+    Enemy(u16),
+    Item(u16),
+    Custom(String),
+}
+
+impl ObjectKind {
+    /// Parses a kind as written in a level archive: `player`, `enemy:<id>`, `item:<id>`, or any
+    /// other name for a custom kind.
+    pub fn parse(name: &str) -> ObjectKind {
+        let parse_id = |id: &str| id.parse::<u16>().ok();
+        if name == "player" {
+            ObjectKind::Player
+        } else if let Some(id) = name.strip_prefix("enemy:").and_then(parse_id) {
+            ObjectKind::Enemy(id)
+        } else if let Some(id) = name.strip_prefix("item:").and_then(parse_id) {
+            ObjectKind::Item(id)
+        } else {
+            ObjectKind::Custom(String::from(name))
+        }
+    }
+    /// Returns true if this kind can be written to a level archive and parsed back as the same
+    /// kind. A custom name can't be if it is empty, contains a `,` or a line break, or is also
+    /// the name of a built-in kind.
+    pub fn can_save(&self) -> bool {
+        match self {
+            ObjectKind::Custom(name) => {
+                !name.is_empty()
+                    && !name.contains([',', '\n', '\r'])
+                    && ObjectKind::parse(name) == *self
+            }
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for ObjectKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjectKind::Player => write!(f, "player"),
+            ObjectKind::Enemy(id) => write!(f, "enemy:{}", id),
+            ObjectKind::Item(id) => write!(f, "item:{}", id),
+            ObjectKind::Custom(name) => write!(f, "{}", name),
+        }
+    }
+}
+
 pub struct Object {
-    pub kind: &'static str,
+    pub kind: ObjectKind,
     pub x: f32,
     pub y: f32,
     pub width: u32,
     pub height: u32,
+    /// Objects are drawn in increasing layer order. Layers below 0 are drawn behind the tiles.
+    pub layer: i16,
     pub draw: ObjectDraw,
 }
 
@@ -29,25 +83,54 @@ impl Object {
     pub fn pixel_y(&self) -> i32 {
         self.y as i32
     }
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        let (left, top) = (self.pixel_x(), self.pixel_y());
+        x >= left && y >= top && x < left + self.width as i32 && y < top + self.height as i32
+    }
+    pub fn intersects(&self, x: i32, y: i32, width: u32, height: u32) -> bool {
+        let (left, top) = (self.pixel_x(), self.pixel_y());
+        left < x + width as i32
+            && x < left + self.width as i32
+            && top < y + height as i32
+            && y < top + self.height as i32
+    }
 }
 
-#[derive(Clone, Copy)]
-pub struct ObjectId(usize);
+/// Identifies an object in a level. The generation is bumped whenever a slot is reused, so an id
+/// kept after its object was removed never refers to a different object.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ObjectId {
+    index: usize,
+    generation: u32,
+}
+
+struct ObjectSlot {
+    generation: u32,
+    object: Option<Object>,
+}
+
+const DEFAULT_TILE_SIZE: u32 = 16;
 
 pub struct Level {
     width: usize,
     height: usize,
+    tile_size: u32,
+    tileset: Tileset,
+    animation_state: tileset::AnimationState,
     scroll: (i32, i32),
     background_color: u32,
     background_tiles: Vec<u8>,
     foreground_tiles: Vec<u8>,
-    objects: Vec<Option<Object>>,
+    objects: Vec<ObjectSlot>,
 }
 
 impl Level {
     pub fn load(data: &[u8]) -> Result<Self, LevelLoadError> {
         archive::LevelArchive::load(data)
     }
+    pub fn save(&self) -> Result<Vec<u8>, LevelSaveError> {
+        archive::LevelArchive::save(self)
+    }
 
     pub fn width(&self) -> usize {
         self.width
@@ -55,6 +138,26 @@ impl Level {
     pub fn height(&self) -> usize {
         self.height
     }
+    /// The size in pixels of one tile, used to map pixel coordinates to tile cells.
+    pub fn tile_size(&self) -> u32 {
+        self.tile_size
+    }
+    pub fn set_tile_size(&mut self, tile_size: u32) {
+        self.tile_size = tile_size;
+    }
+    pub fn tileset(&self) -> &Tileset {
+        &self.tileset
+    }
+    pub fn tileset_mut(&mut self) -> &mut Tileset {
+        &mut self.tileset
+    }
+    pub fn pixel_width(&self) -> u32 {
+        self.width as u32 * self.tile_size
+    }
+    pub fn pixel_height(&self) -> u32 {
+        self.height as u32 * self.tile_size
+    }
+    /// The level pixel shown at the top left of the view.
     pub fn scroll_x(&self) -> i32 {
         self.scroll.0
     }
@@ -64,55 +167,201 @@ impl Level {
     pub fn background_color(&self) -> u32 {
         self.background_color
     }
+    pub fn set_scroll(&mut self, x: i32, y: i32) {
+        self.scroll = (x, y);
+    }
+    /// Clamps the scroll position to `[0, level_pixels - view_size]` on each axis, so a view of the
+    /// given size never shows anything outside the level.
+    pub fn clamp_scroll(&mut self, view_width: u32, view_height: u32) {
+        let max_x = self.pixel_width().saturating_sub(view_width) as i32;
+        let max_y = self.pixel_height().saturating_sub(view_height) as i32;
+        self.scroll = (self.scroll.0.clamp(0, max_x), self.scroll.1.clamp(0, max_y));
+    }
+    /// Scrolls so the pixel is in the center of the view, as far as the level edges allow.
+    pub fn center_scroll_on(&mut self, x: i32, y: i32, view_width: u32, view_height: u32) {
+        self.scroll = (x - view_width as i32 / 2, y - view_height as i32 / 2);
+        self.clamp_scroll(view_width, view_height);
+    }
+    /// The columns and rows of tiles that are at least partly inside a view of the given size at
+    /// the current scroll position.
+    pub fn visible_tiles(&self, view_width: u32, view_height: u32) -> (Range<u32>, Range<u32>) {
+        if self.tile_size == 0 {
+            return (0..0, 0..0);
+        }
+        let visible = |scroll: i32, view: u32, tiles: usize| {
+            let start = scroll.max(0) as u32 / self.tile_size;
+            let end = (scroll + view as i32).max(0) as u32;
+            let end = end.div_ceil(self.tile_size).min(tiles as u32);
+            start.min(end)..end
+        };
+        (
+            visible(self.scroll.0, view_width, self.width),
+            visible(self.scroll.1, view_height, self.height),
+        )
+    }
+    pub fn set_background_color(&mut self, color: u32) {
+        self.background_color = color;
+    }
 
-    fn get_index(&self, x: u32, y: u32) -> usize {
-        x as usize + (y as usize * self.width)
+    /// Returns true if `(x, y)` is a tile inside the level.
+    pub fn in_bounds(&self, x: u32, y: u32) -> bool {
+        (x as usize) < self.width && (y as usize) < self.height
+    }
+    fn get_index(&self, x: u32, y: u32) -> Option<usize> {
+        if self.in_bounds(x, y) {
+            Some(x as usize + (y as usize * self.width))
+        } else {
+            None
+        }
+    }
+    pub fn background_tiles(&self) -> &[u8] {
+        &self.background_tiles
+    }
+    pub fn background_tiles_mut(&mut self) -> &mut [u8] {
+        &mut self.background_tiles
+    }
+    pub fn foreground_tiles(&self) -> &[u8] {
+        &self.foreground_tiles
+    }
+    pub fn foreground_tiles_mut(&mut self) -> &mut [u8] {
+        &mut self.foreground_tiles
     }
+    pub fn fill_background(&mut self, tile: u8) {
+        self.background_tiles.fill(tile);
+    }
+    /// Returns the tile at `(x, y)`, or 0 (no tile) outside the level.
     pub fn get_background_tile(&self, x: u32, y: u32) -> u8 {
-        self.background_tiles
-            .get(self.get_index(x, y))
-            .map(|t| *t)
+        self.get_index(x, y)
+            .and_then(|idx| self.background_tiles.get(idx))
+            .copied()
             .unwrap_or_default()
     }
-    pub fn set_background_tile(&mut self, x: u32, y: u32, tile: u8) {
-        let idx = self.get_index(x, y);
-        self.background_tiles[idx] = tile;
+    /// Sets a tile, returning false if `(x, y)` is outside the level.
+    pub fn set_background_tile(&mut self, x: u32, y: u32, tile: u8) -> bool {
+        if let Some(slot) = self
+            .get_index(x, y)
+            .and_then(|idx| self.background_tiles.get_mut(idx))
+        {
+            *slot = tile;
+            return true;
+        }
+        false
     }
+    /// Returns the tile at `(x, y)`, or 0 (no tile) outside the level.
     pub fn get_foreground_tile(&self, x: u32, y: u32) -> u8 {
-        self.foreground_tiles
-            .get(self.get_index(x, y))
-            .map(|t| *t)
+        self.get_index(x, y)
+            .and_then(|idx| self.foreground_tiles.get(idx))
+            .copied()
             .unwrap_or_default()
     }
-    pub fn set_foreground_tile(&mut self, x: u32, y: u32, tile: u8) {
-        let idx = self.get_index(x, y);
-        self.foreground_tiles[idx] = tile;
+    /// Sets a tile, returning false if `(x, y)` is outside the level.
+    pub fn set_foreground_tile(&mut self, x: u32, y: u32, tile: u8) -> bool {
+        if let Some(slot) = self
+            .get_index(x, y)
+            .and_then(|idx| self.foreground_tiles.get_mut(idx))
+        {
+            *slot = tile;
+            return true;
+        }
+        false
     }
 
+    /// Updates the frame shown by every animated tile for the given tick.
+    pub fn advance_animations(&mut self, tick: u64) {
+        self.animation_state.advance(&self.tileset, tick);
+    }
+    /// Returns the tile id to draw for `tile`, taking animations into account.
+    pub fn effective_tile(&self, tile: u8) -> u8 {
+        self.animation_state.get(tile)
+    }
+    pub fn get_effective_background_tile(&self, x: u32, y: u32) -> u8 {
+        self.effective_tile(self.get_background_tile(x, y))
+    }
+    pub fn get_effective_foreground_tile(&self, x: u32, y: u32) -> u8 {
+        self.effective_tile(self.get_foreground_tile(x, y))
+    }
+
+    /// Returns true if the pixel is inside a foreground tile the tileset marks as solid. Pixels
+    /// outside the level never collide.
+    pub fn tile_collides(&self, px: i32, py: i32) -> bool {
+        if px < 0 || py < 0 || self.tile_size == 0 {
+            return false;
+        }
+        let x = px as u32 / self.tile_size;
+        let y = py as u32 / self.tile_size;
+        self.tileset.is_solid(self.get_foreground_tile(x, y))
+    }
+
+    fn get_slot(&mut self, id: ObjectId) -> Option<&mut ObjectSlot> {
+        self.objects
+            .get_mut(id.index)
+            .filter(|slot| slot.generation == id.generation)
+    }
     pub fn get_object(&mut self, id: ObjectId) -> Option<&mut Object> {
-        self.objects.get_mut(id.0).and_then(|obj| obj.as_mut())
+        self.get_slot(id).and_then(|slot| slot.object.as_mut())
     }
     pub fn add_object(&mut self, object: Object) -> ObjectId {
         for (index, slot) in self.objects.iter_mut().enumerate() {
-            if slot.is_none() {
-                *slot = Some(object);
-                return ObjectId(index);
+            if slot.object.is_none() {
+                slot.generation = slot.generation.wrapping_add(1);
+                slot.object = Some(object);
+                return ObjectId {
+                    index,
+                    generation: slot.generation,
+                };
             }
         }
         let index = self.objects.len();
-        self.objects.push(Some(object));
-        ObjectId(index)
+        self.objects.push(ObjectSlot {
+            generation: 0,
+            object: Some(object),
+        });
+        ObjectId {
+            index,
+            generation: 0,
+        }
     }
     pub fn remove_object(&mut self, id: ObjectId) -> bool {
-        if let Some(slot) = self.objects.get_mut(id.0) {
-            if slot.is_some() {
-                *slot = None;
-                return true;
-            }
-        }
-        false
+        self.get_slot(id)
+            .and_then(|slot| slot.object.take())
+            .is_some()
     }
+    /// Returns every object in drawing order, sorted by layer. Objects in the same layer keep their
+    /// relative order.
     pub fn objects(&self) -> impl Iterator<Item = &Object> {
-        self.objects.iter().filter_map(|obj| obj.as_ref())
+        let mut objects: Vec<&Object> = self
+            .objects
+            .iter()
+            .filter_map(|slot| slot.object.as_ref())
+            .collect();
+        objects.sort_by_key(|obj| obj.layer);
+        objects.into_iter()
+    }
+    fn object_ids(&self) -> impl Iterator<Item = (ObjectId, &Object)> {
+        self.objects.iter().enumerate().filter_map(|(index, slot)| {
+            let id = ObjectId {
+                index,
+                generation: slot.generation,
+            };
+            slot.object.as_ref().map(|obj| (id, obj))
+        })
+    }
+    /// Returns the first object containing the pixel.
+    pub fn object_at(&self, x: i32, y: i32) -> Option<ObjectId> {
+        self.object_ids()
+            .find(|(_, obj)| obj.contains(x, y))
+            .map(|(id, _)| id)
+    }
+    /// Returns every object overlapping the given pixel rectangle.
+    pub fn objects_in_rect(
+        &self,
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+    ) -> impl Iterator<Item = ObjectId> + '_ {
+        self.object_ids()
+            .filter(move |(_, obj)| obj.intersects(x, y, width, height))
+            .map(|(id, _)| id)
     }
 }
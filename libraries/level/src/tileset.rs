@@ -0,0 +1,93 @@
+use alloc::vec::Vec;
+
+/// An animated tile. Wherever `tile` is placed, the level shows each of `frames` in turn for
+/// `ticks_per_frame` ticks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TileAnimation {
+    pub tile: u8,
+    pub frames: Vec<u8>,
+    pub ticks_per_frame: u32,
+}
+
+impl TileAnimation {
+    pub fn frame_at(&self, tick: u64) -> u8 {
+        if self.frames.is_empty() || self.ticks_per_frame == 0 {
+            return self.tile;
+        }
+        let index = (tick / self.ticks_per_frame as u64) % self.frames.len() as u64;
+        self.frames[index as usize]
+    }
+}
+
+/// Properties of a tile used by collision and rendering code.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TileFlags(pub u8);
+
+impl TileFlags {
+    /// Objects can't move through the tile.
+    pub const SOLID: TileFlags = TileFlags(1 << 0);
+
+    pub fn contains(self, flags: TileFlags) -> bool {
+        self.0 & flags.0 == flags.0
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Tileset {
+    animations: Vec<TileAnimation>,
+    /// Indexed by tile id. Tiles past the end have no flags.
+    flags: Vec<TileFlags>,
+}
+
+impl Tileset {
+    pub fn new() -> Self {
+        Tileset::default()
+    }
+
+    pub fn animations(&self) -> &[TileAnimation] {
+        &self.animations
+    }
+    /// Adds an animation, replacing any existing animation for the same tile.
+    pub fn add_animation(&mut self, animation: TileAnimation) {
+        self.animations.retain(|a| a.tile != animation.tile);
+        self.animations.push(animation);
+    }
+    pub fn is_animated(&self, tile: u8) -> bool {
+        self.animations.iter().any(|a| a.tile == tile)
+    }
+
+    pub fn flags(&self, tile: u8) -> TileFlags {
+        self.flags.get(tile as usize).copied().unwrap_or_default()
+    }
+    pub fn set_flags(&mut self, tile: u8, flags: TileFlags) {
+        let index = tile as usize;
+        if index >= self.flags.len() {
+            self.flags.resize(index + 1, TileFlags::default());
+        }
+        self.flags[index] = flags;
+    }
+    pub fn is_solid(&self, tile: u8) -> bool {
+        self.flags(tile).contains(TileFlags::SOLID)
+    }
+}
+
+/// The frame currently shown for every tile id.
+pub(crate) struct AnimationState([u8; 256]);
+
+impl AnimationState {
+    pub fn new() -> Self {
+        let mut frames = [0; 256];
+        for (tile, frame) in frames.iter_mut().enumerate() {
+            *frame = tile as u8;
+        }
+        AnimationState(frames)
+    }
+    pub fn advance(&mut self, tileset: &Tileset, tick: u64) {
+        for animation in tileset.animations() {
+            self.0[animation.tile as usize] = animation.frame_at(tick);
+        }
+    }
+    pub fn get(&self, tile: u8) -> u8 {
+        self.0[tile as usize]
+    }
+}
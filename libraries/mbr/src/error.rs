@@ -12,6 +12,22 @@ impl MbrError {
     }
 }
 
+/// An error thrown while reading a partition table from a block device.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum ReadError<E> {
+    /// The device failed to read a block.
+    Device(E),
+
+    /// The data read from the device was not a valid partition table.
+    Mbr(MbrError),
+}
+
+impl<E> From<MbrError> for ReadError<E> {
+    fn from(err: MbrError) -> Self {
+        ReadError::Mbr(err)
+    }
+}
+
 /// The possible causes of an error.
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub enum ErrorCause {
@@ -38,4 +54,75 @@ pub enum ErrorCause {
         /// The size of the buffer passed into the function
         actual: usize,
     },
+
+    /// The error was thrown because a GPT header did not start with the `EFI PART`
+    /// signature, or had an impossible header size.
+    InvalidGptSignature,
+
+    /// The error was thrown because the CRC32 of a GPT header did not match its contents.
+    InvalidGptHeaderChecksum {
+        /// The checksum stored in the header
+        expected: u32,
+
+        /// The checksum calculated from the header
+        actual: u32,
+    },
+
+    /// The error was thrown because the CRC32 of a GPT partition entry array did not match
+    /// the checksum stored in the header.
+    InvalidGptEntriesChecksum {
+        /// The checksum stored in the header
+        expected: u32,
+
+        /// The checksum calculated from the entry array
+        actual: u32,
+    },
+
+    /// The error was thrown because the block ranges of two used partition table entries
+    /// overlap.
+    OverlappingPartitions {
+        /// The index of the first overlapping entry
+        a: usize,
+
+        /// The index of the second overlapping entry
+        b: usize,
+    },
+
+    /// The error was thrown because a partition table entry extends past the end of the disk.
+    PartitionOutOfBounds {
+        /// The index of the entry
+        index: usize,
+    },
+
+    /// The error was thrown because a chain of extended boot records was longer than the
+    /// crate allows, which usually means it loops back on itself.
+    EbrChainTooLong {
+        /// The maximum number of extended boot records that are followed
+        limit: usize,
+    },
+
+    /// The error was thrown because an extended boot record points to a logical partition or
+    /// the next record beyond the largest LBA an MBR can address.
+    EbrOutOfRange,
+
+    /// The error was thrown because a GPT header declared a partition entry size the crate
+    /// cannot parse.
+    UnsupportedGptEntrySize {
+        /// The entry size read from the header
+        size: u32,
+    },
+
+    /// The error was thrown because a used GPT partition entry ends before it starts, or its last
+    /// block is the largest possible LBA.
+    InvalidGptPartitionRange {
+        /// The index of the entry in the partition entry array
+        index: usize,
+    },
+
+    /// The error was thrown because the partition entry array LBA in a GPT header is too large to
+    /// be turned into a byte offset.
+    GptEntriesOutOfRange {
+        /// The entry array LBA read from the header
+        lba: u64,
+    },
 }
@@ -0,0 +1,435 @@
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
+use crate::{read_u32_le, ErrorCause, MbrError, BUFFER_SIZE};
+
+fn read_u64_le(buf: &[u8]) -> u64 {
+    u64::from_le_bytes(buf.try_into().unwrap())
+}
+
+/// Computes the CRC32 (IEEE 802.3) checksum used by GPT headers and entry arrays.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// A GUID as stored on disk, with the first three fields little-endian.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    pub const UNUSED: Guid = Guid([0; 16]);
+    pub const EFI_SYSTEM: Guid = Guid::from_fields(
+        0xc12a7328,
+        0xf81f,
+        0x11d2,
+        [0xba, 0x4b, 0x00, 0xa0, 0xc9, 0x3e, 0xc9, 0x3b],
+    );
+    pub const BASIC_DATA: Guid = Guid::from_fields(
+        0xebd0a0a2,
+        0xb9e5,
+        0x4433,
+        [0x87, 0xc0, 0x68, 0xb6, 0xb7, 0x26, 0x99, 0xc7],
+    );
+    pub const LINUX_FILESYSTEM: Guid = Guid::from_fields(
+        0x0fc63daf,
+        0x8483,
+        0x4772,
+        [0x8e, 0x79, 0x3d, 0x69, 0xd8, 0x47, 0x7d, 0xe4],
+    );
+
+    /// Creates a GUID from the fields of its usual text form, e.g.
+    /// `C12A7328-F81F-11D2-BA4B-00A0C93EC93B`.
+    pub const fn from_fields(data1: u32, data2: u16, data3: u16, data4: [u8; 8]) -> Guid {
+        let d1 = data1.to_le_bytes();
+        let d2 = data2.to_le_bytes();
+        let d3 = data3.to_le_bytes();
+        Guid([
+            d1[0], d1[1], d1[2], d1[3], d2[0], d2[1], d3[0], d3[1], data4[0], data4[1], data4[2],
+            data4[3], data4[4], data4[5], data4[6], data4[7],
+        ])
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-",
+            read_u32_le(&b[0..4]),
+            u16::from_le_bytes([b[4], b[5]]),
+            u16::from_le_bytes([b[6], b[7]]),
+            b[8],
+            b[9]
+        )?;
+        for byte in &b[10..16] {
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// The GPT header, found at LBA 1.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct GptHeader {
+    pub revision: u32,
+    pub current_lba: u64,
+    pub backup_lba: u64,
+    pub first_usable_lba: u64,
+    pub last_usable_lba: u64,
+    pub disk_guid: Guid,
+    /// The first block of the partition entry array.
+    pub entries_lba: u64,
+    pub num_entries: u32,
+    pub entry_size: u32,
+    pub entries_crc32: u32,
+}
+
+const GPT_SIGNATURE: [u8; 8] = *b"EFI PART";
+const HEADER_CRC_OFFSET: usize = 16;
+const MIN_HEADER_SIZE: usize = 92;
+const ENTRY_SIZE: usize = 128;
+const NAME_OFFSET: usize = 56;
+
+impl GptHeader {
+    /// Parses and validates the GPT header from the raw contents of LBA 1.
+    ///
+    /// Throws an error in the following cases:
+    /// * `BufferWrongSizeError` if `bytes.len()` is less than 512
+    /// * `InvalidGptSignature` if the block does not start with `EFI PART`
+    /// * `InvalidGptHeaderChecksum` if the header's CRC32 does not match its contents
+    pub fn from_bytes<T: AsRef<[u8]>>(bytes: &T) -> Result<GptHeader, MbrError> {
+        let buffer: &[u8] = bytes.as_ref();
+        if buffer.len() < BUFFER_SIZE {
+            return Err(MbrError::from_cause(ErrorCause::BufferWrongSizeError {
+                expected: BUFFER_SIZE,
+                actual: buffer.len(),
+            }));
+        }
+        if buffer[0..8] != GPT_SIGNATURE {
+            return Err(MbrError::from_cause(ErrorCause::InvalidGptSignature));
+        }
+        let header_size = read_u32_le(&buffer[12..16]) as usize;
+        if !(MIN_HEADER_SIZE..=BUFFER_SIZE).contains(&header_size) {
+            return Err(MbrError::from_cause(ErrorCause::InvalidGptSignature));
+        }
+
+        // The checksum is calculated with the checksum field itself zeroed.
+        let expected = read_u32_le(&buffer[HEADER_CRC_OFFSET..HEADER_CRC_OFFSET + 4]);
+        let mut header = [0u8; BUFFER_SIZE];
+        header[..header_size].copy_from_slice(&buffer[..header_size]);
+        header[HEADER_CRC_OFFSET..HEADER_CRC_OFFSET + 4].fill(0);
+        let actual = crc32(&header[..header_size]);
+        if actual != expected {
+            return Err(MbrError::from_cause(ErrorCause::InvalidGptHeaderChecksum {
+                expected,
+                actual,
+            }));
+        }
+
+        let mut disk_guid = Guid::UNUSED;
+        disk_guid.0.copy_from_slice(&buffer[56..72]);
+        Ok(GptHeader {
+            revision: read_u32_le(&buffer[8..12]),
+            current_lba: read_u64_le(&buffer[24..32]),
+            backup_lba: read_u64_le(&buffer[32..40]),
+            first_usable_lba: read_u64_le(&buffer[40..48]),
+            last_usable_lba: read_u64_le(&buffer[48..56]),
+            disk_guid,
+            entries_lba: read_u64_le(&buffer[72..80]),
+            num_entries: read_u32_le(&buffer[80..84]),
+            entry_size: read_u32_le(&buffer[84..88]),
+            entries_crc32: read_u32_le(&buffer[88..92]),
+        })
+    }
+
+    /// The size in bytes of the partition entry array described by this header.
+    pub fn entries_len(&self) -> usize {
+        self.num_entries as usize * self.entry_size as usize
+    }
+}
+
+/// A used entry in the GPT partition entry array.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct GptPartition {
+    /// The type of partition in this entry, e.g. `Guid::EFI_SYSTEM`.
+    pub type_guid: Guid,
+
+    /// A GUID unique to this partition.
+    pub unique_guid: Guid,
+
+    /// The index of the first block of this entry.
+    pub start_lba: u64,
+
+    /// The index of the last block of this entry (inclusive).
+    pub end_lba: u64,
+
+    pub attributes: u64,
+
+    pub name: String,
+}
+
+impl GptPartition {
+    fn from_bytes(entry: &[u8]) -> GptPartition {
+        let mut type_guid = Guid::UNUSED;
+        type_guid.0.copy_from_slice(&entry[0..16]);
+        let mut unique_guid = Guid::UNUSED;
+        unique_guid.0.copy_from_slice(&entry[16..32]);
+        // The name is up to 36 UTF-16LE code units, padded with zeros.
+        let name_units = entry[NAME_OFFSET..ENTRY_SIZE]
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|&c| c != 0);
+        let name = char::decode_utf16(name_units)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+        GptPartition {
+            type_guid,
+            unique_guid,
+            start_lba: read_u64_le(&entry[32..40]),
+            end_lba: read_u64_le(&entry[40..48]),
+            attributes: read_u64_le(&entry[48..56]),
+            name,
+        }
+    }
+
+    /// The total number of blocks in this entry, or 0 if it ends before it starts.
+    pub fn sector_count(&self) -> u64 {
+        self.end_lba
+            .checked_sub(self.start_lba)
+            .map_or(0, |blocks| blocks.saturating_add(1))
+    }
+}
+
+/// A GUID partition table.
+pub struct GuidPartitionTable {
+    pub header: GptHeader,
+    /// The used entries of the partition entry array, in order.
+    pub partitions: Vec<GptPartition>,
+}
+
+impl GuidPartitionTable {
+    /// Parses the partition table from the raw header block (LBA 1) and the raw partition entry
+    /// array (starting at `header.entries_lba`).
+    ///
+    /// Throws an error in the following cases:
+    /// * Any error from `GptHeader::from_bytes`
+    /// * `UnsupportedGptEntrySize` if the header's entry size is smaller than 128 bytes
+    /// * `BufferWrongSizeError` if `entries` is shorter than the array described by the header
+    /// * `InvalidGptEntriesChecksum` if the array's CRC32 does not match the header
+    /// * `InvalidGptPartitionRange` if a used entry's last block is before its first block
+    pub fn from_bytes<H: AsRef<[u8]>, E: AsRef<[u8]>>(
+        header: &H,
+        entries: &E,
+    ) -> Result<GuidPartitionTable, MbrError> {
+        let header = GptHeader::from_bytes(header)?;
+        let entries: &[u8] = entries.as_ref();
+        let entry_size = header.entry_size as usize;
+        if entry_size < ENTRY_SIZE || !entry_size.is_multiple_of(8) {
+            return Err(MbrError::from_cause(ErrorCause::UnsupportedGptEntrySize {
+                size: header.entry_size,
+            }));
+        }
+        if entries.len() < header.entries_len() {
+            return Err(MbrError::from_cause(ErrorCause::BufferWrongSizeError {
+                expected: header.entries_len(),
+                actual: entries.len(),
+            }));
+        }
+        let entries = &entries[..header.entries_len()];
+        let actual = crc32(entries);
+        if actual != header.entries_crc32 {
+            return Err(MbrError::from_cause(
+                ErrorCause::InvalidGptEntriesChecksum {
+                    expected: header.entries_crc32,
+                    actual,
+                },
+            ));
+        }
+
+        let mut partitions = Vec::new();
+        for (index, entry) in entries.chunks_exact(entry_size).enumerate() {
+            if entry[0..16] == Guid::UNUSED.0 {
+                continue;
+            }
+            let partition = GptPartition::from_bytes(entry);
+            if partition.end_lba < partition.start_lba || partition.end_lba == u64::MAX {
+                return Err(MbrError::from_cause(ErrorCause::InvalidGptPartitionRange {
+                    index,
+                }));
+            }
+            partitions.push(partition);
+        }
+        Ok(GuidPartitionTable { header, partitions })
+    }
+
+    /// Returns the first partition with the given type GUID.
+    pub fn find_by_type(&self, type_guid: &Guid) -> Option<&GptPartition> {
+        self.partitions.iter().find(|p| p.type_guid == *type_guid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MasterBootRecord, PartitionTable, PartitionTableEntry, PartitionType};
+    use alloc::{string::ToString, vec};
+
+    const DISK_GUID: Guid = Guid::from_fields(
+        0x01234567,
+        0x89ab,
+        0xcdef,
+        [0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef],
+    );
+    const EFI_GUID: Guid = Guid::from_fields(0x11111111, 0x2222, 0x3333, [4; 8]);
+    const DATA_GUID: Guid = Guid::from_fields(0x55555555, 0x6666, 0x7777, [8; 8]);
+
+    const NUM_ENTRIES: usize = 4;
+    const DISK_BLOCKS: u64 = 2048;
+
+    fn write_entry(
+        entry: &mut [u8],
+        type_guid: Guid,
+        unique_guid: Guid,
+        lba: (u64, u64),
+        name: &str,
+    ) {
+        entry[0..16].copy_from_slice(&type_guid.0);
+        entry[16..32].copy_from_slice(&unique_guid.0);
+        entry[32..40].copy_from_slice(&lba.0.to_le_bytes());
+        entry[40..48].copy_from_slice(&lba.1.to_le_bytes());
+        for (unit, bytes) in name
+            .encode_utf16()
+            .zip(entry[NAME_OFFSET..].chunks_exact_mut(2))
+        {
+            bytes.copy_from_slice(&unit.to_le_bytes());
+        }
+    }
+
+    /// A disk with a protective MBR, the GPT header at LBA 1 and the entry array at LBA 2.
+    fn gpt_disk() -> Vec<u8> {
+        let mut disk = vec![0u8; BUFFER_SIZE * 3];
+
+        let mut entries = [PartitionTableEntry::empty(); crate::MAX_ENTRIES];
+        entries[0] = PartitionTableEntry::new(
+            false,
+            PartitionType::GptProtective(0xee),
+            1,
+            (DISK_BLOCKS - 1) as u32,
+        );
+        let mbr = MasterBootRecord {
+            disk_signature: 0,
+            entries,
+        };
+        mbr.serialize(&mut disk).unwrap();
+
+        let array = &mut disk[BUFFER_SIZE * 2..];
+        write_entry(
+            &mut array[..ENTRY_SIZE],
+            EFI_GUID,
+            DISK_GUID,
+            (34, 99),
+            "EFI",
+        );
+        write_entry(
+            &mut array[ENTRY_SIZE * 2..ENTRY_SIZE * 3],
+            DATA_GUID,
+            EFI_GUID,
+            (100, 2013),
+            "data",
+        );
+        let entries_crc32 = crc32(&disk[BUFFER_SIZE * 2..]);
+
+        let header = &mut disk[BUFFER_SIZE..BUFFER_SIZE * 2];
+        header[0..8].copy_from_slice(&GPT_SIGNATURE);
+        header[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+        header[12..16].copy_from_slice(&(MIN_HEADER_SIZE as u32).to_le_bytes());
+        header[24..32].copy_from_slice(&1u64.to_le_bytes());
+        header[32..40].copy_from_slice(&(DISK_BLOCKS - 1).to_le_bytes());
+        header[40..48].copy_from_slice(&34u64.to_le_bytes());
+        header[48..56].copy_from_slice(&(DISK_BLOCKS - 34).to_le_bytes());
+        header[56..72].copy_from_slice(&DISK_GUID.0);
+        header[72..80].copy_from_slice(&2u64.to_le_bytes());
+        header[80..84].copy_from_slice(&(NUM_ENTRIES as u32).to_le_bytes());
+        header[84..88].copy_from_slice(&(ENTRY_SIZE as u32).to_le_bytes());
+        header[88..92].copy_from_slice(&entries_crc32.to_le_bytes());
+        let header_crc32 = crc32(&header[..MIN_HEADER_SIZE]);
+        header[HEADER_CRC_OFFSET..HEADER_CRC_OFFSET + 4]
+            .copy_from_slice(&header_crc32.to_le_bytes());
+        disk
+    }
+
+    fn parse_cause(disk: &[u8]) -> Option<ErrorCause> {
+        PartitionTable::from_bytes(&disk).err().map(|err| err.cause)
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn protective_mbr_reads_gpt() {
+        let table = match PartitionTable::from_bytes(&gpt_disk()) {
+            Ok(PartitionTable::Gpt(table)) => table,
+            Ok(PartitionTable::Mbr(_)) => panic!("read a GPT disk as MBR"),
+            Err(err) => panic!("{:?}", err.cause),
+        };
+        assert_eq!(table.header.disk_guid, DISK_GUID);
+        assert_eq!(table.header.entries_lba, 2);
+        assert_eq!(table.header.num_entries, NUM_ENTRIES as u32);
+        assert_eq!(table.header.last_usable_lba, DISK_BLOCKS - 34);
+
+        // The unused entry between the two partitions is skipped.
+        assert_eq!(table.partitions.len(), 2);
+        let efi = &table.partitions[0];
+        assert_eq!(efi.type_guid, EFI_GUID);
+        assert_eq!(efi.unique_guid, DISK_GUID);
+        assert_eq!((efi.start_lba, efi.end_lba), (34, 99));
+        assert_eq!(efi.sector_count(), 66);
+        assert_eq!(efi.name, "EFI");
+        let data = &table.partitions[1];
+        assert_eq!(data.type_guid, DATA_GUID);
+        assert_eq!((data.start_lba, data.end_lba), (100, 2013));
+        assert_eq!(data.name, "data");
+        assert_eq!(table.find_by_type(&DATA_GUID), Some(data));
+    }
+
+    #[test]
+    fn flipped_header_byte_is_rejected() {
+        let mut disk = gpt_disk();
+        // The low byte of last_usable_lba.
+        disk[BUFFER_SIZE + 48] ^= 1;
+        assert!(matches!(
+            parse_cause(&disk),
+            Some(ErrorCause::InvalidGptHeaderChecksum { .. })
+        ));
+    }
+
+    #[test]
+    fn flipped_entry_byte_is_rejected() {
+        let mut disk = gpt_disk();
+        // The low byte of the second partition's start LBA.
+        disk[BUFFER_SIZE * 2 + ENTRY_SIZE * 2 + 32] ^= 1;
+        assert!(matches!(
+            parse_cause(&disk),
+            Some(ErrorCause::InvalidGptEntriesChecksum { .. })
+        ));
+    }
+
+    #[test]
+    fn guid_display() {
+        assert_eq!(
+            Guid::EFI_SYSTEM.to_string(),
+            "C12A7328-F81F-11D2-BA4B-00A0C93EC93B"
+        );
+    }
+}
@@ -1,9 +1,16 @@
 // Based on https://github.com/ischeinkman/mbr-nostd
 
 #![no_std]
+extern crate alloc;
+
+use alloc::{vec, vec::Vec};
+use block_device::BlockDevice;
 
 mod error;
-pub use error::{ErrorCause, MbrError};
+pub use error::{ErrorCause, MbrError, ReadError};
+
+mod gpt;
+pub use gpt::*;
 
 mod partition;
 pub use partition::*;
@@ -18,14 +25,19 @@ fn write_u32_le(buf: &mut [u8], val: u32) {
 
 /// A struct representing an MBR partition table.
 pub struct MasterBootRecord {
+    /// The 32-bit disk signature some operating systems use to identify the disk.
+    pub disk_signature: u32,
     pub entries: [PartitionTableEntry; MAX_ENTRIES],
 }
 
 const BUFFER_SIZE: usize = 512;
+const SIGNATURE_OFFSET: usize = 440;
 const TABLE_OFFSET: usize = 446;
 const ENTRY_SIZE: usize = 16;
 const SUFFIX_BYTES: [u8; 2] = [0x55, 0xaa];
 const MAX_ENTRIES: usize = (BUFFER_SIZE - TABLE_OFFSET - 2) / ENTRY_SIZE;
+/// The most extended boot records `read_logical` will follow before giving up.
+const MAX_EBR_CHAIN: usize = 128;
 
 impl MasterBootRecord {
     /// Parses the MBR table from a raw byte buffer.
@@ -57,9 +69,30 @@ impl MasterBootRecord {
             let buffer_off = &buffer[offset..];
             let lba = read_u32_le(&buffer_off[8..12]);
             let len = read_u32_le(&buffer_off[12..16]);
-            entries[idx] = PartitionTableEntry::new(bootable, partition_type, lba, len);
+            let mut entry = PartitionTableEntry::new(bootable, partition_type, lba, len);
+            entry.chs_start.copy_from_slice(&buffer_off[1..4]);
+            entry.chs_end.copy_from_slice(&buffer_off[5..8]);
+            entries[idx] = entry;
         }
-        Ok(MasterBootRecord { entries })
+        let disk_signature = read_u32_le(&buffer[SIGNATURE_OFFSET..SIGNATURE_OFFSET + 4]);
+        Ok(MasterBootRecord {
+            disk_signature,
+            entries,
+        })
+    }
+
+    /// Reads the first block of a device and parses the MBR table from it.
+    ///
+    /// Throws an error in the following cases:
+    /// * `ReadError::Device` if the device fails to read the block
+    /// * `ReadError::Mbr` for any error from `from_bytes`
+    pub fn read_from<D: BlockDevice>(device: &D) -> Result<MasterBootRecord, ReadError<D::Error>> {
+        let block_size = D::BLOCK_SIZE as usize;
+        let mut buffer = vec![0u8; block_size.max(BUFFER_SIZE)];
+        device
+            .read(&mut buffer[..block_size], 0, 1)
+            .map_err(ReadError::Device)?;
+        Ok(MasterBootRecord::from_bytes(&buffer)?)
     }
 
     /// Serializes this MBR partition table to a raw byte buffer.
@@ -67,9 +100,9 @@ impl MasterBootRecord {
     /// Throws an error in the following cases:
     /// * `BufferWrongSizeError` if `buffer.len()` is less than 512
     ///
-    /// Note that it only affects the partition table itself, which only appears starting
-    /// from byte `446` of the MBR; no bytes before this are affected, even though it is
-    /// still necessary to pass a full `512` byte buffer.
+    /// Note that it only affects the disk signature and partition table, which only appear
+    /// starting from byte `440` of the MBR; no bytes before this are affected, even though it
+    /// is still necessary to pass a full `512` byte buffer.
     pub fn serialize<T: AsMut<[u8]>>(&self, buffer: &mut T) -> Result<usize, MbrError> {
         let buffer: &mut [u8] = buffer.as_mut();
         if buffer.len() < BUFFER_SIZE {
@@ -82,11 +115,17 @@ impl MasterBootRecord {
             let suffix: &mut [u8] = &mut buffer[BUFFER_SIZE - SUFFIX_BYTES.len()..BUFFER_SIZE];
             suffix.copy_from_slice(&SUFFIX_BYTES);
         }
+        write_u32_le(
+            &mut buffer[SIGNATURE_OFFSET..SIGNATURE_OFFSET + 4],
+            self.disk_signature,
+        );
         for idx in 0..MAX_ENTRIES {
             let offset = TABLE_OFFSET + idx * ENTRY_SIZE;
             let entry = self.entries[idx];
             buffer[offset] = if entry.bootable { 0x80 } else { 0x00 };
+            buffer[offset + 1..offset + 4].copy_from_slice(&entry.chs_start);
             buffer[offset + 4] = entry.partition_type.to_mbr_tag_byte();
+            buffer[offset + 5..offset + 8].copy_from_slice(&entry.chs_end);
             {
                 let lba_slice: &mut [u8] = &mut buffer[offset + 8..offset + 12];
                 write_u32_le(lba_slice, entry.logical_block_address);
@@ -98,4 +137,220 @@ impl MasterBootRecord {
         }
         Ok(BUFFER_SIZE)
     }
+
+    /// Returns the entries that describe a partition, along with their index in the table.
+    /// Entries that are `Unused` or have no sectors are skipped.
+    pub fn used_partitions(&self) -> impl Iterator<Item = (usize, &PartitionTableEntry)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.is_used())
+    }
+
+    /// Returns the first used entry marked as bootable, along with its index in the table.
+    pub fn first_bootable(&self) -> Option<(usize, &PartitionTableEntry)> {
+        self.used_partitions().find(|(_, entry)| entry.bootable)
+    }
+
+    /// Checks that the used entries of this table describe a sensible layout.
+    ///
+    /// Throws an error in the following cases:
+    /// * `OverlappingPartitions` if the block ranges of two used entries intersect
+    /// * `PartitionOutOfBounds` if `disk_sectors` is given and an entry extends past it
+    pub fn validate(&self, disk_sectors: Option<u64>) -> Result<(), MbrError> {
+        for (a, first) in self.used_partitions() {
+            if let Some(disk_sectors) = disk_sectors {
+                if first.end_block() > disk_sectors {
+                    return Err(MbrError::from_cause(ErrorCause::PartitionOutOfBounds {
+                        index: a,
+                    }));
+                }
+            }
+            for (b, second) in self.used_partitions().filter(|(b, _)| *b > a) {
+                if (first.logical_block_address as u64) < second.end_block()
+                    && (second.logical_block_address as u64) < first.end_block()
+                {
+                    return Err(MbrError::from_cause(ErrorCause::OverlappingPartitions {
+                        a,
+                        b,
+                    }));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Follows the chain of extended boot records of every extended partition in this table,
+    /// returning the logical partitions they contain. The returned entries have absolute LBAs.
+    ///
+    /// Throws an error in the following cases:
+    /// * `ReadError::Device` if the device fails to read a block
+    /// * `ReadError::Mbr` if an extended boot record can't be parsed
+    /// * `EbrChainTooLong` if the chain has more than 128 records, e.g. because it is cyclic
+    /// * `EbrOutOfRange` if a logical partition or the next record is past the last 32-bit LBA
+    pub fn read_logical<D: BlockDevice>(
+        &self,
+        device: &D,
+    ) -> Result<Vec<PartitionTableEntry>, ReadError<D::Error>> {
+        let block_size = D::BLOCK_SIZE as usize;
+        let mut buffer = vec![0u8; block_size.max(BUFFER_SIZE)];
+        let mut logical = Vec::new();
+        for extended in self
+            .entries
+            .iter()
+            .filter(|e| matches!(e.partition_type, PartitionType::Extended(_)))
+        {
+            // The first entry of each EBR is relative to that EBR, the second (the link to the
+            // next EBR) is relative to the start of the extended partition.
+            let mut ebr_lba = extended.logical_block_address;
+            let mut visited = 0;
+            while ebr_lba != 0 {
+                visited += 1;
+                if visited > MAX_EBR_CHAIN {
+                    return Err(ReadError::Mbr(MbrError::from_cause(
+                        ErrorCause::EbrChainTooLong {
+                            limit: MAX_EBR_CHAIN,
+                        },
+                    )));
+                }
+                device
+                    .read(&mut buffer[..block_size], ebr_lba as usize * block_size, 1)
+                    .map_err(ReadError::Device)?;
+                let ebr = MasterBootRecord::from_bytes(&buffer)?;
+                let out_of_range =
+                    || ReadError::Mbr(MbrError::from_cause(ErrorCause::EbrOutOfRange));
+                let entry = ebr.entries[0];
+                if entry.is_used() {
+                    logical.push(PartitionTableEntry {
+                        logical_block_address: ebr_lba
+                            .checked_add(entry.logical_block_address)
+                            .ok_or_else(out_of_range)?,
+                        ..entry
+                    });
+                }
+                let next = ebr.entries[1];
+                ebr_lba = if next.partition_type == PartitionType::Unused {
+                    0
+                } else {
+                    extended
+                        .logical_block_address
+                        .checked_add(next.logical_block_address)
+                        .ok_or_else(out_of_range)?
+                };
+            }
+        }
+        Ok(logical)
+    }
+
+    /// Returns true if this is a protective MBR, meaning the disk actually uses a GPT.
+    pub fn is_protective(&self) -> bool {
+        matches!(
+            self.entries[0].partition_type,
+            PartitionType::GptProtective(_)
+        )
+    }
+}
+
+/// The partition table of a disk, either a legacy MBR or a GPT behind a protective MBR.
+pub enum PartitionTable {
+    Mbr(MasterBootRecord),
+    Gpt(GuidPartitionTable),
+}
+
+impl PartitionTable {
+    /// Parses the partition table from the raw bytes at the start of a disk.
+    ///
+    /// `bytes` must contain at least the MBR. If that is a protective MBR, `bytes` must also
+    /// contain the GPT header at LBA 1 and the whole partition entry array it points to.
+    ///
+    /// Throws any error from `MasterBootRecord::from_bytes` or `GuidPartitionTable::from_bytes`,
+    /// or `GptEntriesOutOfRange` if the header's entry array LBA can't be a byte offset.
+    pub fn from_bytes<T: AsRef<[u8]>>(bytes: &T) -> Result<PartitionTable, MbrError> {
+        let buffer: &[u8] = bytes.as_ref();
+        let mbr = MasterBootRecord::from_bytes(&buffer)?;
+        if !mbr.is_protective() {
+            return Ok(PartitionTable::Mbr(mbr));
+        }
+        let header = buffer
+            .get(BUFFER_SIZE..BUFFER_SIZE * 2)
+            .ok_or(MbrError::from_cause(ErrorCause::BufferWrongSizeError {
+                expected: BUFFER_SIZE * 2,
+                actual: buffer.len(),
+            }))?;
+        let entries_lba = GptHeader::from_bytes(&header)?.entries_lba;
+        let entries_start = usize::try_from(entries_lba)
+            .ok()
+            .and_then(|lba| lba.checked_mul(BUFFER_SIZE))
+            .ok_or(MbrError::from_cause(ErrorCause::GptEntriesOutOfRange {
+                lba: entries_lba,
+            }))?;
+        let entries = buffer.get(entries_start..).unwrap_or(&[]);
+        GuidPartitionTable::from_bytes(&header, &entries).map(PartitionTable::Gpt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(ranges: &[(u32, u32)]) -> MasterBootRecord {
+        let mut entries = [PartitionTableEntry::empty(); MAX_ENTRIES];
+        for (entry, &(lba, len)) in entries.iter_mut().zip(ranges) {
+            *entry = PartitionTableEntry::new(false, PartitionType::LinuxExt(0x83), lba, len);
+        }
+        MasterBootRecord {
+            disk_signature: 0,
+            entries,
+        }
+    }
+
+    fn validate_cause(mbr: &MasterBootRecord, disk_sectors: Option<u64>) -> Option<ErrorCause> {
+        mbr.validate(disk_sectors).err().map(|err| err.cause)
+    }
+
+    #[test]
+    fn adjacent_partitions_are_valid() {
+        let mbr = table(&[(1, 10), (11, 10), (21, 10)]);
+        assert_eq!(validate_cause(&mbr, Some(31)), None);
+    }
+
+    #[test]
+    fn contained_partition_overlaps() {
+        let mbr = table(&[(1, 100), (20, 10)]);
+        assert_eq!(
+            validate_cause(&mbr, None),
+            Some(ErrorCause::OverlappingPartitions { a: 0, b: 1 })
+        );
+        let mbr = table(&[(20, 10), (1, 100)]);
+        assert_eq!(
+            validate_cause(&mbr, None),
+            Some(ErrorCause::OverlappingPartitions { a: 0, b: 1 })
+        );
+    }
+
+    #[test]
+    fn partially_overlapping_partitions() {
+        let mbr = table(&[(1, 10), (30, 10), (10, 10)]);
+        assert_eq!(
+            validate_cause(&mbr, None),
+            Some(ErrorCause::OverlappingPartitions { a: 0, b: 2 })
+        );
+    }
+
+    #[test]
+    fn unused_entries_are_ignored() {
+        let mut mbr = table(&[(1, 10), (1, 10)]);
+        mbr.entries[1].partition_type = PartitionType::Unused;
+        assert_eq!(validate_cause(&mbr, None), None);
+    }
+
+    #[test]
+    fn partition_past_disk_end() {
+        let mbr = table(&[(1, 10), (11, 10)]);
+        assert_eq!(validate_cause(&mbr, Some(21)), None);
+        assert_eq!(
+            validate_cause(&mbr, Some(20)),
+            Some(ErrorCause::PartitionOutOfBounds { index: 1 })
+        );
+    }
 }
@@ -6,10 +6,14 @@ pub enum PartitionType {
     Fat12(u8),
     Fat16(u8),
     Fat32(u8),
+    Extended(u8),
+    LinuxSwap(u8),
     LinuxExt(u8),
     HfsPlus(u8),
     ISO9660(u8),
     NtfsExfat(u8),
+    GptProtective(u8),
+    EfiSystem(u8),
 }
 
 impl PartitionType {
@@ -20,9 +24,14 @@ impl PartitionType {
             0x01 => PartitionType::Fat12(tag),
             0x04 | 0x06 | 0x0e => PartitionType::Fat16(tag),
             0x0b | 0x0c | 0x1b | 0x1c => PartitionType::Fat32(tag),
+            0x05 | 0x0f | 0x85 => PartitionType::Extended(tag),
+            0x82 => PartitionType::LinuxSwap(tag),
             0x83 => PartitionType::LinuxExt(tag),
             0x07 => PartitionType::NtfsExfat(tag),
             0xaf => PartitionType::HfsPlus(tag),
+            0x96 => PartitionType::ISO9660(tag),
+            0xee => PartitionType::GptProtective(tag),
+            0xef => PartitionType::EfiSystem(tag),
             _ => PartitionType::Unknown(tag),
         }
     }
@@ -35,10 +44,14 @@ impl PartitionType {
             PartitionType::Fat12(t) => t,
             PartitionType::Fat16(t) => t,
             PartitionType::Fat32(t) => t,
+            PartitionType::Extended(t) => t,
+            PartitionType::LinuxSwap(t) => t,
             PartitionType::LinuxExt(t) => t,
             PartitionType::HfsPlus(t) => t,
             PartitionType::ISO9660(t) => t,
             PartitionType::NtfsExfat(t) => t,
+            PartitionType::GptProtective(t) => t,
+            PartitionType::EfiSystem(t) => t,
         }
     }
 }
@@ -48,9 +61,17 @@ impl PartitionType {
 pub struct PartitionTableEntry {
     pub bootable: bool,
 
+    /// The cylinder-head-sector address of the first block of this entry, as stored in the
+    /// table.
+    pub chs_start: [u8; 3],
+
     /// The type of partition in this entry.
     pub partition_type: PartitionType,
 
+    /// The cylinder-head-sector address of the last block of this entry, as stored in the
+    /// table.
+    pub chs_end: [u8; 3],
+
     /// The index of the first block of this entry.
     pub logical_block_address: u32,
 
@@ -67,7 +88,9 @@ impl PartitionTableEntry {
     ) -> PartitionTableEntry {
         PartitionTableEntry {
             bootable,
+            chs_start: [0; 3],
             partition_type,
+            chs_end: [0; 3],
             logical_block_address,
             sector_count,
         }
@@ -76,4 +99,68 @@ impl PartitionTableEntry {
     pub fn empty() -> PartitionTableEntry {
         PartitionTableEntry::new(false, PartitionType::Unused, 0, 0)
     }
+
+    /// The index of the first block after this entry.
+    pub fn end_block(&self) -> u64 {
+        self.logical_block_address as u64 + self.sector_count as u64
+    }
+
+    /// Returns true if this entry describes a partition with at least one block.
+    pub fn is_used(&self) -> bool {
+        self.partition_type != PartitionType::Unused && self.sector_count > 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mapped_tags_round_trip() {
+        let mapped = [
+            0x01, 0x04, 0x05, 0x06, 0x07, 0x0b, 0x0c, 0x0e, 0x0f, 0x1b, 0x1c, 0x82, 0x83, 0x85,
+            0x96, 0xaf, 0xee, 0xef,
+        ];
+        for tag in mapped {
+            let partition_type = PartitionType::from_mbr_tag_byte(tag);
+            assert!(
+                !matches!(partition_type, PartitionType::Unknown(_)),
+                "tag {:#04x} is not mapped",
+                tag
+            );
+            assert_eq!(partition_type.to_mbr_tag_byte(), tag);
+        }
+    }
+
+    #[test]
+    fn every_tag_round_trips() {
+        for tag in 0..=u8::MAX {
+            assert_eq!(PartitionType::from_mbr_tag_byte(tag).to_mbr_tag_byte(), tag);
+        }
+        assert_eq!(PartitionType::from_mbr_tag_byte(0), PartitionType::Unused);
+    }
+
+    #[test]
+    fn new_types() {
+        assert_eq!(
+            PartitionType::from_mbr_tag_byte(0x0f),
+            PartitionType::Extended(0x0f)
+        );
+        assert_eq!(
+            PartitionType::from_mbr_tag_byte(0x82),
+            PartitionType::LinuxSwap(0x82)
+        );
+        assert_eq!(
+            PartitionType::from_mbr_tag_byte(0xee),
+            PartitionType::GptProtective(0xee)
+        );
+        assert_eq!(
+            PartitionType::from_mbr_tag_byte(0xef),
+            PartitionType::EfiSystem(0xef)
+        );
+        assert_eq!(
+            PartitionType::from_mbr_tag_byte(0x96),
+            PartitionType::ISO9660(0x96)
+        );
+    }
 }
@@ -0,0 +1,169 @@
+#![no_std]
+extern crate alloc;
+
+use alloc::{vec, vec::Vec};
+use core::{cell::RefCell, fmt, ops::Range};
+
+pub use block_device::BlockDevice;
+
+#[derive(Debug, Copy, Clone)]
+pub enum RamDiskError {
+    AddressNotAligned,
+    OutOfBounds,
+    WrongSizeBuffer,
+}
+
+impl fmt::Display for RamDiskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            RamDiskError::AddressNotAligned => write!(f, "address not aligned to block size"),
+            RamDiskError::OutOfBounds => write!(f, "address out of bounds"),
+            RamDiskError::WrongSizeBuffer => write!(f, "buffer size does not match block count"),
+        }
+    }
+}
+
+/// A block device stored in memory, for using the filesystem and partition table code without a
+/// real drive.
+pub struct RamDisk {
+    data: RefCell<Vec<u8>>,
+}
+
+impl RamDisk {
+    /// Creates a zeroed disk with the given number of blocks.
+    pub fn new(block_count: usize) -> RamDisk {
+        RamDisk {
+            data: RefCell::new(vec![0; block_count * Self::BLOCK_SIZE as usize]),
+        }
+    }
+    /// Creates a disk holding a copy of an image. The last block is padded with zeroes if the image
+    /// isn't a whole number of blocks.
+    pub fn from_bytes(bytes: &[u8]) -> RamDisk {
+        const BLOCK_SIZE: usize = RamDisk::BLOCK_SIZE as usize;
+        let mut data = bytes.to_vec();
+        data.resize(bytes.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, 0);
+        RamDisk {
+            data: RefCell::new(data),
+        }
+    }
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data.into_inner()
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.data.borrow().len() / Self::BLOCK_SIZE as usize
+    }
+    pub fn size_in_kib(&self) -> usize {
+        self.block_count() / 2
+    }
+
+    /// Returns the byte range covered by an access, checking it the same way as `ata::Drive`.
+    fn byte_range(
+        &self,
+        buf_len: usize,
+        address: usize,
+        number_of_blocks: usize,
+    ) -> Result<Range<usize>, RamDiskError> {
+        const BLOCK_SIZE: usize = RamDisk::BLOCK_SIZE as usize;
+        if buf_len != number_of_blocks * BLOCK_SIZE {
+            return Err(RamDiskError::WrongSizeBuffer);
+        }
+        if address % BLOCK_SIZE != 0 {
+            return Err(RamDiskError::AddressNotAligned);
+        }
+        match address.checked_add(buf_len) {
+            Some(end) if end <= self.data.borrow().len() => Ok(address..end),
+            _ => Err(RamDiskError::OutOfBounds),
+        }
+    }
+}
+
+impl BlockDevice for RamDisk {
+    const BLOCK_SIZE: u32 = 512;
+    type Error = RamDiskError;
+    fn read(
+        &self,
+        buf: &mut [u8],
+        address: usize,
+        number_of_blocks: usize,
+    ) -> Result<(), Self::Error> {
+        let range = self.byte_range(buf.len(), address, number_of_blocks)?;
+        buf.copy_from_slice(&self.data.borrow()[range]);
+        Ok(())
+    }
+    fn write(
+        &self,
+        buf: &[u8],
+        address: usize,
+        number_of_blocks: usize,
+    ) -> Result<(), Self::Error> {
+        let range = self.byte_range(buf.len(), address, number_of_blocks)?;
+        self.data.borrow_mut()[range].copy_from_slice(buf);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mbr::{MasterBootRecord, PartitionTableEntry, PartitionType};
+
+    const BLOCK_SIZE: usize = RamDisk::BLOCK_SIZE as usize;
+
+    #[test]
+    fn mbr_round_trip() {
+        let disk = RamDisk::new(16);
+        let mut entries = [PartitionTableEntry::empty(); 4];
+        entries[0] = PartitionTableEntry::new(true, PartitionType::Fat32(0x0c), 1, 8);
+        entries[1] = PartitionTableEntry::new(false, PartitionType::LinuxExt(0x83), 9, 7);
+        let mut block = [0; BLOCK_SIZE];
+        MasterBootRecord {
+            disk_signature: 0x1234_5678,
+            entries,
+        }
+        .serialize(&mut block)
+        .unwrap();
+        disk.write(&block, 0, 1).unwrap();
+
+        let mbr = MasterBootRecord::read_from(&disk).unwrap();
+        assert_eq!(mbr.disk_signature, 0x1234_5678);
+        assert_eq!(mbr.entries, entries);
+        assert_eq!(mbr.validate(Some(disk.block_count() as u64)), Ok(()));
+    }
+
+    #[test]
+    fn read_back_what_was_written() {
+        let disk = RamDisk::new(4);
+        let data: Vec<u8> = (0..BLOCK_SIZE * 2).map(|i| i as u8).collect();
+        disk.write(&data, BLOCK_SIZE, 2).unwrap();
+        let mut buf = vec![0; BLOCK_SIZE * 2];
+        disk.read(&mut buf, BLOCK_SIZE, 2).unwrap();
+        assert_eq!(buf, data);
+
+        let bytes = disk.into_bytes();
+        assert!(bytes[..BLOCK_SIZE].iter().all(|b| *b == 0));
+        assert!(bytes[BLOCK_SIZE * 3..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn access_checks() {
+        let disk = RamDisk::from_bytes(&[1; BLOCK_SIZE + 1]);
+        assert_eq!(disk.block_count(), 2);
+        let mut buf = [0; BLOCK_SIZE];
+        assert!(matches!(
+            disk.read(&mut buf, BLOCK_SIZE * 2, 1),
+            Err(RamDiskError::OutOfBounds)
+        ));
+        assert!(matches!(
+            disk.read(&mut buf, 1, 1),
+            Err(RamDiskError::AddressNotAligned)
+        ));
+        assert!(matches!(
+            disk.read(&mut buf, 0, 2),
+            Err(RamDiskError::WrongSizeBuffer)
+        ));
+        disk.read(&mut buf, BLOCK_SIZE, 1).unwrap();
+        assert_eq!(buf[0], 1);
+        assert!(buf[1..].iter().all(|b| *b == 0));
+    }
+}
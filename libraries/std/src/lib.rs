@@ -1,15 +1,18 @@
 #![feature(core_intrinsics)]
 #![feature(alloc_error_handler)]
+#![feature(asm_const)]
 #![no_std]
 extern crate alloc;
 
+pub mod rand;
 pub mod screen;
+mod syscall;
+pub mod time;
 
 pub use alloc::*;
 pub use core::*;
 
 use core::alloc::{GlobalAlloc, Layout};
-use core::arch::asm;
 use kernel_common::*;
 
 pub type SystemError = UserError;
@@ -21,50 +24,43 @@ macro_rules! entry_point {
         pub extern "C" fn _start() -> ! {
             let f: fn() = $path; // validate entry point signature
             f();
-            $crate::exit();
-            unreachable!();
+            $crate::exit()
         }
     };
 }
 
-fn syscall(id: Syscall, arg_base: u64, arg_len: u64) -> Result<(u64, u64), SystemError> {
-    unsafe {
-        let id: u64 = mem::transmute(id);
-        let ret0: u64;
-        let ret1: u64;
-        asm!(
-            "syscall",
-            in("rdi") id,
-            in("rsi") arg_base,
-            in("rdx") arg_len,
-            lateout("rax") ret0,
-            lateout("rdx") ret1,
-            clobber_abi("sysv64"),
-        );
-        if ret0 == 0 {
-            Err(mem::transmute(ret1))
-        } else {
-            Ok((ret0, ret1))
-        }
-    }
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => {
+        $crate::_print(format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! println {
+    () => {
+        $crate::print!("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::print!("{}\n", format_args!($($arg)*))
+    };
+}
+
+#[doc(hidden)]
+pub fn _print(args: core::fmt::Arguments) {
+    let message = format!("{}", args);
+    unsafe { syscall::syscall_program_print(&message) }
 }
 
 #[panic_handler]
 fn panic(info: &panic::PanicInfo) -> ! {
     let info = format!("{}", info);
-    let info = info.as_bytes();
-    syscall(
-        Syscall::ProgramPanic,
-        info.as_ptr() as u64,
-        info.len() as u64,
-    )
-    .unwrap_or_default();
-    unreachable!();
+    unsafe { syscall::syscall_program_panic(&info) }
 }
 
 #[alloc_error_handler]
 fn alloc_error_handler(layout: Layout) -> ! {
-    panic!("alloc failed: {:?}", layout);
+    panic!("out of memory: {:?}", layout);
 }
 
 struct SystemAllocator;
@@ -72,27 +68,51 @@ struct SystemAllocator;
 #[global_allocator]
 static ALLOCATOR: SystemAllocator = SystemAllocator;
 
+// Running out of memory gives a null pointer, so the `alloc_error_handler` runs.
 unsafe impl GlobalAlloc for SystemAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        syscall(Syscall::MemAlloc, 0, layout.pack_u64()).unwrap().1 as *mut u8
+        syscall::syscall_mem_alloc(layout)
     }
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        syscall(Syscall::MemDealloc, ptr as u64, layout.pack_u64()).unwrap();
+        syscall::syscall_mem_dealloc(ptr, layout)
     }
     unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
-        syscall(Syscall::MemAllocZeroed, 0, layout.pack_u64())
-            .unwrap()
-            .1 as *mut u8
+        syscall::syscall_mem_alloc_zeroed(layout)
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        syscall::syscall_mem_realloc(ptr, layout, new_size)
     }
-    // unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
-    //     syscall(Syscall::MemRealloc, ptr as u64, pack_layout(layout)).unwrap() as *mut u8
-    // }
 }
 
-pub fn exit() {
-    syscall(Syscall::ProgramExit, 0, 0).unwrap_or_default();
+pub fn exit() -> ! {
+    unsafe { syscall::syscall_program_exit() }
 }
 
 pub fn wait_for_confirm() {
-    syscall(Syscall::ProgramWaitForConfirm, 0, 0).unwrap();
+    unsafe {
+        syscall::syscall_program_wait_for_confirm(0);
+    }
+}
+
+/// Waits until space or enter is pressed or `ticks` timer ticks have passed, whichever comes
+/// first. Returns `true` if a key was pressed.
+pub fn wait_for_confirm_timeout(ticks: u64) -> bool {
+    if ticks == 0 {
+        // A timeout of 0 means waiting forever to the kernel.
+        return false;
+    }
+    unsafe { syscall::syscall_program_wait_for_confirm(ticks) == 1 }
+}
+
+pub fn shutdown() -> ! {
+    unsafe { syscall::syscall_program_shutdown() }
+}
+
+pub fn reboot() -> ! {
+    unsafe { syscall::syscall_program_reboot() }
+}
+
+/// Returns the CPU time and heap usage of this program.
+pub fn stats() -> ProgramStats {
+    unsafe { syscall::syscall_out(syscall::syscall_program_stats).unwrap() }
 }
@@ -0,0 +1,15 @@
+use crate::syscall;
+
+/// A random `u64` from the kernel. This comes from the CPU's hardware generator when it has one,
+/// but otherwise from a seeded PRNG, so it is not suitable for cryptography.
+pub fn u64() -> u64 {
+    unsafe { syscall::syscall_program_random() }
+}
+
+/// Fills `buf` with random bytes from `u64()`.
+pub fn fill(buf: &mut [u8]) {
+    for chunk in buf.chunks_mut(8) {
+        let bytes = u64().to_le_bytes();
+        chunk.copy_from_slice(&bytes[..chunk.len()]);
+    }
+}
@@ -1,20 +1,96 @@
-use crate::{syscall, SyscallArg, SystemError};
-use kernel_common::Syscall;
+use crate::{syscall, SystemError};
+use kernel_common::{graphics::Texture, UserError};
 
-pub use kernel_common::Color;
+pub use kernel_common::{graphics::PixelFormat, Color, Key, MouseState};
 
+/// Returns the width, height and pixel format of the framebuffer.
+pub fn info() -> Result<(usize, usize, PixelFormat), SystemError> {
+    let (framebuffer, context) = unsafe {
+        (
+            syscall::syscall_out(syscall::syscall_info_framebuffer)?,
+            syscall::syscall_out(syscall::syscall_info_graphics_ctx)?,
+        )
+    };
+    Ok((
+        framebuffer.width() as usize,
+        framebuffer.height() as usize,
+        context.pixel_format(),
+    ))
+}
+
+/// Returns the width and height of the framebuffer in pixels.
+pub fn dimensions() -> (usize, usize) {
+    let (width, height, _) = info().unwrap();
+    (width, height)
+}
+
+/// Creates the program's screen. An image screen starts out buffered: drawing only changes the
+/// program's back buffer, and nothing is shown until `present` is called.
 pub fn create(image: bool) -> Result<(), SystemError> {
-    syscall(Syscall::ScreenCreate, bool::pack_u64(image), 0).map(|_| ())
+    UserError::check(unsafe { syscall::syscall_screen_create(image as u64) })
 }
 
+/// Sets the character at `(x, y)` on the program's text screen. `color` is an index into the
+/// 16-color console palette; only its low 4 bits are used.
+///
+/// Returns `UserError::InvalidValue` if the cell is outside the screen, or
+/// `UserError::MissingScreen` if the program has not created a text screen.
 pub fn set_char(x: usize, y: usize, ch: u8, color: u8) -> Result<(), SystemError> {
-    let arg_pos = (x as u32, y as u32).pack_u64();
-    let arg_data = (ch as u32, color as u32).pack_u64();
-    syscall(Syscall::ScreenSetChar, arg_pos, arg_data).map(|_| ())
+    UserError::check(unsafe { syscall::syscall_screen_set_char(x, y, ch as u64, color as u64) })
 }
 
+/// Sets one pixel of the program's image screen. Pixels outside the screen are ignored.
+///
+/// Returns `UserError::MissingScreen` if the program has not created an image screen.
 pub fn set_pixel(x: usize, y: usize, color: Color) -> Result<(), SystemError> {
-    let arg_pos = (x as u32, y as u32).pack_u64();
-    let arg_data = color.pack_u64();
-    syscall(Syscall::ScreenSetPixel, arg_pos, arg_data).map(|_| ())
+    UserError::check(unsafe { syscall::syscall_screen_set_pixel(x, y, color.to_u32() as u64) })
+}
+
+/// Copies a `width` by `height` block of pixels, stored row by row, to the screen at `(x, y)` in a
+/// single syscall. Much faster than calling `set_pixel` for every pixel.
+pub fn blit(
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    pixels: &[Color],
+) -> Result<(), SystemError> {
+    assert_eq!(
+        pixels.len(),
+        width * height,
+        "wrong number of pixels for blit"
+    );
+    UserError::check(unsafe { syscall::syscall_screen_blit(x, y, width, height, pixels) })
+}
+
+/// Copies the program's image screen to the display in one go. Call this once per finished frame.
+///
+/// Returns `UserError::MissingScreen` if the program has not created an image screen.
+pub fn present() -> Result<(), SystemError> {
+    UserError::check(unsafe { syscall::syscall_screen_present() })
+}
+
+/// Fills the program's image screen with `color`.
+///
+/// Returns `UserError::MissingScreen` if the program has not created an image screen.
+pub fn clear(color: Color) -> Result<(), SystemError> {
+    UserError::check(unsafe { syscall::syscall_screen_clear(color.to_u32() as u64) })
+}
+
+/// Moves the contents of the program's image screen by `(dx, dy)` pixels. The area that is
+/// uncovered is filled with `fill_color`. This is much faster than redrawing the whole screen.
+///
+/// Returns `UserError::MissingScreen` if the program has not created an image screen.
+pub fn scroll(dx: isize, dy: isize, fill_color: Color) -> Result<(), SystemError> {
+    UserError::check(unsafe { syscall::syscall_screen_scroll(dx, dy, fill_color.to_u32() as u64) })
+}
+
+/// Returns the next key press, or `None` if no keys are waiting.
+pub fn read_key() -> Option<Key> {
+    unsafe { syscall::syscall_program_read_key() }
+}
+
+/// Returns the mouse cursor position and held buttons.
+pub fn read_mouse() -> Result<MouseState, SystemError> {
+    unsafe { syscall::syscall_out(syscall::syscall_program_read_mouse) }
 }
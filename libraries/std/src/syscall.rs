@@ -0,0 +1,95 @@
+use core::{alloc::Layout, arch::global_asm, mem::MaybeUninit};
+use kernel_common::{graphics, Color, Key, MouseState, ProgramStats, Syscall, UserError};
+
+// Each of these is a stub that puts the syscall's ID in `rax` and enters the kernel, so arguments
+// and return values use the `sysv64` registers directly. The stubs match the ones in `userspace`.
+#[allow(improper_ctypes)]
+extern "sysv64" {
+    pub fn syscall_info_framebuffer(out: *mut graphics::FrameBuffer) -> u64;
+    pub fn syscall_info_graphics_ctx(out: *mut graphics::GraphicsContext) -> u64;
+
+    pub fn syscall_mem_alloc(layout: Layout) -> *mut u8;
+    pub fn syscall_mem_dealloc(ptr: *mut u8, layout: Layout);
+    pub fn syscall_mem_alloc_zeroed(layout: Layout) -> *mut u8;
+    pub fn syscall_mem_realloc(ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8;
+
+    pub fn syscall_program_panic(message: &str) -> !;
+    pub fn syscall_program_print(message: &str);
+    pub fn syscall_program_read_key() -> Option<Key>;
+    pub fn syscall_program_time_ticks() -> u64;
+    pub fn syscall_program_sleep_ticks(ticks: u64);
+    pub fn syscall_program_exit() -> !;
+    pub fn syscall_program_shutdown() -> !;
+    pub fn syscall_program_reboot() -> !;
+    pub fn syscall_program_read_mouse(out: *mut MouseState) -> u64;
+    pub fn syscall_program_random() -> u64;
+    pub fn syscall_program_stats(out: *mut ProgramStats) -> u64;
+    pub fn syscall_program_wait_for_confirm(timeout_ticks: u64) -> u64;
+
+    pub fn syscall_screen_create(image: u64) -> u64;
+    pub fn syscall_screen_set_char(x: usize, y: usize, ch: u64, color: u64) -> u64;
+    pub fn syscall_screen_set_pixel(x: usize, y: usize, color: u64) -> u64;
+    pub fn syscall_screen_blit(
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        pixels: &[Color],
+    ) -> u64;
+    pub fn syscall_screen_present() -> u64;
+    pub fn syscall_screen_clear(color: u64) -> u64;
+    pub fn syscall_screen_scroll(dx: isize, dy: isize, fill_color: u64) -> u64;
+}
+
+/// Calls a syscall that writes its result through a pointer, and returns the result.
+pub unsafe fn syscall_out<T>(
+    syscall: unsafe extern "sysv64" fn(*mut T) -> u64,
+) -> Result<T, UserError> {
+    let mut out = MaybeUninit::uninit();
+    UserError::check(syscall(out.as_mut_ptr()))?;
+    Ok(out.assume_init())
+}
+
+macro_rules! impl_syscall {
+    ($name:expr, $id:expr) => {
+        global_asm!(concat!(".globl ", $name, "\n", $name, ":\n",
+            r#"
+                mov rax, {syscall_addr}
+                push rcx
+                syscall
+                ret"#),
+            syscall_addr = const $id as usize * 8);
+    };
+}
+
+impl_syscall!("syscall_info_framebuffer", Syscall::InfoFramebuffer);
+impl_syscall!("syscall_info_graphics_ctx", Syscall::InfoGraphicsCtx);
+
+impl_syscall!("syscall_mem_alloc", Syscall::MemAlloc);
+impl_syscall!("syscall_mem_dealloc", Syscall::MemDealloc);
+impl_syscall!("syscall_mem_alloc_zeroed", Syscall::MemAllocZeroed);
+impl_syscall!("syscall_mem_realloc", Syscall::MemRealloc);
+
+impl_syscall!("syscall_program_panic", Syscall::ProgramPanic);
+impl_syscall!("syscall_program_print", Syscall::ProgramPrint);
+impl_syscall!("syscall_program_read_key", Syscall::ProgramReadKey);
+impl_syscall!("syscall_program_time_ticks", Syscall::ProgramTimeTicks);
+impl_syscall!("syscall_program_sleep_ticks", Syscall::ProgramSleepTicks);
+impl_syscall!("syscall_program_exit", Syscall::ProgramExit);
+impl_syscall!("syscall_program_shutdown", Syscall::ProgramShutdown);
+impl_syscall!("syscall_program_reboot", Syscall::ProgramReboot);
+impl_syscall!("syscall_program_read_mouse", Syscall::ProgramReadMouse);
+impl_syscall!("syscall_program_random", Syscall::ProgramRandom);
+impl_syscall!("syscall_program_stats", Syscall::ProgramStats);
+impl_syscall!(
+    "syscall_program_wait_for_confirm",
+    Syscall::ProgramWaitForConfirm
+);
+
+impl_syscall!("syscall_screen_create", Syscall::ScreenCreate);
+impl_syscall!("syscall_screen_set_char", Syscall::ScreenSetChar);
+impl_syscall!("syscall_screen_set_pixel", Syscall::ScreenSetPixel);
+impl_syscall!("syscall_screen_blit", Syscall::ScreenBlit);
+impl_syscall!("syscall_screen_present", Syscall::ScreenPresent);
+impl_syscall!("syscall_screen_clear", Syscall::ScreenClear);
+impl_syscall!("syscall_screen_scroll", Syscall::ScreenScroll);
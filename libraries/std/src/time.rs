@@ -0,0 +1,18 @@
+use crate::syscall;
+
+pub use kernel_common::time::{millis_to_ticks, ticks_to_millis};
+
+/// The number of system timer ticks since boot. The timer runs at about 60.1 Hz.
+pub fn ticks() -> u64 {
+    unsafe { syscall::syscall_program_time_ticks() }
+}
+
+/// The number of milliseconds since boot, with the precision of one timer tick.
+pub fn millis() -> u64 {
+    ticks_to_millis(ticks())
+}
+
+/// Waits until `ticks` timer ticks have passed. `sleep_ticks(1)` waits for the next tick.
+pub fn sleep_ticks(ticks: u64) {
+    unsafe { syscall::syscall_program_sleep_ticks(ticks) }
+}
@@ -7,12 +7,14 @@ entry_point!(main);
 
 fn main() {
     screen::create(true).unwrap();
-    for y in 0..480 {
-        let t = y as f32 / 480.0;
-        for x in 0..640 {
+    let (width, height) = screen::dimensions();
+    for y in 0..height {
+        let t = y as f32 / height as f32;
+        for x in 0..width {
             let col = (t * 255.0) as u8;
             screen::set_pixel(x, y, screen::Color::new(col, col, 255)).unwrap();
         }
     }
+    screen::present().unwrap();
     wait_for_confirm();
 }
@@ -1,36 +1,61 @@
-use crate::ffi::OsString;
+use crate::ffi::{CStr, OsString};
 use crate::fmt;
+use crate::ptr;
+use crate::sync::atomic::{AtomicIsize, AtomicPtr, Ordering};
+use crate::sys::os_str::Buf;
+use crate::sys_common::FromInner;
+use crate::vec;
 
-pub struct Args {}
+static ARGC: AtomicIsize = AtomicIsize::new(0);
+static ARGV: AtomicPtr<*const u8> = AtomicPtr::new(ptr::null_mut());
+
+/// Saves the arguments the kernel passed to `_start`.
+pub unsafe fn init(argc: isize, argv: *const *const u8) {
+    ARGC.store(argc, Ordering::Relaxed);
+    ARGV.store(argv as *mut _, Ordering::Relaxed);
+}
+
+pub struct Args {
+    iter: vec::IntoIter<OsString>,
+}
 
 pub fn args() -> Args {
-    Args {}
+    let argc = ARGC.load(Ordering::Relaxed);
+    let argv = ARGV.load(Ordering::Relaxed);
+    let args = (0..argc)
+        .map(|i| {
+            // SAFETY: the kernel passes `argc` valid pointers to NUL-terminated strings.
+            let arg = unsafe { CStr::from_ptr(*argv.offset(i) as *const _) };
+            OsString::from_inner(Buf { inner: arg.to_bytes().to_vec() })
+        })
+        .collect::<crate::vec::Vec<_>>();
+    Args { iter: args.into_iter() }
 }
 
 impl fmt::Debug for Args {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_list().finish()
+        self.iter.as_slice().fmt(f)
     }
 }
 
 impl Iterator for Args {
     type Item = OsString;
     fn next(&mut self) -> Option<OsString> {
-        None
+        self.iter.next()
     }
     fn size_hint(&self) -> (usize, Option<usize>) {
-        (0, Some(0))
+        self.iter.size_hint()
     }
 }
 
 impl ExactSizeIterator for Args {
     fn len(&self) -> usize {
-        0
+        self.iter.len()
     }
 }
 
 impl DoubleEndedIterator for Args {
     fn next_back(&mut self) -> Option<OsString> {
-        None
+        self.iter.next_back()
     }
 }
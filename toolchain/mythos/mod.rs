@@ -30,12 +30,18 @@ pub use common::*;
 // The linker will normally include a small C-runtime file for the platform with a name like crt.o,
 // which has the real entry point: the "_start" symbol. Mythos doesn't have any such file, so
 // define it right here!
+//
+// The kernel starts programs with `argc` in rdi and `argv` in rsi, and rsp pointing at a null
+// return address, so `_start` is entered like a normally called function (rsp % 16 == 8). Above
+// the return address the same values are on the stack in the System V layout: `argc`, followed by
+// the `argv` pointers, a null pointer, a null `envp` and an `AT_NULL` auxiliary vector entry. The
+// argument strings are stored above that.
 mod rt {
     extern "C" { fn main(argc: isize, argv: *const *const u8); }
 
     #[no_mangle]
-    extern "C" fn _start() -> ! {
-        unsafe { main(0, core::ptr::null()); }
+    extern "C" fn _start(argc: isize, argv: *const *const u8) -> ! {
+        unsafe { main(argc, argv); }
         loop {}
     }
 }
@@ -1,4 +1,4 @@
-use crate::spec::{Cc, LinkerFlavor, Lld, RelroLevel, TargetOptions, SanitizerSet, PanicStrategy, CodeModel};
+use crate::spec::{Cc, LinkerFlavor, Lld, RelroLevel, TargetOptions, SanitizerSet, PanicStrategy, CodeModel, FramePointer};
 
 pub fn opts() -> TargetOptions {
     TargetOptions {
@@ -19,6 +19,8 @@ pub fn opts() -> TargetOptions {
         disable_redzone: true,
         panic_strategy: PanicStrategy::Abort,
         code_model: Some(CodeModel::Kernel),
+        // The kernel follows frame pointers to print a backtrace when a program panics.
+        frame_pointer: FramePointer::Always,
 
         dll_prefix: "".into(),
         dll_suffix: ".dylib".into(),
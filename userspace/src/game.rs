@@ -1,9 +1,7 @@
 use alloc::vec::Vec;
-use level::{Level, Object, ObjectDraw};
+use level::{Level, Object, ObjectDraw, ObjectKind};
 
-use crate::graphics::{Framebuffer, GraphicsContext, Image, ImageFormat, LevelRenderer};
-
-pub(crate) static mut WAIT_FRAME: bool = false;
+use crate::graphics::{Framebuffer, GraphicsContext, Image, ImageFormat, LevelRenderer, Texture};
 
 #[derive(Clone, Copy)]
 struct LevelId(usize);
@@ -13,6 +11,7 @@ struct ObjectId(LevelId, level::ObjectId);
 
 struct Game {
     renderer: LevelRenderer,
+    tile_size: u32,
     levels: Vec<Option<Level>>,
     player: Option<ObjectId>,
 }
@@ -36,11 +35,13 @@ impl Game {
         renderer.add_object_image(context, &player_image);
         Game {
             renderer,
+            tile_size,
             levels: Vec::new(),
             player: None,
         }
     }
-    fn add_level(&mut self, level: Level) -> LevelId {
+    fn add_level(&mut self, mut level: Level) -> LevelId {
+        level.set_tile_size(self.tile_size);
         for (index, slot) in self.levels.iter_mut().enumerate() {
             if slot.is_none() {
                 *slot = Some(level);
@@ -60,11 +61,12 @@ impl Game {
         }
         if let Some(Some(level)) = self.levels.get_mut(id.0) {
             let player_obj = Object {
-                kind: "player",
+                kind: ObjectKind::Player,
                 x: 64.0,
                 y: 64.0,
                 width: 32,
                 height: 32,
+                layer: 0,
                 draw: ObjectDraw::Image(0, 0),
             };
             let player_id = level.add_object(player_obj);
@@ -89,18 +91,34 @@ impl Game {
     }
 
     fn wait_for_next_frame(&self) {
+        // The timer ticks at about 60 Hz, so wait for the next tick boundary.
         unsafe {
-            WAIT_FRAME = true;
-            while WAIT_FRAME {
-                x86_64::instructions::hlt();
-            }
+            crate::syscall_program_sleep_ticks(1);
         }
     }
+    /// Scrolls the level so the player is in the middle of the view, clamped to the level edges.
+    fn center_scroll_on_player(level: &mut Level, player: level::ObjectId, view: (u32, u32)) {
+        let player_obj = level.get_object(player).expect("player removed");
+        let center_x = player_obj.pixel_x() + player_obj.width as i32 / 2;
+        let center_y = player_obj.pixel_y() + player_obj.height as i32 / 2;
+        level.center_scroll_on(center_x, center_y, view.0, view.1);
+    }
     fn update(&mut self, context: &GraphicsContext) {
+        let texture = self.renderer.texture();
+        let view = (texture.width(), texture.height());
         if let Some(player) = self.player {
             if let Some(Some(level)) = self.levels.get_mut(player.0 .0) {
                 let player_obj = level.get_object(player.1).expect("player removed");
-                player_obj.x += 1.0;
+                let next_x = player_obj.x + 1.0;
+                let front_x = next_x as i32 + player_obj.width as i32 - 1;
+                let (top, bottom) = (
+                    player_obj.pixel_y(),
+                    player_obj.pixel_y() + player_obj.height as i32 - 1,
+                );
+                if !level.tile_collides(front_x, top) && !level.tile_collides(front_x, bottom) {
+                    level.get_object(player.1).unwrap().x = next_x;
+                }
+                Self::center_scroll_on_player(level, player.1, view);
 
                 self.renderer.draw_level(context, level);
             } else {
@@ -5,36 +5,41 @@
 extern crate alloc;
 
 use alloc::{format, string::String};
-use core::{alloc::Layout, arch::global_asm, fmt::Write};
-use kernel_common::{graphics, Syscall};
+use core::{alloc::Layout, arch::global_asm, fmt::Write, mem::MaybeUninit};
+use kernel_common::{graphics, Key, MouseState, ProgramStats, Syscall, UserError};
 
 #[no_mangle]
 pub extern "C" fn _start() -> ! {
-    let mut framebuffer = unsafe { syscall_info_framebuffer() };
-    let context = unsafe { syscall_info_graphics_ctx() };
+    let mut framebuffer = unsafe { syscall_out(syscall_info_framebuffer) };
+    let context = unsafe { syscall_out(syscall_info_graphics_ctx) };
     graphics::load_system_font(&context, [255, 255, 255]);
     let mut writer = graphics::TextWriter::new(&context, &mut framebuffer, 0, 0);
-    let os_name = unsafe { syscall_info_os_name() };
-    let os_version = unsafe { syscall_info_os_version() };
-    let bootloader_version = unsafe { syscall_info_bootloader_version() };
+    let os_name = unsafe { syscall_out(syscall_info_os_name) };
+    let os_version = unsafe { syscall_out(syscall_info_os_version) };
+    let bootloader_version = unsafe { syscall_out(syscall_info_bootloader_version) };
     let _ = writeln!(writer, "{} v{}", os_name, os_version);
     let _ = writeln!(writer, "Bootloader v{}", bootloader_version);
 
     unsafe {
         ata::init();
+        ata::set_interrupt_wait(Some(wait_for_ata));
     }
     let drives = ata::list().unwrap();
     let _ = writeln!(writer, "{:?}", drives[0]);
-    loop {}
+    let _ = writeln!(writer, "Press space or enter to exit");
+    unsafe {
+        syscall_program_wait_for_confirm(0);
+        syscall_program_exit();
+    }
 }
 
 #[allow(improper_ctypes)]
 extern "sysv64" {
-    fn syscall_info_os_name() -> String;
-    fn syscall_info_os_version() -> String;
-    fn syscall_info_bootloader_version() -> String;
-    fn syscall_info_framebuffer() -> graphics::FrameBuffer;
-    fn syscall_info_graphics_ctx() -> graphics::GraphicsContext;
+    fn syscall_info_os_name(out: *mut String) -> u64;
+    fn syscall_info_os_version(out: *mut String) -> u64;
+    fn syscall_info_bootloader_version(out: *mut String) -> u64;
+    fn syscall_info_framebuffer(out: *mut graphics::FrameBuffer) -> u64;
+    fn syscall_info_graphics_ctx(out: *mut graphics::GraphicsContext) -> u64;
 
     fn syscall_mem_alloc(layout: Layout) -> *mut u8;
     fn syscall_mem_dealloc(ptr: *mut u8, layout: Layout);
@@ -42,6 +47,31 @@ extern "sysv64" {
     fn syscall_mem_realloc(ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8;
 
     fn syscall_program_panic(message: &str) -> !;
+    fn syscall_program_print(message: &str);
+    fn syscall_program_read_key() -> Option<Key>;
+    fn syscall_program_time_ticks() -> u64;
+    fn syscall_program_sleep_ticks(ticks: u64);
+    fn syscall_program_exit() -> !;
+    fn syscall_program_shutdown() -> !;
+    fn syscall_program_reboot() -> !;
+    fn syscall_program_read_mouse(out: *mut MouseState) -> u64;
+    fn syscall_program_random() -> u64;
+    fn syscall_program_stats(out: *mut ProgramStats) -> u64;
+    fn syscall_program_wait_for_confirm(timeout_ticks: u64) -> u64;
+    fn syscall_program_wait_ata(bus: u64, timeout_ticks: u64) -> u64;
+}
+
+fn wait_for_ata(bus: u8, timeout_ticks: u64) -> bool {
+    unsafe { syscall_program_wait_ata(bus as u64, timeout_ticks) == 1 }
+}
+
+/// Calls a syscall that writes its result through a pointer, and returns the result.
+unsafe fn syscall_out<T>(syscall: unsafe extern "sysv64" fn(*mut T) -> u64) -> T {
+    let mut out = MaybeUninit::uninit();
+    if let Err(err) = UserError::check(syscall(out.as_mut_ptr())) {
+        panic!("syscall failed: {:?}", err);
+    }
+    out.assume_init()
 }
 
 macro_rules! impl_syscall {
@@ -52,25 +82,40 @@ macro_rules! impl_syscall {
                 push rcx
                 syscall
                 ret"#),
-            syscall_addr = const $id * 8);
+            syscall_addr = const $id as usize * 8);
     };
 }
 
-impl_syscall!("syscall_info_os_name", Syscall::INFO_OS_NAME);
-impl_syscall!("syscall_info_os_version", Syscall::INFO_OS_VERSION);
+impl_syscall!("syscall_info_os_name", Syscall::InfoOsName);
+impl_syscall!("syscall_info_os_version", Syscall::InfoOsVersion);
 impl_syscall!(
     "syscall_info_bootloader_version",
-    Syscall::INFO_BOOTLOADER_VERSION
+    Syscall::InfoBootloaderVersion
 );
-impl_syscall!("syscall_info_framebuffer", Syscall::INFO_FRAMEBUFFER);
-impl_syscall!("syscall_info_graphics_ctx", Syscall::INFO_GRAPHICS_CTX);
+impl_syscall!("syscall_info_framebuffer", Syscall::InfoFramebuffer);
+impl_syscall!("syscall_info_graphics_ctx", Syscall::InfoGraphicsCtx);
 
-impl_syscall!("syscall_mem_alloc", Syscall::MEM_ALLOC);
-impl_syscall!("syscall_mem_dealloc", Syscall::MEM_DEALLOC);
-impl_syscall!("syscall_mem_alloc_zeroed", Syscall::MEM_ALLOC_ZEROED);
-impl_syscall!("syscall_mem_realloc", Syscall::MEM_REALLOC);
+impl_syscall!("syscall_mem_alloc", Syscall::MemAlloc);
+impl_syscall!("syscall_mem_dealloc", Syscall::MemDealloc);
+impl_syscall!("syscall_mem_alloc_zeroed", Syscall::MemAllocZeroed);
+impl_syscall!("syscall_mem_realloc", Syscall::MemRealloc);
 
-impl_syscall!("syscall_program_panic", Syscall::PROGRAM_PANIC);
+impl_syscall!("syscall_program_panic", Syscall::ProgramPanic);
+impl_syscall!("syscall_program_print", Syscall::ProgramPrint);
+impl_syscall!("syscall_program_read_key", Syscall::ProgramReadKey);
+impl_syscall!("syscall_program_time_ticks", Syscall::ProgramTimeTicks);
+impl_syscall!("syscall_program_sleep_ticks", Syscall::ProgramSleepTicks);
+impl_syscall!("syscall_program_exit", Syscall::ProgramExit);
+impl_syscall!("syscall_program_shutdown", Syscall::ProgramShutdown);
+impl_syscall!("syscall_program_reboot", Syscall::ProgramReboot);
+impl_syscall!("syscall_program_read_mouse", Syscall::ProgramReadMouse);
+impl_syscall!("syscall_program_random", Syscall::ProgramRandom);
+impl_syscall!("syscall_program_stats", Syscall::ProgramStats);
+impl_syscall!(
+    "syscall_program_wait_for_confirm",
+    Syscall::ProgramWaitForConfirm
+);
+impl_syscall!("syscall_program_wait_ata", Syscall::ProgramWaitAta);
 
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {